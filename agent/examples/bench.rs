@@ -0,0 +1,127 @@
+//! Throughput benchmark against an in-process gateway and echo server.
+//!
+//! Run with `cargo run --release -p cluvio-agent --features test-util --example bench`.
+
+use clap::Parser;
+use cluvio_agent::Agent;
+use cluvio_agent::test_util::{Gateway, Session};
+use futures::{AsyncReadExt, AsyncWriteExt};
+use protocol::Address;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+
+#[derive(Debug, Parser)]
+struct Options {
+    /// Number of streams to open for the latency measurement.
+    #[arg(long, default_value_t = 1000)]
+    streams: usize,
+
+    /// Number of bytes to transfer for the throughput measurement.
+    #[arg(long, default_value_t = 64 * 1024 * 1024)]
+    bytes: usize,
+
+    /// Write buffer sizes to measure throughput with.
+    #[arg(long, value_delimiter = ',', default_value = "4096,16384,65536")]
+    buffer_sizes: Vec<usize>,
+
+    /// Yamux receive windows of the gateway to measure with.
+    #[arg(long, value_delimiter = ',', default_value = "262144,1048576,16777216")]
+    windows: Vec<u32>
+}
+
+#[tokio::main]
+async fn main() {
+    let opts = Options::parse();
+    let echo = echo_server().await;
+
+    for window in &opts.windows {
+        let mut mux = yamux::Config::default();
+        mux.set_receive_window(*window);
+        mux.set_max_buffer_size((*window).try_into().unwrap_or(usize::MAX));
+
+        let mut gateway = Gateway::start_with(mux).await.expect("gateway");
+        let agent = Agent::new(gateway.config(sealed_boxes::gen_secret_key()))
+            .expect("agent")
+            .spawn();
+        let mut session = gateway.accept().await.expect("session");
+        assert!(session.challenge().await.expect("challenge"));
+        session.accept().await.expect("accept");
+
+        println!("receive window: {} bytes", window);
+
+        let (rate, p50, p99) = latency(&mut session, echo, opts.streams).await;
+        println!("  {:>8.0} streams/s, latency p50 = {:?}, p99 = {:?}", rate, p50, p99);
+
+        for size in &opts.buffer_sizes {
+            let mbps = throughput(&mut session, echo, opts.bytes, *size).await;
+            println!("  buffer size {:>6}: {:>8.1} MiB/s", size, mbps)
+        }
+
+        agent.shutdown().await;
+    }
+}
+
+/// Open streams one after another and measure the time until one byte has been echoed.
+async fn latency(session: &mut Session, echo: SocketAddr, n: usize) -> (f64, Duration, Duration) {
+    let mut times = Vec::with_capacity(n);
+    let start = Instant::now();
+    for _ in 0 .. n {
+        let t = Instant::now();
+        let mut stream = session.connect(Address::Addr(echo)).await.expect("connect").expect("stream");
+        stream.write_all(b"x").await.expect("write");
+        let mut buf = [0];
+        stream.read_exact(&mut buf).await.expect("read");
+        times.push(t.elapsed());
+        stream.close().await.expect("close");
+    }
+    let rate = n as f64 / start.elapsed().as_secs_f64();
+    times.sort();
+    let pct = |p: usize| times.get(n * p / 100).or(times.last()).copied().unwrap_or_default();
+    (rate, pct(50), pct(99))
+}
+
+/// Send `total` bytes in chunks of `size` through one stream and read back the echo.
+async fn throughput(session: &mut Session, echo: SocketAddr, total: usize, size: usize) -> f64 {
+    let stream = session.connect(Address::Addr(echo)).await.expect("connect").expect("stream");
+    let (mut r, mut w) = stream.split();
+    let start = Instant::now();
+    let send = async {
+        let buf = vec![0; size];
+        let mut n = 0;
+        while n < total {
+            let k = size.min(total - n);
+            w.write_all(&buf[.. k]).await.expect("write");
+            n += k
+        }
+        w.close().await.expect("close")
+    };
+    let recv = async {
+        let mut buf = vec![0; size];
+        let mut n = 0;
+        while n < total {
+            match r.read(&mut buf).await.expect("read") {
+                0 => break,
+                k => n += k
+            }
+        }
+        n
+    };
+    let ((), n) = futures::join!(send, recv);
+    n as f64 / (1024.0 * 1024.0) / start.elapsed().as_secs_f64()
+}
+
+/// Start a TCP echo server on localhost.
+async fn echo_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().expect("local address");
+    tokio::spawn(async move {
+        while let Ok((mut s, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut r, mut w) = s.split();
+                let _ = tokio::io::copy(&mut r, &mut w).await;
+            });
+        }
+    });
+    addr
+}
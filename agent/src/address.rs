@@ -1,40 +1,53 @@
-use crate::config::Network;
-use protocol::Address;
+use crate::policy::{AddressPolicy, Denylist};
+use protocol::{Address, Scheme, normalize_name};
+use std::borrow::Cow;
 use std::ops::Deref;
 
-/// An address checked against some whitelist.
+/// An address checked against some address policy.
 #[derive(Debug)]
-pub struct CheckedAddr<'a>(Address<'a>);
+pub struct CheckedAddr<'a>(Address<'a>, Scheme);
 
 impl<'a> CheckedAddr<'a> {
-    /// Create a checked address if the given address is part of the whitelist.
-    pub fn check(addr: Address<'a>, whitelist: &[Network]) -> Result<Self, Address<'a>> {
-        let is_allowed = match &addr {
-            Address::Addr(addr) => whitelist.iter().any(|net| {
-                if let Network::Ip(net) = net {
-                    net.contains(&addr.ip())
-                } else {
-                    false
+    /// Create a checked address if it is not denied and the given policy allows it.
+    ///
+    /// Internationalized domain names are converted to their ASCII form and
+    /// all names are normalized (see [`protocol::normalize_name`]) first.
+    pub async fn check(addr: Address<'a>, scheme: Scheme, deny: &Denylist, policy: &dyn AddressPolicy) -> Result<Self, Rejected<'a>> {
+        let addr = match addr {
+            Address::Name(name, port) if !name.is_ascii() =>
+                match util::domain_to_ascii(&name) {
+                    Ok(ascii) => Address::Name(normalize_name(Cow::Owned(ascii)), port),
+                    Err(_)    => return Err(Rejected::NotAllowed(Address::Name(name, port)))
                 }
-            }),
-            Address::Name(addr, _) => whitelist.iter().any(|net| {
-                match net {
-                    Network::Ip(_)  => false,
-                    Network::Dns(n) => n.as_str() == addr,
-                    Network::Pat(p) => p.matches(addr)
-                }
-            })
+            Address::Name(name, port) => Address::Name(normalize_name(name), port),
+            other => other
         };
-        if is_allowed {
-            Ok(CheckedAddr(addr))
+        if deny.contains(&addr, scheme) {
+            return Err(Rejected::Denied(addr))
+        }
+        if policy.allows(&addr, scheme).await {
+            Ok(CheckedAddr(addr, scheme))
         } else {
-            Err(addr)
+            Err(Rejected::NotAllowed(addr))
         }
     }
 
     pub fn addr(&self) -> &Address<'a> {
         &self.0
     }
+
+    pub fn scheme(&self) -> Scheme {
+        self.1
+    }
+}
+
+/// An address which did not pass [`CheckedAddr::check`].
+#[derive(Debug)]
+pub enum Rejected<'a> {
+    /// The address is on the denylist.
+    Denied(Address<'a>),
+    /// The address is not allowed by the policy.
+    NotAllowed(Address<'a>)
 }
 
 impl<'a> Deref for CheckedAddr<'a> {
@@ -1,39 +1,86 @@
-use crate::{Reader, Writer, version};
+use crate::{IO_TIMEOUT, Reader, Writer, version};
+use crate::bandwidth;
 use crate::config::Config;
 use crate::error::Error;
+use crate::event::{Event, Status};
+use crate::health;
+use crate::hook::StreamHook;
+use crate::limit::RateLimits;
+use crate::metrics::Metrics;
+use crate::policy::{AddressPolicy, Allowlist, Denylist};
 use crate::stream::{self, streamer};
 use crate::tls;
 use futures::future;
 use futures::stream::{BoxStream, FuturesUnordered, SelectAll, StreamExt};
 use humantime::format_duration;
+use log::Instrument;
 use protocol::{AgentId, Client, ErrorCode, Id, Message, Server};
-use protocol::{Reason, Version};
+use protocol::{Order, Reason, Seq, SeqCheck, SeqGen, Version, PROTOCOL_VERSION};
 use scopeguard::{ScopeGuard, guard};
-use sealed_boxes::decrypt;
+use sealed_boxes::decrypt_any;
 use std::borrow::Cow;
+use std::future::Future;
 use std::mem;
-use std::sync::Arc;
+use std::net::SocketAddr;
+use std::pin::{Pin, pin};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::Duration;
-use tokio::net;
 use tokio::{select, spawn};
-use tokio::sync::mpsc;
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc, watch};
 use tokio::task::JoinHandle;
-use tokio::time::{sleep, timeout};
+use tokio::time::{Instant, Interval, MissedTickBehavior, interval, interval_at, sleep, timeout};
 use tokio_util::compat::TokioAsyncReadCompatExt;
-use util::io::{send, recv};
+use tokio_util::sync::CancellationToken;
+use util::audit::{AuditEvent, AuditKind, AuditSink};
+use util::dns::{self, Resolver};
+use util::io::{recv, send_timeout};
+use util::retry;
+use util::service::{Service, Unmanaged};
+
+/// Max. time to wait for streams to finish on shutdown.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
 
 /// The connection agent.
 pub struct Agent {
     id: AgentId,
     version: Version,
     config: Arc<Config>,
+    context: Arc<stream::Context>,
+    events: broadcast::Sender<Event>,
+    status: watch::Sender<Status>,
+    reload_tx: mpsc::UnboundedSender<Config>,
+    reload_rx: mpsc::UnboundedReceiver<Config>,
+    /// Is the address policy derived from the config?
+    config_policy: bool,
     client: tls::Client,
+    backoff: retry::Policy,
     attempt: u8,
     ping_state: PingState,
+    service: Arc<dyn Service>,
+    /// Has the service manager been told that we are ready?
+    ready: bool,
+    /// When to answer the service manager's watchdog.
+    watchdog: Option<Interval>,
+    /// When to log the stream statistics per destination.
+    summary: Option<Interval>,
+    /// When to log the connection status.
+    heartbeat: Option<Interval>,
+    /// Has the gateway accepted the current connection?
+    authenticated: Arc<AtomicBool>,
+    /// The round-trip time of the last ping on the current connection.
+    ping_rtt: Arc<PingRtt>,
+    /// The listener of the health endpoint (until the agent runs).
+    health: Option<TcpListener>,
     streams: FuturesUnordered<JoinHandle<Result<(), Error>>>,
     tests: FuturesUnordered<JoinHandle<(Id, Option<ErrorCode>)>>,
     drainage: SelectAll<BoxStream<'static, yamux::Stream>>,
-    online: bool
+    online: bool,
+    /// Sequence numbers of outbound control messages.
+    seq_out: SeqGen,
+    /// Sequence numbers of inbound control messages.
+    seq_in: SeqCheck
 }
 
 /// Connection parts.
@@ -68,13 +115,35 @@ impl Drop for Connection {
     }
 }
 
+/// The round-trip time of the last ping (in microseconds, 0 = none).
+#[derive(Debug, Default)]
+pub(crate) struct PingRtt(AtomicU64);
+
+impl PingRtt {
+    pub(crate) fn get(&self) -> Option<Duration> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            n => Some(Duration::from_micros(n))
+        }
+    }
+
+    fn set(&self, rtt: Duration) {
+        let micros = u64::try_from(rtt.as_micros()).unwrap_or(u64::MAX).max(1);
+        self.0.store(micros, Ordering::Relaxed)
+    }
+
+    fn reset(&self) {
+        self.0.store(0, Ordering::Relaxed)
+    }
+}
+
 /// Ping/Pong state.
 #[derive(Debug)]
 enum PingState {
     /// Normal processing.
     Idle,
-    /// Awaiting pong with the given Id.
-    Awaiting(Id)
+    /// Awaiting pong with the given Id to the ping sent at the given time.
+    Awaiting(Id, Instant)
 }
 
 /// Delay strategy for connection attempts.
@@ -87,16 +156,144 @@ enum Delay {
     Fixed(Duration)
 }
 
-impl Agent {
-    pub fn new(cfg: Config) -> Result<Self, Error> {
-        let client = tls::Client::new(&cfg)?;
+/// Configures and creates an [`Agent`].
+pub struct Builder {
+    config: Config,
+    resolver: Arc<dyn Resolver>,
+    policy: Option<Arc<dyn AddressPolicy>>,
+    audit: Option<Arc<dyn AuditSink>>,
+    hooks: Vec<Arc<dyn StreamHook>>,
+    metrics: Metrics,
+    backoff: retry::Policy,
+    service: Arc<dyn Service>
+}
+
+impl Builder {
+    pub fn new(cfg: Config) -> Self {
+        let backoff = retry::Policy::new()
+            .with_initial_delay(cfg.reconnect_initial_delay)
+            .with_max_delay(cfg.reconnect_max_delay)
+            .with_factor(cfg.reconnect_multiplier);
+        Builder {
+            config: cfg,
+            resolver: Arc::new(dns::System),
+            policy: None,
+            audit: None,
+            hooks: Vec::new(),
+            metrics: Metrics::new(),
+            backoff,
+            service: Arc::new(Unmanaged)
+        }
+    }
+
+    /// Set the resolver for gateway and internal host names.
+    pub fn with_resolver(mut self, r: Arc<dyn Resolver>) -> Self {
+        self.resolver = r;
+        self
+    }
+
+    /// Set the address policy (instead of the allowlist from [`Config::allowed_addresses`]).
+    pub fn with_address_policy(mut self, p: Arc<dyn AddressPolicy>) -> Self {
+        self.policy = Some(p);
+        self
+    }
+
+    /// Set the audit sink (instead of the one configured in [`Config::audit`]).
+    pub fn with_audit(mut self, a: Arc<dyn AuditSink>) -> Self {
+        self.audit = Some(a);
+        self
+    }
+
+    /// Add a hook to be invoked on stream lifecycle changes.
+    ///
+    /// Hooks are invoked in the order they have been added.
+    pub fn with_stream_hook(mut self, h: Arc<dyn StreamHook>) -> Self {
+        self.hooks.push(h);
+        self
+    }
+
+    /// Set the metrics to update (e.g. to share them between agents).
+    pub fn with_metrics(mut self, m: Metrics) -> Self {
+        self.metrics = m;
+        self
+    }
+
+    /// Set the backoff policy of reconnects (instead of the one from the config).
+    pub fn with_backoff(mut self, p: retry::Policy) -> Self {
+        self.backoff = p;
+        self
+    }
+
+    /// Set the service manager to notify about readiness, reloads and shutdown.
+    ///
+    /// Readiness is signalled after the first successful handshake with the
+    /// gateway. If the service manager expects watchdog notifications, they
+    /// are sent from the agent's event loop.
+    pub fn with_service(mut self, s: Arc<dyn Service>) -> Self {
+        self.service = s;
+        self
+    }
+
+    /// Create the agent.
+    ///
+    /// Must be called within a tokio runtime.
+    pub fn build(self) -> Result<Agent, Error> {
+        let client = tls::Client::new(&self.config)?;
+        let audit = match self.audit {
+            Some(a) => a,
+            None    => self.config.audit.sink()?
+        };
+        let config_policy = self.policy.is_none();
+        let policy = match self.policy {
+            Some(p) => p,
+            None    => Arc::new(Allowlist::new(self.config.allowed_addresses.clone()))
+        };
+        let health = match &self.config.health {
+            Some(h) => Some(health::bind(h.listen)?),
+            None    => None
+        };
+        let config = Arc::new(self.config);
+        let events = broadcast::channel(256).0;
+        let (reload_tx, reload_rx) = mpsc::unbounded_channel();
+        let watchdog = self.service.watchdog_interval().map(|d| {
+            let mut i = interval(d / 2);
+            i.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            i
+        });
+        let context = stream::Context {
+            config: RwLock::new(config.clone()),
+            limits: Mutex::new(RateLimits::new()),
+            bandwidth: RwLock::new(bandwidth::Limits::new(&config)),
+            resolver: self.resolver,
+            policy: RwLock::new(policy),
+            denylist: RwLock::new(Arc::new(Denylist::new(config.denied_addresses.clone()))),
+            audit,
+            hooks: self.hooks,
+            metrics: self.metrics,
+            events: events.clone()
+        };
         Ok(Agent {
-            id: AgentId::from(cfg.secret_key.public_key()),
+            id: AgentId::from(config.secret_key.expose().public_key()),
             version: crate::version()?,
-            config: Arc::new(cfg),
+            config,
+            context: Arc::new(context),
+            events,
+            status: watch::channel(Status::Connecting).0,
+            reload_tx,
+            reload_rx,
+            config_policy,
             client,
+            backoff: self.backoff,
             attempt: 0,
             ping_state: PingState::Idle,
+            service: self.service,
+            ready: false,
+            watchdog,
+            summary: optional_interval(config.summary_interval),
+            heartbeat: optional_interval(config.heartbeat_interval),
+            authenticated: Arc::new(AtomicBool::new(false)),
+            ping_rtt: Arc::new(PingRtt::default()),
+            health,
             streams: futures_unordered(),
             tests: futures_unordered(),
             drainage: {
@@ -104,63 +301,263 @@ impl Agent {
                 s.push(futures::stream::pending().boxed());
                 s
             },
-            online: false
+            online: false,
+            seq_out: SeqGen::new(),
+            seq_in: SeqCheck::new()
         })
     }
+}
+
+/// Handle to an agent running in its own task (see [`Agent::spawn`]).
+///
+/// Dropping the handle does not stop the agent.
+pub struct Handle {
+    id: AgentId,
+    events: broadcast::Sender<Event>,
+    status: watch::Receiver<Status>,
+    metrics: Metrics,
+    ping_rtt: Arc<PingRtt>,
+    reloader: Reloader,
+    shutdown: CancellationToken,
+    task: JoinHandle<Option<Reason>>
+}
 
+impl Handle {
     pub fn id(&self) -> &AgentId {
         &self.id
     }
 
+    /// Subscribe to events of the agent.
+    pub fn events(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Watch the connection status of the agent.
+    pub fn status(&self) -> watch::Receiver<Status> {
+        self.status.clone()
+    }
+
+    /// Get the metrics of the agent.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    /// The round-trip time of the last ping on the current connection.
+    pub fn ping_rtt(&self) -> Option<Duration> {
+        self.ping_rtt.get()
+    }
+
+    /// Get a handle to reload the config of the agent.
+    pub fn reloader(&self) -> Reloader {
+        self.reloader.clone()
+    }
+
+    /// Stop the agent and wait for it to finish.
+    ///
+    /// Returns the termination reason if the gateway terminated the agent before.
+    pub async fn shutdown(self) -> Option<Reason> {
+        self.shutdown.cancel();
+        self.join().await
+    }
+
+    /// Wait for the agent to finish.
+    pub async fn join(self) -> Option<Reason> {
+        match self.task.await {
+            Ok(reason) => reason,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(_) => None
+        }
+    }
+}
+
+/// Passes new configs to a running agent (see [`Agent::reloader`]).
+#[derive(Debug, Clone)]
+pub struct Reloader {
+    tx: mpsc::UnboundedSender<Config>
+}
+
+impl Reloader {
+    /// Replace the config of the agent.
+    ///
+    /// Only `allowed-addresses`, `denied-addresses`, `ping-frequency`,
+    /// `connect-timeout`, `max-concurrent-streams` and `max-bandwidth` take
+    /// effect, active streams are not affected. A
+    /// config with a different secret key or server is ignored.
+    pub fn reload(&self, cfg: Config) {
+        let _ = self.tx.send(cfg);
+    }
+}
+
+impl Agent {
+    pub fn new(cfg: Config) -> Result<Self, Error> {
+        Builder::new(cfg).build()
+    }
+
+    pub fn builder(cfg: Config) -> Builder {
+        Builder::new(cfg)
+    }
+
+    pub fn id(&self) -> &AgentId {
+        &self.id
+    }
+
+    /// Subscribe to events of this agent.
+    pub fn events(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Watch the connection status of this agent.
+    pub fn status(&self) -> watch::Receiver<Status> {
+        self.status.subscribe()
+    }
+
+    /// Get the metrics of this agent.
+    pub fn metrics(&self) -> Metrics {
+        self.context.metrics.clone()
+    }
+
+    /// The round-trip time of the last ping on the current connection.
+    pub fn ping_rtt(&self) -> Option<Duration> {
+        self.ping_rtt.get()
+    }
+
+    /// Get a handle to reload the config of this agent while it runs.
+    pub fn reloader(&self) -> Reloader {
+        Reloader { tx: self.reload_tx.clone() }
+    }
+
+    /// The local address of the health endpoint (if configured).
+    pub fn health_address(&self) -> Option<SocketAddr> {
+        self.health.as_ref().and_then(|l| l.local_addr().ok())
+    }
+
+    /// Run this agent in a new task.
+    pub fn spawn(self) -> Handle {
+        self.spawn_in(log::Span::none())
+    }
+
+    /// Run this agent in a new task within the given tracing span.
+    pub(crate) fn spawn_in(self, span: log::Span) -> Handle {
+        let token   = CancellationToken::new();
+        let id      = self.id.clone();
+        let events  = self.events.clone();
+        let status  = self.status.subscribe();
+        let metrics = self.metrics();
+        let rtt     = self.ping_rtt.clone();
+        let reload  = self.reloader();
+        let task    = spawn(self.run(token.clone().cancelled_owned()).instrument(span));
+        Handle { id, events, status, metrics, ping_rtt: rtt, reloader: reload, shutdown: token, task }
+    }
+
     /// Run this agent.
     ///
     /// This method will only return if the gateway terminates the agent with
     /// a reason (which is returned to the caller).
-    pub async fn go(mut self) -> Reason {
-        let mut connection = self.connect(Delay::ExpBackoff).await;
+    pub async fn go(self) -> Reason {
+        match self.run(future::pending()).await {
+            Some(reason) => reason,
+            None => unreachable!("pending future never completes")
+        }
+    }
+
+    /// Run this agent until terminated by the gateway or until `token` is cancelled.
+    ///
+    /// On cancellation, streams in progress are given some time to finish
+    /// before the connection to the gateway is closed. Returns the final
+    /// status, i.e. either [`Status::Terminated`] or [`Status::Stopped`].
+    pub async fn run_until(self, token: CancellationToken) -> Status {
+        match self.run(token.cancelled_owned()).await {
+            Some(reason) => Status::Terminated(reason),
+            None         => Status::Stopped
+        }
+    }
+
+    /// Run this agent until terminated by the gateway or until `shutdown` completes.
+    ///
+    /// Returns the termination reason or `None` on shutdown.
+    async fn run<F: Future<Output = ()>>(mut self, shutdown: F) -> Option<Reason> {
+        let mut shutdown = pin!(shutdown);
+
+        let _health = self.health.take().map(|listener| {
+            log::info!(addr = ?listener.local_addr().ok(), "serving health endpoint");
+            let state = health::State {
+                id: self.id.clone(),
+                version: self.version,
+                status: self.status.subscribe(),
+                authenticated: self.authenticated.clone(),
+                ping_rtt: self.ping_rtt.clone(),
+                metrics: self.context.metrics.clone()
+            };
+            guard(spawn(health::serve(listener, Arc::new(state))), |t| t.abort())
+        });
+
+        let Some(mut connection) = self.connect(Delay::ExpBackoff, &mut shutdown).await else {
+            self.stop(None).await;
+            return None
+        };
 
         log::info! {
             agent   = %self.id,
+            fp      = %format_args!("{:#}", self.id),
             version = %version().expect("valid version"),
             "up and running"
         };
 
+        // The ping deadline persists across events, so that frequent events
+        // (e.g. watchdog ticks) do not postpone pings indefinitely.
+        let mut ping = pin!(sleep(self.config.ping_frequency));
+
         // Event processing.
         loop {
             log::trace!("awaiting event ...");
+            // Set by events which require a new connection.
+            let mut reconnect = None;
             select! {
+                // Shutdown has been requested.
+                () = &mut shutdown => {
+                    self.stop(Some(connection)).await;
+                    return None
+                },
+
                 // A new server message.
                 message = recv(&mut connection.reader) => match message {
                     Err(e) => {
                         log::error!("error reading from server: {}", e);
-                        connection = self.reconnect(connection, Delay::ExpBackoff).await
+                        reconnect = Some(Delay::ExpBackoff)
                     }
                     Ok(None) => {
                         log::warn!("control channel closed by server, reconnecting ...");
-                        connection = self.reconnect(connection, Delay::ExpBackoff).await
+                        reconnect = Some(Delay::ExpBackoff)
                     }
                     Ok(Some(m)) => match self.on_message(&mut connection.writer, m).await {
                         Err(Error::Terminated(Reason::Disabled)) => {
                             // Being disabled is no reason for the agent to give up: Retry in
                             // fixed intervals.
-                            connection = self.reconnect(connection, Delay::Fixed(Duration::from_secs(5))).await
+                            reconnect = Some(Delay::Fixed(Duration::from_secs(5)))
                         }
-                        Err(Error::Terminated(reason)) =>
+                        Err(Error::Terminated(reason)) => {
                             // Other reasons for connection termination are permanent, thus
                             // terminate the agent.
-                            return reason,
+                            self.status.send_replace(Status::Terminated(reason));
+                            let _ = self.events.send(Event::Terminated(reason));
+                            return Some(reason)
+                        }
                         Err(e) => {
                             log::error!("failed to answer server message: {}", e);
-                            connection = self.reconnect(connection, Delay::ExpBackoff).await
+                            reconnect = Some(Delay::ExpBackoff)
                         }
-                        Ok(Some(mut conn)) => {
+                        Ok(true) => {
+                            let Some(mut conn) = self.connect(Delay::ExpBackoff, &mut shutdown).await else {
+                                self.stop(Some(connection)).await;
+                                return None
+                            };
                             mem::swap(&mut connection, &mut conn);
                             let drain = futures::stream::unfold(conn, |mut conn| async move {
                                 conn.inbound.recv().await.map(|s| (s, conn))
                             });
                             self.drainage.push(drain.boxed())
                         }
-                        Ok(None) => {}
+                        Ok(false) => {}
                     }
                 },
 
@@ -168,20 +565,22 @@ impl Agent {
                 stream = connection.inbound.recv(), if self.online => match stream {
                     None => {
                         log::debug!("connection to server lost");
-                        self.online = false
+                        self.online = false;
+                        self.authenticated.store(false, Ordering::Relaxed);
+                        self.ping_rtt.reset();
+                        self.status.send_replace(Status::Connecting);
+                        let _ = self.events.send(Event::Disconnected);
                     }
                     Some(s) => {
                         log::debug!("new inbound stream");
-                        let cfg = self.config.clone();
-                        self.streams.push(spawn(streamer(cfg, s)))
+                        self.accept_stream(s)
                     }
                 },
 
                 // A new inbound stream has been opened.
                 stream = self.drainage.next() => if let Some(s) = stream {
                     log::debug!("new inbound stream while draining");
-                    let cfg = self.config.clone();
-                    self.streams.push(spawn(streamer(cfg, s)))
+                    self.accept_stream(s)
                 },
 
                 // A connection test finished.
@@ -195,73 +594,137 @@ impl Agent {
                     }
                     Ok((re, code)) => {
                         let data = Client::Test { re, code };
-                        if let Err(e) = send(&mut connection.writer, Message::new(data)).await {
+                        if let Err(e) = send_timeout(&mut connection.writer, self.message(data), IO_TIMEOUT).await {
                             log::warn!(id = %re, "error sending message to server: {}", e);
-                            connection = self.reconnect(connection, Delay::ExpBackoff).await
+                            reconnect = Some(Delay::ExpBackoff)
                         }
                     }
                 },
 
                 // A stream completed.
-                Some(result) = self.streams.next() => {
-                    if let Err(e) = result {
+                Some(result) = self.streams.next() => match result {
+                    Ok(Ok(())) => {}
+                    Ok(Err(_)) => self.context.metrics.stream_error(),
+                    Err(e) => {
+                        self.context.metrics.stream_error();
                         if e.is_panic() {
                             log::error!("stream task panic: {}", e)
                         } else {
                             log::warn!("stream task error: {}", e)
                         }
                     }
-                }
+                },
+
+                // A new config has been provided.
+                Some(cfg) = self.reload_rx.recv() => self.reload(cfg),
+
+                // Time to tell the service manager that we are alive.
+                () = tick(&mut self.watchdog) => self.notify("watchdog", |s| s.watchdog()),
+
+                // Time to log the stream statistics.
+                () = tick(&mut self.summary) => self.log_summary(),
+
+                // Time to log the connection status.
+                () = tick(&mut self.heartbeat) => self.log_heartbeat(),
 
                 // Awaiting pong or time to send the next ping.
-                () = sleep(self.config.ping_frequency) => match self.ping_state {
-                    PingState::Idle => {
-                        let msg = Message::new(Client::Ping);
-                        if let Err(e) = send(&mut connection.writer, &msg).await {
-                            log::warn!("error sending message to server: {}", e);
-                            connection = self.reconnect(connection, Delay::ExpBackoff).await
-                        } else {
-                            self.ping_state = PingState::Awaiting(msg.id)
+                () = &mut ping => {
+                    ping.as_mut().reset(Instant::now() + self.config.ping_frequency);
+                    match self.ping_state {
+                        PingState::Idle => {
+                            let msg = self.message(Client::Ping);
+                            if let Err(e) = send_timeout(&mut connection.writer, &msg, IO_TIMEOUT).await {
+                                log::warn!("error sending message to server: {}", e);
+                                reconnect = Some(Delay::ExpBackoff)
+                            } else {
+                                self.ping_state = PingState::Awaiting(msg.id, Instant::now())
+                            }
+                        }
+                        PingState::Awaiting(id, _) => {
+                            log::warn!(%id, "no pong from server");
+                            reconnect = Some(Delay::ExpBackoff)
                         }
                     }
-                    PingState::Awaiting(id) => {
-                        log::warn!(%id, "no pong from server");
-                        connection = self.reconnect(connection, Delay::ExpBackoff).await
+                }
+            }
+
+            if let Some(delay) = reconnect {
+                match self.reconnect(connection, delay, &mut shutdown).await {
+                    Some(c) => connection = c,
+                    None    => {
+                        self.stop(None).await;
+                        return None
                     }
                 }
             }
         }
     }
 
+    /// Stop after shutdown has been requested.
+    ///
+    /// Active streams are given some time to finish before the connection
+    /// (if any) is closed.
+    async fn stop(&mut self, conn: Option<Connection>) {
+        log::info!("shutting down");
+        self.notify("stopping", |s| s.stopping());
+        self.status.send_replace(Status::Draining);
+        self.drain().await;
+        if let Some(c) = conn {
+            self.disconnect(c).await
+        }
+        self.status.send_replace(Status::Stopped)
+    }
+
     /// Handle message from server.
-    async fn on_message(&mut self, writer: &mut Writer, msg: Message<Server<'_>>) -> Result<Option<Connection>, Error> {
-        log::trace!(id = %msg.id, online = %self.online, data = ?msg.data, "received message");
+    ///
+    /// Returns `true` if the gateway asked to switch to a new connection.
+    async fn on_message(&mut self, writer: &mut Writer, msg: Message<Server<'_>>) -> Result<bool, Error> {
+        log::trace!(id = %msg.id, seq = ?msg.seq, online = %self.online, data = ?msg.data, "received message");
+
+        if let Some(seq) = msg.seq {
+            match self.seq_in.check(seq) {
+                Order::First | Order::Next => {}
+                Order::Gap { expected, actual } => {
+                    log::warn!(id = %msg.id, %expected, %actual, "gap in server message sequence")
+                }
+                Order::Duplicate { latest, actual } => {
+                    log::warn!(id = %msg.id, %latest, %actual, "duplicate server message sequence number")
+                }
+            }
+        }
 
         match msg.data {
             Some(Server::Accepted) => {
-                self.attempt = 0
+                self.attempt = 0;
+                self.authenticated.store(true, Ordering::Relaxed);
+                if !mem::replace(&mut self.ready, true) {
+                    self.notify("ready", |s| s.ready())
+                }
             }
             Some(Server::Ping) => {
                 if self.online {
-                    send(writer, Message::new(Client::Pong { re: msg.id })).await?;
+                    send_timeout(writer, self.message(Client::Pong { re: msg.id }), IO_TIMEOUT).await?;
                 }
             }
             Some(Server::Pong { re }) => {
-                if let PingState::Awaiting(p) = self.ping_state {
+                if let PingState::Awaiting(p, sent) = self.ping_state {
                     if re == p {
+                        let rtt = sent.elapsed();
+                        log::debug!(id = %re, ?rtt, "pong from server");
+                        self.ping_rtt.set(rtt);
                         self.ping_state = PingState::Idle
                     }
                 }
             }
             Some(Server::Challenge { text }) =>
                 if self.online {
-                    match decrypt(&self.config.secret_key, text.0.clone()) {
+                    match decrypt_any(self.config.secret_key.expose(), text.0.clone()) {
                         Ok(plain) => {
                             let data = Client::Response {
                                 re: msg.id,
                                 text: Cow::Borrowed(plain.as_ref().into())
                             };
-                            send(writer, Message::new(data)).await?;
+                            send_timeout(writer, self.message(data), IO_TIMEOUT).await?;
                         }
                         Err(e) => {
                             log::warn!(id = %msg.id, "failed to decrypt challenge: {}", e);
@@ -270,7 +733,7 @@ impl Agent {
                                 code: Some(ErrorCode::DecryptionFailed),
                                 msg: None
                             };
-                            send(writer, Message::new(data)).await?;
+                            send_timeout(writer, self.message(data), IO_TIMEOUT).await?;
                         }
                     }
                 }
@@ -278,18 +741,18 @@ impl Agent {
                 log::error!(id = %msg.id, ?reason, "connection terminated by gateway");
                 return Err(Error::Terminated(reason))
             }
-            Some(Server::Test { addr }) =>
+            Some(Server::Test { addr, scheme }) =>
                 if self.online {
-                    match stream::check_addr(addr, &self.config.allowed_addresses) {
+                    match stream::check_addr(addr, scheme.unwrap_or_default(), &self.context).await {
                         Err(code) => {
                             let data = Client::Test { re: msg.id, code: Some(code) };
-                            send(writer, Message::new(data)).await?;
+                            send_timeout(writer, self.message(data), IO_TIMEOUT).await?;
                         }
                         Ok(addr) => {
                             let id = msg.id;
-                            let cf = self.config.clone();
+                            let cx = self.context.clone();
                             self.tests.push(spawn(async move {
-                                if let Err(e) = stream::connect(id, &cf, &addr).await {
+                                if let Err(e) = stream::connect(id, &cx, &addr, None).await {
                                     log::warn!(%id, "test connection failed: {}", e);
                                     (id, Some(ErrorCode::CouldNotConnect))
                                 } else {
@@ -303,29 +766,39 @@ impl Agent {
             Some(Server::SwitchToNewConnection) =>
                 if self.online {
                     log::debug!(id = %msg.id, "switching to new connection and draining the existing one");
-                    send(writer, Message::new(Client::SwitchingConnection { re: msg.id })).await?;
-                    let c = self.connect(Delay::ExpBackoff).await;
-                    return Ok(Some(c))
+                    send_timeout(writer, self.message(Client::SwitchingConnection { re: msg.id }), IO_TIMEOUT).await?;
+                    self.status.send_replace(Status::Draining);
+                    return Ok(true)
                 }
             Some(Server::Error { msg }) => {
                 log::error!(?msg, "server error")
             }
+            Some(Server::RateLimitAdvisory { rate, seconds, addr }) => {
+                log::info!(id = %msg.id, rate, seconds, ?addr, "rate limit advisory");
+                let addr = addr.map(|a| a.into_owned());
+                self.context.limits.lock().expect("rate limits lock").advise(addr, rate, Duration::from_secs(seconds))
+            }
             None => {
                 log::warn!(id = %msg.id, "ignoring unknown gateway message")
             }
         }
-        Ok(None)
+        Ok(false)
     }
 
     /// Connect to server (with exponential backoff between failures).
-    async fn connect(&mut self, delay: Delay) -> Connection {
-        async fn try_connect(client: &tls::Client, version: &Version, cfg: &Config) -> Result<Connection, Error> {
+    ///
+    /// Returns `None` if `shutdown` completes first.
+    async fn connect<F>(&mut self, delay: Delay, shutdown: &mut Pin<&mut F>) -> Option<Connection>
+    where
+        F: Future<Output = ()>
+    {
+        async fn try_connect(client: &tls::Client, resolver: &dyn Resolver, version: &Version, cfg: &Config, seq: Seq) -> Result<Connection, Error> {
             let hostname = &cfg.server.host;
             let host_str = hostname.as_str();
             let port = cfg.server.port;
             log::debug!("connecting to {}:{} ...", host_str, port);
-            let iter     = net::lookup_host((host_str, port)).await?;
-            let future   = client.connect_any(iter, hostname);
+            let addrs    = resolver.resolve(host_str, port).await?;
+            let future   = client.connect_any(addrs.into_iter(), hostname);
             let stream   = timeout(cfg.connect_timeout, future).await??;
             let mut conn = {
                 let cfg = yamux::Config::default();
@@ -349,12 +822,13 @@ impl Agent {
             let stream = ctrl.open_stream().await?;
             let (r, w) = futures::io::AsyncReadExt::split(stream);
             let mut w  = Writer::new(w);
-            let pubkey = cfg.secret_key.public_key();
+            let pubkey = cfg.secret_key.expose().public_key();
             let hello  = Client::Hello {
                 pubkey: Cow::Borrowed(pubkey.as_bytes()[..].into()),
-                agent_version: *version
+                agent_version: *version,
+                protocol_version: Some(PROTOCOL_VERSION)
             };
-            send(&mut w, Message::new(hello)).await?;
+            send_timeout(&mut w, Message::new(hello).with_seq(seq), IO_TIMEOUT).await?;
             Ok(Connection {
                 ctrl,
                 reader: Reader::new(r),
@@ -367,32 +841,50 @@ impl Agent {
         let host = &self.config.server.host;
         let port = self.config.server.port;
 
+        if *self.status.borrow() != Status::Draining {
+            self.status.send_replace(Status::Connecting);
+            self.authenticated.store(false, Ordering::Relaxed);
+            self.ping_rtt.reset();
+        }
+
         loop {
-            match delay {
-                Delay::Fixed(d) => {
-                    log::info!("waiting {} before connecting ...", format_duration(d));
-                    sleep(d).await
-                }
+            let d = match delay {
+                Delay::Fixed(d) => d,
                 Delay::ExpBackoff => {
-                    if self.attempt > 0 {
-                        let d = Duration::from_secs(2u64.pow(self.attempt.into()));
-                        log::info!("waiting {} before connecting ...", format_duration(d));
-                        sleep(d).await
-                    }
-                    if self.attempt < 6 {
-                        self.attempt += 1
-                    }
+                    let d = self.backoff.delay(self.attempt.into());
+                    self.attempt = self.attempt.saturating_add(1);
+                    d
+                }
+            };
+            if !d.is_zero() {
+                log::info!("waiting {} before connecting ...", format_duration(d));
+                select! {
+                    () = watched(&mut self.watchdog, &*self.service, sleep(d)) => {}
+                    () = shutdown.as_mut() => return None
                 }
             }
-            match try_connect(&self.client, &self.version, &self.config).await {
+            let future = try_connect(&self.client, &*self.context.resolver, &self.version, &self.config, self.seq_out.next());
+            let result = select! {
+                r  = watched(&mut self.watchdog, &*self.service, future) => r,
+                () = shutdown.as_mut() => return None
+            };
+            match result {
                 Ok(conn) => {
-                    log::info!("connected to server: {}:{}", host.as_str(), port);
+                    log::info!(agent = %format_args!("{:#}", self.id), "connected to server: {}:{}", host.as_str(), port);
                     self.ping_state = PingState::Idle;
                     self.online = true;
-                    return conn
+                    self.status.send_replace(Status::Online);
+                    let _ = self.events.send(Event::Connected);
+                    return Some(conn)
                 }
                 Err(e) => {
-                    log::warn!(err = %e, "failed to connect to {}:{}", host.as_str(), port)
+                    log::warn!(err = %e, "failed to connect to {}:{}", host.as_str(), port);
+                    // Do not block the service manager's startup while the
+                    // gateway can not be reached.
+                    if !mem::replace(&mut self.ready, true) {
+                        self.notify("ready", |s| s.ready())
+                    }
+                    self.notify("status", |s| s.status("gateway unreachable, retrying"))
                 }
             }
         }
@@ -402,13 +894,177 @@ impl Agent {
     ///
     /// We consume the existing reader and writer to trigger an immediate
     /// close of the current connection.
-    async fn reconnect(&mut self, mut conn: Connection, delay: Delay) -> Connection {
+    async fn reconnect<F>(&mut self, conn: Connection, delay: Delay, shutdown: &mut Pin<&mut F>) -> Option<Connection>
+    where
+        F: Future<Output = ()>
+    {
+        self.disconnect(conn).await;
+        self.context.metrics.reconnect();
+        self.connect(delay, shutdown).await
+    }
+
+    /// Close the connection to the server.
+    async fn disconnect(&mut self, mut conn: Connection) {
         if let Err(e) = timeout(Duration::from_secs(5), conn.ctrl.close()).await {
             log::warn!("error closing connection: {}", e)
         }
         drop(conn);
-        self.online = false;
-        self.connect(delay).await
+        self.authenticated.store(false, Ordering::Relaxed);
+        self.ping_rtt.reset();
+        if mem::replace(&mut self.online, false) {
+            let _ = self.events.send(Event::Disconnected);
+        }
+    }
+
+    /// Apply the reloadable settings of a new config.
+    fn reload(&mut self, cfg: Config) {
+        let old = &self.config;
+        if cfg.secret_key.expose().public_key() != old.secret_key.expose().public_key()
+            || cfg.server.host != old.server.host
+            || cfg.server.port != old.server.port
+        {
+            log::warn!("ignoring new config: secret key and server can not be changed at runtime");
+            return
+        }
+        if self.ready {
+            self.notify("reloading", |s| s.reloading())
+        }
+        let cfg = Arc::new(cfg);
+        if self.config_policy {
+            let policy = Arc::new(Allowlist::new(cfg.allowed_addresses.clone()));
+            *self.context.policy.write().expect("policy lock") = policy;
+            self.context.audit.record(&AuditEvent::new(AuditKind::PolicyChanged {
+                description: "allowed-addresses reloaded from config".to_string()
+            }));
+        }
+        let denylist = Arc::new(Denylist::new(cfg.denied_addresses.clone()));
+        *self.context.denylist.write().expect("denylist lock") = denylist;
+        {
+            let mut limits = self.context.bandwidth.write().expect("bandwidth lock");
+            *limits = limits.reload(&cfg);
+            *self.context.config.write().expect("config lock") = cfg.clone();
+        }
+        if cfg.summary_interval != self.config.summary_interval {
+            self.summary = optional_interval(cfg.summary_interval)
+        }
+        if cfg.heartbeat_interval != self.config.heartbeat_interval {
+            self.heartbeat = optional_interval(cfg.heartbeat_interval)
+        }
+        self.config = cfg;
+        log::info!("config reloaded");
+        if self.ready {
+            self.notify("ready", |s| s.ready())
+        }
+        let _ = self.events.send(Event::ConfigReloaded);
+    }
+
+    /// Handle an inbound stream unless `max-concurrent-streams` has been reached.
+    fn accept_stream(&mut self, s: yamux::Stream) {
+        // `self.streams` always contains the sentinel task.
+        let active = self.streams.len() - 1;
+        if self.config.max_concurrent_streams.is_some_and(|max| active >= max) {
+            log::warn!(active, "rejecting stream, max. number of concurrent streams reached");
+            self.context.metrics.stream_rejected();
+            // Rejections are bounded by `IO_TIMEOUT` and must not count
+            // against the limit, so they are not added to `self.streams`.
+            spawn(async move {
+                if let Err(e) = stream::reject(s, ErrorCode::TooManyStreams).await {
+                    log::debug!("error rejecting stream: {}", e)
+                }
+            });
+        } else {
+            self.streams.push(spawn(streamer(self.context.clone(), s)))
+        }
+    }
+
+    /// Wait for active streams to finish (at most `DRAIN_TIMEOUT`).
+    async fn drain(&mut self) {
+        let streams = async {
+            // `self.streams` always contains the sentinel task.
+            while self.streams.len() > 1 {
+                self.streams.next().await;
+            }
+        };
+        if timeout(DRAIN_TIMEOUT, streams).await.is_err() {
+            log::warn!(streams = self.streams.len() - 1, "aborting streams still active after {}", format_duration(DRAIN_TIMEOUT))
+        }
+    }
+
+    /// Log the stream statistics per destination.
+    fn log_summary(&self) {
+        for (dest, stats) in self.context.metrics.destinations() {
+            let terminations = stats.terminations.iter()
+                .map(|(t, n)| format!("{t}={n}"))
+                .collect::<Vec<_>>()
+                .join(",");
+            log::info! {
+                to      = %dest,
+                streams = stats.streams,
+                active  = stats.active_streams,
+                sent    = stats.bytes_sent,
+                recv    = stats.bytes_received,
+                time    = %stats.duration.as_secs_f32(),
+                causes  = %terminations,
+                "stream summary"
+            }
+        }
+    }
+
+    /// Log the connection status and the last ping round-trip time.
+    fn log_heartbeat(&self) {
+        let metrics = self.context.metrics.snapshot();
+        log::info! {
+            status  = ?*self.status.borrow(),
+            rtt     = ?self.ping_rtt.get(),
+            streams = metrics.active_streams,
+            "heartbeat"
+        }
+    }
+
+    /// Create a new control message with the next sequence number.
+    fn message<D>(&mut self, data: D) -> Message<D> {
+        Message::new(data).with_seq(self.seq_out.next())
+    }
+
+    /// Send a notification to the service manager.
+    fn notify<F>(&self, what: &str, f: F)
+    where
+        F: FnOnce(&dyn Service) -> std::io::Result<()>
+    {
+        if let Err(e) = f(&*self.service) {
+            log::warn!(manager = %self.service.manager(), "failed to send {} notification: {}", what, e)
+        }
+    }
+}
+
+/// Wait for the next tick of an optional interval.
+async fn tick(timer: &mut Option<Interval>) {
+    match timer {
+        Some(i) => { i.tick().await; }
+        None    => future::pending().await
+    }
+}
+
+/// An interval which first ticks after `d` (None if `d` is zero).
+fn optional_interval(d: Duration) -> Option<Interval> {
+    if d.is_zero() {
+        return None
+    }
+    let mut i = interval_at(Instant::now() + d, d);
+    i.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    Some(i)
+}
+
+/// Await `future` while answering the service manager's watchdog.
+async fn watched<F: Future>(watchdog: &mut Option<Interval>, service: &dyn Service, future: F) -> F::Output {
+    let mut future = pin!(future);
+    loop {
+        select! {
+            x  = &mut future => return x,
+            () = tick(watchdog) => if let Err(e) = service.watchdog() {
+                log::warn!(manager = %service.manager(), "failed to send watchdog notification: {}", e)
+            }
+        }
     }
 }
 
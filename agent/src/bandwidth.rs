@@ -0,0 +1,201 @@
+use crate::config::Config;
+use crate::policy;
+use protocol::{Address, Scheme};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+/// The bandwidth limits of a config.
+#[derive(Debug, Default)]
+pub struct Limits {
+    /// The limit of all streams (`max-bandwidth`).
+    global: Option<Arc<Bandwidth>>,
+    /// The limits of `allowed-addresses` entries (same order), with the
+    /// address of the entry.
+    rules: Vec<Option<(String, Arc<Bandwidth>)>>
+}
+
+impl Limits {
+    pub fn new(cfg: &Config) -> Self {
+        Limits {
+            global: cfg.max_bandwidth.map(|n| Arc::new(Bandwidth::new(n))),
+            rules: cfg.allowed_addresses.iter()
+                .map(|r| r.max_bandwidth.map(|n| (r.to_string(), Arc::new(Bandwidth::new(n)))))
+                .collect()
+        }
+    }
+
+    /// Create the limits of a new config.
+    ///
+    /// The global limit and the limits of entries are kept if their rate
+    /// (and address) have not changed.
+    pub fn reload(&self, cfg: &Config) -> Self {
+        let mut new = Limits::new(cfg);
+        if self.global.as_ref().map(|b| b.rate()) == cfg.max_bandwidth {
+            new.global = self.global.clone()
+        }
+        for (addr, b) in new.rules.iter_mut().flatten() {
+            let old = self.rules.iter().flatten().find(|(a, o)| a == addr && o.rate() == b.rate());
+            if let Some((_, o)) = old {
+                *b = o.clone()
+            }
+        }
+        new
+    }
+
+    /// Select the limits which apply to streams to the given address.
+    ///
+    /// The first entry of `allowed-addresses` with bandwidth settings which
+    /// applies to the address is used. `cfg` must be the config these
+    /// limits have been created for.
+    pub fn select(&self, cfg: &Config, addr: &Address<'_>, scheme: Scheme) -> Vec<Arc<Bandwidth>> {
+        let rule = cfg.allowed_addresses.iter()
+            .zip(&self.rules)
+            .filter(|(r, _)| r.max_bandwidth.is_some() || r.global_bandwidth.is_some())
+            .find(|(r, _)| policy::rule_applies(r, addr, scheme));
+        let mut limits = Vec::new();
+        match rule {
+            Some((r, b)) => {
+                limits.extend(b.as_ref().map(|(_, b)| b.clone()));
+                if r.global_bandwidth.unwrap_or(true) {
+                    limits.extend(self.global.clone())
+                }
+            }
+            None => limits.extend(self.global.clone())
+        }
+        limits
+    }
+}
+
+/// A token bucket limiting the data rate of all streams sharing it.
+///
+/// The bucket holds at most one second worth of data. Callers may take
+/// more than is available and wait until the debt has been paid off.
+#[derive(Debug)]
+pub struct Bandwidth {
+    /// Bytes per second.
+    rate: u64,
+    state: Mutex<State>
+}
+
+#[derive(Debug)]
+struct State {
+    /// Available bytes (negative if overdrawn).
+    tokens: f64,
+    /// Time of the last refill.
+    last: Instant
+}
+
+impl Bandwidth {
+    /// Create a bucket for the given rate in bytes per second.
+    pub fn new(rate: u64) -> Self {
+        Bandwidth {
+            rate,
+            state: Mutex::new(State { tokens: rate as f64, last: Instant::now() })
+        }
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Take `n` bytes from the bucket, waiting until they are available.
+    pub async fn take(&self, n: usize) {
+        let wait = self.reserve(n, Instant::now());
+        if !wait.is_zero() {
+            sleep(wait).await
+        }
+    }
+
+    /// Take `n` bytes and return how long to wait for them.
+    fn reserve(&self, n: usize, now: Instant) -> Duration {
+        let rate = self.rate as f64;
+        let mut s = self.state.lock().expect("bandwidth lock");
+        let elapsed = now.saturating_duration_since(s.last).as_secs_f64();
+        s.tokens = (s.tokens + elapsed * rate).min(rate) - n as f64;
+        s.last = now.max(s.last);
+        if s.tokens < 0.0 {
+            Duration::from_secs_f64(-s.tokens / rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Config;
+    use crate::config::Rule;
+    use protocol::{Address, Scheme};
+    use super::{Bandwidth, Limits};
+    use std::time::{Duration, Instant};
+    use util::{HostName, NonEmpty};
+
+    #[test]
+    fn select_limits() {
+        let host = HostName::try_from("gateway.example.com").unwrap();
+        let mut cfg = Config::new(sealed_boxes::gen_secret_key(), host, 443);
+        cfg.max_bandwidth = Some(1000);
+        cfg.allowed_addresses = NonEmpty::try_from(vec![
+            Rule::try_from("10.1.0.0/16").unwrap().with_max_bandwidth(100),
+            Rule::try_from("10.2.0.0/16").unwrap().with_max_bandwidth(200).with_global_bandwidth(false),
+            Rule::try_from("10.0.0.0/8").unwrap()
+        ]).unwrap();
+        let limits = Limits::new(&cfg);
+        let rates = |ip: &str| {
+            let addr = Address::Addr((ip.parse::<std::net::IpAddr>().unwrap(), 80).into());
+            limits.select(&cfg, &addr, Scheme::Tcp).iter().map(|b| b.rate()).collect::<Vec<_>>()
+        };
+        assert_eq!(vec![100, 1000], rates("10.1.2.3"));
+        assert_eq!(vec![200], rates("10.2.2.3"));
+        assert_eq!(vec![1000], rates("10.3.2.3"));
+        let a = limits.select(&cfg, &Address::Addr(([10, 1, 0, 1], 80).into()), Scheme::Tcp);
+        let b = limits.select(&cfg, &Address::Addr(([10, 1, 0, 2], 80).into()), Scheme::Tcp);
+        assert!(std::sync::Arc::ptr_eq(&a[0], &b[0]))
+    }
+
+    #[test]
+    fn reload_limits() {
+        let host = HostName::try_from("gateway.example.com").unwrap();
+        let config = |rules: Vec<Rule>| {
+            let mut cfg = Config::new(sealed_boxes::gen_secret_key(), host.clone(), 443);
+            cfg.max_bandwidth = Some(1000);
+            cfg.allowed_addresses = NonEmpty::try_from(rules).unwrap();
+            cfg
+        };
+        let old = config(vec![
+            Rule::try_from("10.1.0.0/16").unwrap().with_max_bandwidth(100),
+            Rule::try_from("10.2.0.0/16").unwrap().with_max_bandwidth(200),
+            Rule::try_from("10.3.0.0/16").unwrap().with_max_bandwidth(300)
+        ]);
+        let new = config(vec![
+            Rule::try_from("10.0.0.0/16").unwrap().with_max_bandwidth(100),
+            Rule::try_from("10.3.0.0/16").unwrap().with_max_bandwidth(300),
+            Rule::try_from("10.2.0.0/16").unwrap().with_max_bandwidth(250),
+            Rule::try_from("10.1.0.0/16").unwrap().with_max_bandwidth(100)
+        ]);
+        let a = Limits::new(&old);
+        let b = a.reload(&new);
+        let bucket = |l: &Limits, cfg: &Config, ip: [u8; 4]| {
+            l.select(cfg, &Address::Addr((ip, 80).into()), Scheme::Tcp)[0].clone()
+        };
+        assert!(std::sync::Arc::ptr_eq(&bucket(&a, &old, [10, 1, 0, 1]), &bucket(&b, &new, [10, 1, 0, 1])));
+        assert!(std::sync::Arc::ptr_eq(&bucket(&a, &old, [10, 3, 0, 1]), &bucket(&b, &new, [10, 3, 0, 1])));
+        assert!(!std::sync::Arc::ptr_eq(&bucket(&a, &old, [10, 2, 0, 1]), &bucket(&b, &new, [10, 2, 0, 1])));
+        assert_eq!(250, bucket(&b, &new, [10, 2, 0, 1]).rate());
+        assert_eq!(100, bucket(&b, &new, [10, 0, 0, 1]).rate());
+        assert!(std::sync::Arc::ptr_eq(a.global.as_ref().unwrap(), b.global.as_ref().unwrap()))
+    }
+
+    #[test]
+    fn token_bucket() {
+        let b = Bandwidth::new(1000);
+        let t = Instant::now();
+        assert_eq!(Duration::ZERO, b.reserve(1000, t));
+        assert_eq!(Duration::from_millis(500), b.reserve(500, t));
+        assert_eq!(Duration::from_millis(1000), b.reserve(500, t));
+        assert_eq!(Duration::ZERO, b.reserve(500, t + Duration::from_secs(2)));
+        assert_eq!(Duration::ZERO, b.reserve(500, t + Duration::from_secs(10)));
+        assert_eq!(Duration::from_millis(500), b.reserve(1000, t + Duration::from_secs(10)))
+    }
+}
@@ -1,15 +1,25 @@
 use crate::dns_pattern::DnsPattern;
+use crate::error::Error;
+use crate::schedule::Schedule;
+use crate::secrets;
+use protocol::Scheme;
 use sealed_boxes::SecretKey;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde::de::{self, IntoDeserializer};
 use std::borrow::{Borrow, Cow};
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::fmt;
-use std::path::PathBuf;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
+use thiserror::Error;
 use tokio_rustls::rustls::pki_types::CertificateDer;
-use util::{HostName, NonEmpty};
+use util::{HostName, Location, NonEmpty, Secret};
+use util::audit::{self, AuditSink};
+use util::location::Registry;
 
 pub use ipnet::{IpNet, Ipv4Net, Ipv6Net};
 
@@ -20,6 +30,8 @@ pub use ipnet::{IpNet, Ipv4Net, Ipv6Net};
 pub struct Options {
     /// Path to the configuration file.
     ///
+    /// The format is determined by the file extension (`.toml`, `.json`, `.yaml` or `.yml`).
+    /// If the path is a directory, all config files in it are merged in lexical order.
     /// If this option is not present, a config file named `cluvio-agent.toml` is looked
     /// for in various locations.
     ///
@@ -51,46 +63,407 @@ pub struct Options {
     #[arg(short, long)]
     pub json: bool,
 
+    /// Where to send log messages.
+    #[arg(long, value_enum)]
+    pub log_target: Option<LogTarget>,
+
+    /// Append log messages to this file instead of printing them to stdout.
+    #[arg(long)]
+    pub log_file: Option<PathBuf>,
+
+    /// When to start a new log file.
+    #[arg(long, value_enum)]
+    pub log_rotation: Option<Rotation>,
+
+    /// Start a new log file when the current one exceeds this size, e.g. `10MB`.
+    #[arg(long, value_parser = parse_byte_size)]
+    pub log_max_size: Option<u64>,
+
+    /// The number of rotated log files to keep.
+    #[arg(long)]
+    pub log_max_files: Option<usize>,
+
     /// Generate a new keypair.
     #[arg(short, long)]
-    pub gen_keypair: bool
+    pub gen_keypair: bool,
+
+    /// Print the effective configuration (with the secret key redacted) and exit.
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Print the JSON Schema of the configuration file and exit.
+    #[arg(long)]
+    pub dump_config_schema: bool,
+
+    /// Detach from the terminal and run in the background.
+    ///
+    /// Output is discarded unless a log file is configured.
+    #[cfg(unix)]
+    #[arg(long)]
+    pub daemon: bool,
+
+    /// Write the process ID of the daemon to this file.
+    #[cfg(unix)]
+    #[arg(long, requires = "daemon")]
+    pub pid_file: Option<PathBuf>,
+
+    /// Run as a Windows service (the service must be named `cluvio-agent`).
+    #[cfg(windows)]
+    #[arg(long)]
+    pub service: bool
 }
 
 /// Config file representation.
-#[derive(Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 #[non_exhaustive]
 pub struct Config {
     /// The base64-encoded private key of this agent.
+    ///
+    /// Alternatively, `secret-key-file` may name a file which contains the
+    /// base64-encoded key and which must not be world-readable, or the key
+    /// may be a secret manager reference like `aws-sm://<name>` (requires
+    /// feature `cloud-secrets` and the `aws` or `gcloud` CLI on `PATH`).
+    /// Both are resolved by [`Config::from_path`] and similar functions,
+    /// which therefore block.
     #[serde(deserialize_with = "util::serde::decode_secret_key")]
-    pub secret_key: SecretKey,
+    pub secret_key: Secret<SecretKey>,
 
     /// The timeout of connects.
     #[serde(deserialize_with = "util::serde::decode_duration", default = "default_connect_timeout")]
+    #[serde(serialize_with = "util::serde::encode_duration")]
     pub connect_timeout: Duration,
 
     /// How often to check if the server is still there.
     #[serde(deserialize_with = "util::serde::decode_duration", default = "default_ping_frequency")]
+    #[serde(serialize_with = "util::serde::encode_duration")]
     pub ping_frequency: Duration,
 
+    /// The delay before the first reconnect attempt.
+    #[serde(deserialize_with = "util::serde::decode_duration", default = "default_reconnect_initial_delay")]
+    #[serde(serialize_with = "util::serde::encode_duration")]
+    pub reconnect_initial_delay: Duration,
+
+    /// The max. delay between reconnect attempts.
+    #[serde(deserialize_with = "util::serde::decode_duration", default = "default_reconnect_max_delay")]
+    #[serde(serialize_with = "util::serde::encode_duration")]
+    pub reconnect_max_delay: Duration,
+
+    /// The factor by which the reconnect delay grows with each failed attempt.
+    #[serde(default = "default_reconnect_multiplier")]
+    pub reconnect_multiplier: u32,
+
+    /// Local IP address to connect to internal addresses from.
+    pub bind_address: Option<IpAddr>,
+
+    /// Idle time before TCP keepalive probes are sent on connections to
+    /// internal addresses (zero disables keepalive).
+    #[serde(deserialize_with = "util::serde::decode_duration", default = "default_keepalive_time")]
+    #[serde(serialize_with = "util::serde::encode_duration")]
+    pub keepalive_time: Duration,
+
+    /// The interval between TCP keepalive probes.
+    #[serde(deserialize_with = "util::serde::decode_duration", default = "default_keepalive_interval")]
+    #[serde(serialize_with = "util::serde::encode_duration")]
+    pub keepalive_interval: Duration,
+
+    /// The number of unanswered TCP keepalive probes before a connection
+    /// is considered dead (ignored on Windows).
+    #[serde(default = "default_keepalive_retries")]
+    pub keepalive_retries: u32,
+
+    /// How often to log the stream statistics per destination (zero disables the summary).
+    #[serde(deserialize_with = "util::serde::decode_duration", default = "default_summary_interval")]
+    #[serde(serialize_with = "util::serde::encode_duration")]
+    pub summary_interval: Duration,
+
+    /// How often to log the connection status and ping round-trip time (zero disables the heartbeat).
+    #[serde(deserialize_with = "util::serde::decode_duration", default)]
+    #[serde(serialize_with = "util::serde::encode_duration")]
+    pub heartbeat_interval: Duration,
+
+    /// The max. number of concurrent data streams (per default there is no limit).
+    pub max_concurrent_streams: Option<usize>,
+
+    /// The max. data rate of all streams in bytes per second, e.g. `"50MB/s"`
+    /// (per default there is no limit). Applies to both directions combined.
+    #[serde(deserialize_with = "util::serde::decode_opt_data_rate", default)]
+    #[serde(serialize_with = "util::serde::encode_opt_data_rate", skip_serializing_if = "Option::is_none")]
+    pub max_bandwidth: Option<u64>,
+
     /// List of allowed domains or IPv4/IPv6 networks (per default there are no constraints).
     #[serde(default = "default_net")]
-    pub allowed_addresses: NonEmpty<Network>,
+    pub allowed_addresses: NonEmpty<Rule>,
+
+    /// Check every IP address a DNS name resolves to against the IP entries
+    /// of `allowed_addresses` before connecting.
+    #[serde(default)]
+    pub strict_ip_check: bool,
+
+    /// List of denied domains or IPv4/IPv6 networks, checked before `allowed_addresses`.
+    #[serde(default)]
+    pub denied_addresses: Vec<Rule>,
+
+    /// Static host name overrides, consulted before DNS resolution.
+    #[serde(default)]
+    pub hosts: BTreeMap<String, SocketAddr>,
 
     /// Server settings.
-    pub server: Server
+    pub server: Server,
+
+    /// Audit settings.
+    #[serde(default)]
+    pub audit: Audit,
+
+    /// Local health endpoint (disabled per default).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health: Option<Health>,
+
+    /// Log settings.
+    #[serde(default)]
+    pub log: Log
+}
+
+/// An allowed address entry.
+///
+/// Syntax: `[<scheme>://]<network>`, e.g. `tls://*.example.com`, where
+/// `<scheme>` is `tcp`, `tls` or `udp`. Without a scheme, the entry applies
+/// to all schemes.
+///
+/// An entry may also be a table with the `address` and further settings:
+///
+/// - a schedule, e.g. `{ address = "10.1.0.0/16", days = ["mon-fri"], hours = "08:00-20:00" }`.
+///   Outside of its schedule the entry does not apply.
+/// - `half-close = true|false` to override whether the gateway's request to
+///   use half-close is honoured for matching addresses.
+/// - `connect-timeout`, e.g. `"60s"`, to override the global `connect-timeout`.
+/// - `max-bandwidth`, e.g. `"10MB/s"`, to limit the data rate of all streams
+///   to matching addresses, in addition to the global `max-bandwidth` unless
+///   `global-bandwidth = false`.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    /// The transport scheme this rule is restricted to (None = any).
+    pub scheme: Option<Scheme>,
+    /// The network this rule applies to.
+    pub network: Network,
+    /// The time window this rule applies in (None = always).
+    pub schedule: Option<Schedule>,
+    /// Use (or do not use) half-close regardless of the gateway's request.
+    pub half_close: Option<bool>,
+    /// The timeout of connects to matching addresses.
+    pub connect_timeout: Option<Duration>,
+    /// The max. data rate of all streams to matching addresses (bytes per second).
+    pub max_bandwidth: Option<u64>,
+    /// Whether the global bandwidth limit applies to matching addresses (default: true).
+    pub global_bandwidth: Option<bool>
+}
+
+impl Rule {
+    /// Check if this rule applies to the given scheme.
+    pub fn allows_scheme(&self, scheme: Scheme) -> bool {
+        self.scheme.map(|s| s == scheme).unwrap_or(true)
+    }
+
+    /// Check if this rule applies at the current time.
+    pub fn is_active(&self) -> bool {
+        self.schedule.as_ref().map(Schedule::is_active).unwrap_or(true)
+    }
+
+    pub fn with_schedule(mut self, s: Schedule) -> Self {
+        self.schedule = Some(s);
+        self
+    }
+
+    pub fn with_half_close(mut self, b: bool) -> Self {
+        self.half_close = Some(b);
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, d: Duration) -> Self {
+        self.connect_timeout = Some(d);
+        self
+    }
+
+    pub fn with_max_bandwidth(mut self, n: u64) -> Self {
+        self.max_bandwidth = Some(n);
+        self
+    }
+
+    pub fn with_global_bandwidth(mut self, b: bool) -> Self {
+        self.global_bandwidth = Some(b);
+        self
+    }
+
+    /// Does this rule carry any settings beyond the address?
+    fn has_settings(&self) -> bool {
+        self.schedule.is_some()
+            || self.half_close.is_some()
+            || self.connect_timeout.is_some()
+            || self.max_bandwidth.is_some()
+            || self.global_bandwidth.is_some()
+    }
+
+    fn parse(s: &str) -> Result<Self, serde::de::value::Error> {
+        if let Some((scheme, net)) = s.split_once("://") {
+            let scheme = Scheme::from_str(scheme).map_err(de::Error::custom)?;
+            if scheme == Scheme::Unix {
+                // Networks do not match file system paths.
+                return Err(de::Error::custom(format!("unsupported scheme in address rule: {scheme}")))
+            }
+            let network = Network::try_from(net)?;
+            return Ok(Rule { scheme: Some(scheme), ..Rule::from(network) })
+        }
+        Network::try_from(s).map(Rule::from)
+    }
+}
+
+impl From<Network> for Rule {
+    fn from(network: Network) -> Self {
+        Rule {
+            scheme: None,
+            network,
+            schedule: None,
+            half_close: None,
+            connect_timeout: None,
+            max_bandwidth: None,
+            global_bandwidth: None
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(scheme) = self.scheme {
+            write!(f, "{}://", scheme)?
+        }
+        fmt::Display::fmt(&self.network, f)
+    }
+}
+
+impl Serialize for Rule {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        if !self.has_settings() {
+            return s.collect_str(self)
+        }
+        #[derive(Serialize)]
+        #[serde(rename_all = "kebab-case")]
+        struct Table<'a> {
+            address: String,
+            #[serde(flatten)]
+            schedule: Option<&'a Schedule>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            half_close: Option<bool>,
+            #[serde(serialize_with = "util::serde::encode_opt_duration", skip_serializing_if = "Option::is_none")]
+            connect_timeout: Option<Duration>,
+            #[serde(serialize_with = "util::serde::encode_opt_data_rate", skip_serializing_if = "Option::is_none")]
+            max_bandwidth: Option<u64>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            global_bandwidth: Option<bool>
+        }
+        Table {
+            address: self.to_string(),
+            schedule: self.schedule.as_ref(),
+            half_close: self.half_close,
+            connect_timeout: self.connect_timeout,
+            max_bandwidth: self.max_bandwidth,
+            global_bandwidth: self.global_bandwidth
+        }
+        .serialize(s)
+    }
+}
+
+impl TryFrom<&str> for Rule {
+    type Error = serde::de::value::Error;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        Rule::parse(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rule {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        struct RuleVisitor;
+
+        impl<'de> de::Visitor<'de> for RuleVisitor {
+            type Value = Rule;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("an address or a table with address and settings")
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<Rule, E> {
+                Rule::parse(s).map_err(E::custom)
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, m: A) -> Result<Rule, A::Error> {
+                #[derive(Deserialize)]
+                #[serde(rename_all = "kebab-case", deny_unknown_fields)]
+                struct Table {
+                    address: String,
+                    #[serde(default)]
+                    days: Vec<String>,
+                    hours: Option<String>,
+                    half_close: Option<bool>,
+                    #[serde(deserialize_with = "util::serde::decode_opt_duration", default)]
+                    connect_timeout: Option<Duration>,
+                    #[serde(deserialize_with = "util::serde::decode_opt_data_rate", default)]
+                    max_bandwidth: Option<u64>,
+                    global_bandwidth: Option<bool>
+                }
+                let t = Table::deserialize(de::value::MapAccessDeserializer::new(m))?;
+                let mut rule = Rule::parse(&t.address).map_err(de::Error::custom)?;
+                if !t.days.is_empty() || t.hours.is_some() {
+                    rule.schedule = Some(Schedule::parse(&t.days, t.hours.as_deref()).map_err(de::Error::custom)?)
+                }
+                rule.half_close = t.half_close;
+                rule.connect_timeout = t.connect_timeout;
+                rule.max_bandwidth = t.max_bandwidth;
+                rule.global_bandwidth = t.global_bandwidth;
+                Ok(rule)
+            }
+        }
+
+        d.deserialize_any(RuleVisitor)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum Network {
     /// IP network.
     Ip(IpNet),
+    /// IP network without some of its subnets.
+    ///
+    /// Syntax: `<network> - <subnet> [- <subnet> ...]`, e.g. `10.0.0.0/8 - 10.13.0.0/16`.
+    IpExcept(IpNet, NonEmpty<IpNet>),
     /// A DNS name.
     Dns(HostName),
     /// A DNS name pattern.
     Pat(DnsPattern),
 }
 
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Network::Ip(net) => fmt::Display::fmt(net, f),
+            Network::IpExcept(net, except) => {
+                fmt::Display::fmt(net, f)?;
+                for x in except.iter() {
+                    write!(f, " - {}", x)?
+                }
+                Ok(())
+            }
+            Network::Dns(dns) => fmt::Display::fmt(dns, f),
+            Network::Pat(pat) => fmt::Display::fmt(pat, f)
+        }
+    }
+}
+
+impl Serialize for Network {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
 impl TryFrom<&str> for Network {
     type Error = serde::de::value::Error;
 
@@ -105,6 +478,12 @@ impl<'de> Deserialize<'de> for Network {
         if let Ok(net) = IpNet::from_str(&s) {
             return Ok(Network::Ip(net))
         }
+        if let Some((net, except)) = parse_exclusion(&s) {
+            if let Some(sub) = except.iter().find(|sub| !net.contains(*sub)) {
+                return Err(de::Error::custom(format!("{} is not a subnet of {}", sub, net)))
+            }
+            return Ok(Network::IpExcept(net, except))
+        }
         if let Ok(dns) = HostName::try_from(&*s) {
             return Ok(Network::Dns(dns))
         }
@@ -118,11 +497,28 @@ impl<'de> Deserialize<'de> for Network {
 impl Config {
     pub fn new(sk: SecretKey, host: HostName, port: u16) -> Self {
         Config {
-            secret_key: sk,
+            secret_key: Secret::self_zeroizing(sk),
             connect_timeout: default_connect_timeout(),
             ping_frequency: default_ping_frequency(),
+            reconnect_initial_delay: default_reconnect_initial_delay(),
+            reconnect_max_delay: default_reconnect_max_delay(),
+            reconnect_multiplier: default_reconnect_multiplier(),
+            keepalive_time: default_keepalive_time(),
+            keepalive_interval: default_keepalive_interval(),
+            keepalive_retries: default_keepalive_retries(),
+            summary_interval: default_summary_interval(),
+            heartbeat_interval: Duration::ZERO,
+            bind_address: None,
+            max_concurrent_streams: None,
+            max_bandwidth: None,
             allowed_addresses: default_net(),
-            server: Server { host, port, trust: None }
+            strict_ip_check: false,
+            denied_addresses: Vec::new(),
+            hosts: BTreeMap::new(),
+            server: Server { host, port, trust: None, bind_address: None },
+            audit: Audit::default(),
+            health: None,
+            log: Log::default()
         }
     }
 
@@ -130,24 +526,440 @@ impl Config {
         &mut self.server
     }
 
-    pub fn allowed_addresses_mut(&mut self) -> &mut NonEmpty<Network> {
+    pub fn allowed_addresses_mut(&mut self) -> &mut NonEmpty<Rule> {
         &mut self.allowed_addresses
     }
+
+    pub fn denied_addresses_mut(&mut self) -> &mut Vec<Rule> {
+        &mut self.denied_addresses
+    }
+
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::new()
+    }
+
+    /// Read and validate the config file at the given path.
+    ///
+    /// The format is determined by the file extension: `.toml`, `.json`,
+    /// `.yaml` or `.yml`. If the path is a directory, its files are merged
+    /// (see [`Config::from_dir`]).
+    ///
+    /// Environment variables with prefix `CLUVIO_AGENT_` override the
+    /// settings of the file, e.g. `CLUVIO_AGENT_SERVER_PORT`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            return Self::from_dir(path)
+        }
+        Self::load(::config::File::from(path))
+    }
+
+    /// Read, merge and validate all config files of a directory.
+    ///
+    /// Files are merged in lexical order of their names and later files
+    /// override settings of earlier ones, except for `allowed-addresses`
+    /// and `denied-addresses` whose entries are concatenated. Only files
+    /// with a supported extension (see [`Config::from_path`]) are considered.
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        const EXTENSIONS: &[&str] = &["toml", "json", "yaml", "yml"];
+        const LISTS: [&str; 2] = ["allowed-addresses", "denied-addresses"];
+
+        let config_error = |e: ::config::ConfigError| Error::Config(Box::new(e));
+
+        let mut files = std::fs::read_dir(dir)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        files.retain(|p| {
+            p.is_file() && p.extension().and_then(|e| e.to_str()).is_some_and(|e| EXTENSIONS.contains(&e))
+        });
+        files.sort();
+
+        let mut merged = ::config::Config::builder();
+        let mut lists  = LISTS.map(|_| Vec::new());
+        for file in files {
+            log::debug!(?file, "merging config file");
+            let part = ::config::Config::builder()
+                .add_source(::config::File::from(file))
+                .build()
+                .map_err(config_error)?;
+            for (key, list) in LISTS.iter().zip(&mut lists) {
+                if let Ok(values) = part.get_array(key) {
+                    list.extend(values)
+                }
+            }
+            merged = merged.add_source(part)
+        }
+        for (key, list) in LISTS.into_iter().zip(lists) {
+            if !list.is_empty() {
+                merged = merged.set_override(key, list).map_err(config_error)?
+            }
+        }
+        Self::load(merged.build().map_err(config_error)?)
+    }
+
+    /// Parse and validate a TOML config.
+    pub fn from_toml(s: &str) -> Result<Self, Error> {
+        Self::load(::config::File::from_str(s, ::config::FileFormat::Toml))
+    }
+
+    /// Parse and validate a JSON config.
+    pub fn from_json(s: &str) -> Result<Self, Error> {
+        Self::load(::config::File::from_str(s, ::config::FileFormat::Json))
+    }
+
+    /// Parse and validate a YAML config.
+    pub fn from_yaml(s: &str) -> Result<Self, Error> {
+        Self::load(::config::File::from_str(s, ::config::FileFormat::Yaml))
+    }
+
+    fn load<S>(source: S) -> Result<Self, Error>
+    where
+        S: ::config::Source + Send + Sync + 'static
+    {
+        let config_error = |e: ::config::ConfigError| Error::Config(Box::new(e));
+        let mut raw = ::config::Config::builder()
+            .add_source(source)
+            .add_source(::config::Environment::with_prefix("CLUVIO_AGENT").separator("_"))
+            .build()
+            .map_err(config_error)?;
+        if let Ok(path) = raw.get_string("secret-key-file") {
+            if raw.get_string("secret-key").is_ok() {
+                return Err(Error::InvalidConfig(vec![ConfigError::SecretKeyConflict]))
+            }
+            let key = read_secret_key_file(Path::new(&path))?;
+            raw = ::config::Config::builder()
+                .add_source(raw)
+                .set_override("secret-key", key)
+                .and_then(|b| b.build())
+                .map_err(config_error)?
+        }
+        let resolved = match raw.get_string("secret-key") {
+            Ok(key) => secrets::resolve(&key)?,
+            Err(_)  => None
+        };
+        if let Some(key) = resolved {
+            raw = ::config::Config::builder()
+                .add_source(raw)
+                .set_override("secret-key", key)
+                .and_then(|b| b.build())
+                .map_err(config_error)?
+        }
+        let cfg: Config = raw.try_deserialize().map_err(config_error)?;
+        cfg.validate().map_err(Error::InvalidConfig)?;
+        Ok(cfg)
+    }
+
+    /// Render this config as TOML, with the secret key redacted.
+    pub fn to_toml(&self) -> Result<String, Error> {
+        toml::to_string(self).map_err(|e| Error::Config(Box::new(e)))
+    }
+
+    /// Check the settings for semantic errors.
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut errors = Vec::new();
+        if self.server.port == 0 {
+            errors.push(ConfigError::InvalidPort)
+        }
+        let zero_timeout = |r: &Rule| r.connect_timeout.is_some_and(|d| d.is_zero());
+        if self.connect_timeout.is_zero() || self.allowed_addresses.iter().any(zero_timeout) {
+            errors.push(ConfigError::ZeroConnectTimeout)
+        }
+        if self.ping_frequency.is_zero() {
+            errors.push(ConfigError::ZeroPingFrequency)
+        }
+        if self.reconnect_initial_delay.is_zero() {
+            errors.push(ConfigError::ZeroReconnectDelay)
+        }
+        if self.reconnect_max_delay < self.reconnect_initial_delay {
+            errors.push(ConfigError::ReconnectDelayRange)
+        }
+        if self.reconnect_multiplier == 0 {
+            errors.push(ConfigError::ZeroReconnectMultiplier)
+        }
+        if !self.keepalive_time.is_zero() && self.keepalive_interval.is_zero() {
+            errors.push(ConfigError::ZeroKeepaliveInterval)
+        }
+        if self.max_concurrent_streams == Some(0) {
+            errors.push(ConfigError::ZeroMaxConcurrentStreams)
+        }
+        if self.audit.webhook.as_deref().is_some_and(|u| !u.starts_with("http://") && !u.starts_with("https://")) {
+            errors.push(ConfigError::InvalidWebhook)
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// A semantic config error.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[non_exhaustive]
+pub enum ConfigError {
+    #[error("missing secret key")]
+    MissingSecretKey,
+
+    #[error("missing server host")]
+    MissingHost,
+
+    #[error("server port must not be 0")]
+    InvalidPort,
+
+    #[error("connect timeout must not be 0")]
+    ZeroConnectTimeout,
+
+    #[error("ping frequency must not be 0")]
+    ZeroPingFrequency,
+
+    #[error("reconnect initial delay must not be 0")]
+    ZeroReconnectDelay,
+
+    #[error("reconnect max delay must not be less than the initial delay")]
+    ReconnectDelayRange,
+
+    #[error("reconnect multiplier must not be 0")]
+    ZeroReconnectMultiplier,
+
+    #[error("keepalive interval must not be 0")]
+    ZeroKeepaliveInterval,
+
+    #[error("max. concurrent streams must not be 0")]
+    ZeroMaxConcurrentStreams,
+
+    #[error("audit webhook is not an http(s) url")]
+    InvalidWebhook,
+
+    #[error("secret-key and secret-key-file are mutually exclusive")]
+    SecretKeyConflict,
+
+    #[error("secret key file {0:?} must not be world-readable")]
+    InsecureSecretKeyFile(PathBuf)
+}
+
+/// Builds a [`Config`] programmatically.
+#[derive(Debug, Default)]
+pub struct ConfigBuilder {
+    secret_key: Option<Secret<SecretKey>>,
+    host: Option<HostName>,
+    port: Option<u16>,
+    trust: Vec<CertificateDer<'static>>,
+    connect_timeout: Option<Duration>,
+    ping_frequency: Option<Duration>,
+    reconnect_initial_delay: Option<Duration>,
+    reconnect_max_delay: Option<Duration>,
+    reconnect_multiplier: Option<u32>,
+    keepalive_time: Option<Duration>,
+    keepalive_interval: Option<Duration>,
+    keepalive_retries: Option<u32>,
+    summary_interval: Option<Duration>,
+    heartbeat_interval: Duration,
+    bind_address: Option<IpAddr>,
+    server_bind_address: Option<IpAddr>,
+    max_concurrent_streams: Option<usize>,
+    max_bandwidth: Option<u64>,
+    allowed_addresses: Vec<Rule>,
+    strict_ip_check: bool,
+    denied_addresses: Vec<Rule>,
+    hosts: BTreeMap<String, SocketAddr>,
+    audit: Audit,
+    health: Option<Health>,
+    log: Log
 }
 
-impl fmt::Debug for Config {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("Config")
-            .field("secret_key", &"********")
-            .field("connect_timeout", &self.connect_timeout)
-            .field("ping_frequency", &self.ping_frequency)
-            .field("server", &self.server)
-            .field("allowed_addresses", &self.allowed_addresses)
-            .finish()
+impl ConfigBuilder {
+    pub fn new() -> Self {
+        ConfigBuilder::default()
+    }
+
+    pub fn with_secret_key(mut self, sk: SecretKey) -> Self {
+        self.secret_key = Some(Secret::self_zeroizing(sk));
+        self
+    }
+
+    /// Set the gateway host name.
+    pub fn with_host(mut self, h: HostName) -> Self {
+        self.host = Some(h);
+        self
+    }
+
+    /// Set the gateway port (default = 443).
+    pub fn with_port(mut self, p: u16) -> Self {
+        self.port = Some(p);
+        self
+    }
+
+    /// Add a certificate to trust in addition to the system roots.
+    pub fn with_trust(mut self, c: CertificateDer<'static>) -> Self {
+        self.trust.push(c);
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, d: Duration) -> Self {
+        self.connect_timeout = Some(d);
+        self
+    }
+
+    pub fn with_ping_frequency(mut self, d: Duration) -> Self {
+        self.ping_frequency = Some(d);
+        self
+    }
+
+    pub fn with_reconnect_initial_delay(mut self, d: Duration) -> Self {
+        self.reconnect_initial_delay = Some(d);
+        self
+    }
+
+    pub fn with_reconnect_max_delay(mut self, d: Duration) -> Self {
+        self.reconnect_max_delay = Some(d);
+        self
+    }
+
+    pub fn with_reconnect_multiplier(mut self, m: u32) -> Self {
+        self.reconnect_multiplier = Some(m);
+        self
+    }
+
+    /// Set the TCP keepalive idle time (zero disables keepalive).
+    pub fn with_keepalive_time(mut self, d: Duration) -> Self {
+        self.keepalive_time = Some(d);
+        self
+    }
+
+    pub fn with_keepalive_interval(mut self, d: Duration) -> Self {
+        self.keepalive_interval = Some(d);
+        self
+    }
+
+    pub fn with_keepalive_retries(mut self, n: u32) -> Self {
+        self.keepalive_retries = Some(n);
+        self
+    }
+
+    /// Set how often to log the stream statistics per destination (zero disables the summary).
+    pub fn with_summary_interval(mut self, d: Duration) -> Self {
+        self.summary_interval = Some(d);
+        self
+    }
+
+    /// Set how often to log the connection status (zero disables the heartbeat).
+    pub fn with_heartbeat_interval(mut self, d: Duration) -> Self {
+        self.heartbeat_interval = d;
+        self
+    }
+
+    /// Limit the number of concurrent data streams.
+    pub fn with_max_concurrent_streams(mut self, n: usize) -> Self {
+        self.max_concurrent_streams = Some(n);
+        self
+    }
+
+    /// Limit the data rate of all streams (bytes per second).
+    pub fn with_max_bandwidth(mut self, n: u64) -> Self {
+        self.max_bandwidth = Some(n);
+        self
+    }
+
+    /// Set the local IP address to connect to internal addresses from.
+    pub fn with_bind_address(mut self, a: IpAddr) -> Self {
+        self.bind_address = Some(a);
+        self
+    }
+
+    /// Set the local IP address to connect to the gateway from.
+    pub fn with_server_bind_address(mut self, a: IpAddr) -> Self {
+        self.server_bind_address = Some(a);
+        self
+    }
+
+    /// Add an allowed address (per default there are no constraints).
+    pub fn with_allowed_address(mut self, r: Rule) -> Self {
+        self.allowed_addresses.push(r);
+        self
+    }
+
+    /// Check resolved IP addresses of DNS names against the allowed addresses.
+    pub fn with_strict_ip_check(mut self, b: bool) -> Self {
+        self.strict_ip_check = b;
+        self
+    }
+
+    /// Add a denied address, checked before the allowed addresses.
+    pub fn with_denied_address(mut self, r: Rule) -> Self {
+        self.denied_addresses.push(r);
+        self
+    }
+
+    /// Use the given socket address for a host name instead of resolving it.
+    pub fn with_host_override<S: Into<String>>(mut self, name: S, addr: SocketAddr) -> Self {
+        self.hosts.insert(name.into(), addr);
+        self
+    }
+
+    pub fn with_audit(mut self, a: Audit) -> Self {
+        self.audit = a;
+        self
+    }
+
+    /// Serve the health endpoint on the given socket address.
+    pub fn with_health(mut self, listen: SocketAddr) -> Self {
+        self.health = Some(Health::new(listen));
+        self
+    }
+
+    pub fn with_log(mut self, l: Log) -> Self {
+        self.log = l;
+        self
+    }
+
+    /// Create and validate the config.
+    pub fn build(self) -> Result<Config, Vec<ConfigError>> {
+        let mut errors = Vec::new();
+        if self.secret_key.is_none() {
+            errors.push(ConfigError::MissingSecretKey)
+        }
+        if self.host.is_none() {
+            errors.push(ConfigError::MissingHost)
+        }
+        let (Some(secret_key), Some(host)) = (self.secret_key, self.host) else {
+            return Err(errors)
+        };
+        let config = Config {
+            secret_key,
+            connect_timeout: self.connect_timeout.unwrap_or_else(default_connect_timeout),
+            ping_frequency: self.ping_frequency.unwrap_or_else(default_ping_frequency),
+            reconnect_initial_delay: self.reconnect_initial_delay.unwrap_or_else(default_reconnect_initial_delay),
+            reconnect_max_delay: self.reconnect_max_delay.unwrap_or_else(default_reconnect_max_delay),
+            reconnect_multiplier: self.reconnect_multiplier.unwrap_or_else(default_reconnect_multiplier),
+            keepalive_time: self.keepalive_time.unwrap_or_else(default_keepalive_time),
+            keepalive_interval: self.keepalive_interval.unwrap_or_else(default_keepalive_interval),
+            keepalive_retries: self.keepalive_retries.unwrap_or_else(default_keepalive_retries),
+            summary_interval: self.summary_interval.unwrap_or_else(default_summary_interval),
+            heartbeat_interval: self.heartbeat_interval,
+            bind_address: self.bind_address,
+            max_concurrent_streams: self.max_concurrent_streams,
+            max_bandwidth: self.max_bandwidth,
+            allowed_addresses: NonEmpty::try_from(self.allowed_addresses).unwrap_or_else(|_| default_net()),
+            strict_ip_check: self.strict_ip_check,
+            denied_addresses: self.denied_addresses,
+            hosts: self.hosts,
+            server: Server {
+                host,
+                port: self.port.unwrap_or_else(default_port),
+                trust: NonEmpty::try_from(self.trust).ok(),
+                bind_address: self.server_bind_address
+            },
+            audit: self.audit,
+            health: self.health,
+            log: self.log
+        };
+        config.validate()?;
+        Ok(config)
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(try_from = "ServerSpec")]
 #[non_exhaustive]
 pub struct Server {
     /// The hostname of the remote server.
@@ -159,7 +971,192 @@ pub struct Server {
 
     /// Optional certificate to add as trusted.
     #[serde(deserialize_with = "util::serde::decode_opt_certificates", default)]
-    pub trust: Option<NonEmpty<CertificateDer<'static>>>
+    #[serde(serialize_with = "util::serde::encode_opt_certificates", skip_serializing_if = "Option::is_none")]
+    pub trust: Option<NonEmpty<CertificateDer<'static>>>,
+
+    /// Local IP address to connect to the server from.
+    pub bind_address: Option<IpAddr>
+}
+
+/// Where to send audit events.
+///
+/// Without any destination, audit events are logged with target `audit`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct Audit {
+    /// Append audit events as JSON lines to this file.
+    pub file: Option<PathBuf>,
+
+    /// Send audit events to the local syslog daemon (Unix only).
+    #[serde(default)]
+    pub syslog: bool,
+
+    /// Post audit events to this URL (requires feature `webhook`).
+    pub webhook: Option<String>
+}
+
+impl Audit {
+    /// Create the audit sink for these settings.
+    pub fn sink(&self) -> Result<Arc<dyn AuditSink>, Error> {
+        let mut sinks = audit::Fanout::new();
+        if let Some(path) = &self.file {
+            sinks.add(audit::FileSink::open(path)?);
+        }
+        if self.syslog {
+            #[cfg(unix)]
+            sinks.add(audit::Syslog::connect(env!("CARGO_PKG_NAME"))?);
+            #[cfg(not(unix))]
+            log::warn!("syslog audit sink is not supported on this platform");
+        }
+        if let Some(url) = &self.webhook {
+            #[cfg(feature = "webhook")]
+            sinks.add(audit::Webhook::new(url, 1024, Duration::from_secs(10))?);
+            #[cfg(not(feature = "webhook"))]
+            log::warn!(%url, "webhook audit sink requires feature `webhook`");
+        }
+        if sinks.is_empty() {
+            return Ok(Arc::new(audit::Log))
+        }
+        Ok(Arc::new(sinks))
+    }
+}
+
+/// The local HTTP endpoint for health checks.
+///
+/// Serves `/healthz` (the process is up), `/readyz` (the agent is
+/// authenticated to the gateway) and `/status` (JSON summary).
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct Health {
+    /// The socket address to listen on, e.g. `127.0.0.1:9000`.
+    pub listen: SocketAddr
+}
+
+impl Health {
+    pub fn new(listen: SocketAddr) -> Self {
+        Health { listen }
+    }
+}
+
+/// Where to write log messages.
+///
+/// The command-line options `--log-target`, `--log-file`, `--log-rotation`,
+/// `--log-max-size` and `--log-max-files` take precedence. Changes require
+/// a restart.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct Log {
+    /// Where to send log messages.
+    #[serde(default)]
+    pub target: LogTarget,
+
+    /// Append log messages to this file instead of printing them to stdout.
+    pub file: Option<PathBuf>,
+
+    /// When to start a new log file.
+    #[serde(default)]
+    pub rotation: Rotation,
+
+    /// Start a new log file when the current one exceeds this size in bytes.
+    #[serde(deserialize_with = "util::serde::decode_opt_byte_size", default)]
+    #[serde(serialize_with = "util::serde::encode_opt_byte_size", skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+
+    /// The number of rotated log files to keep (named `<file>.1`, `<file>.2`, ...).
+    #[serde(default = "default_log_max_files")]
+    pub max_files: usize
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Log {
+            target: LogTarget::default(),
+            file: None,
+            rotation: Rotation::default(),
+            max_size: None,
+            max_files: default_log_max_files()
+        }
+    }
+}
+
+/// Where log messages are sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogTarget {
+    /// Standard output or the log file (if any).
+    #[default]
+    Stdout,
+    /// The local syslog daemon (Unix only).
+    Syslog,
+    /// The systemd journal (requires feature `journald`).
+    Journald
+}
+
+/// Time-based rotation of log files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rotation {
+    #[default]
+    Never,
+    Hourly,
+    Daily
+}
+
+/// Server settings as given in the config file.
+///
+/// Instead of `host`, a `location` may be given whose gateway host name is
+/// looked up in the location registry (see [`util::location::Registry::from_env`]).
+/// Locations unknown to the registry use the default host template.
+#[derive(Deserialize)]
+struct ServerSpec {
+    host: Option<HostName>,
+    location: Option<Location>,
+    #[serde(default = "default_port")]
+    port: u16,
+    #[serde(deserialize_with = "util::serde::decode_opt_certificates", default)]
+    trust: Option<NonEmpty<CertificateDer<'static>>>,
+    bind_address: Option<IpAddr>
+}
+
+impl TryFrom<ServerSpec> for Server {
+    type Error = String;
+
+    fn try_from(s: ServerSpec) -> Result<Self, Self::Error> {
+        let host = match (s.host, s.location) {
+            (Some(h), _)    => h,
+            (None, Some(l)) => {
+                let r = Registry::from_env().map_err(|e| e.to_string())?;
+                r.gateway_host(&l).map_err(|e| e.to_string())?
+            }
+            (None, None) => return Err("missing field `host` or `location`".to_string())
+        };
+        Ok(Server { host, port: s.port, trust: s.trust, bind_address: s.bind_address })
+    }
+}
+
+/// Read the base64-encoded secret key from a file.
+///
+/// On Unix, world-readable files are rejected.
+fn read_secret_key_file(path: &Path) -> Result<String, Error> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if std::fs::metadata(path)?.permissions().mode() & 0o004 != 0 {
+            return Err(Error::InvalidConfig(vec![ConfigError::InsecureSecretKeyFile(path.to_path_buf())]))
+        }
+    }
+    Ok(std::fs::read_to_string(path)?.trim().to_string())
+}
+
+/// Parse `<network> - <subnet> [- <subnet> ...]`.
+fn parse_exclusion(s: &str) -> Option<(IpNet, NonEmpty<IpNet>)> {
+    let mut parts = s.split('-').map(|p| IpNet::from_str(p.trim()));
+    let net = parts.next()?.ok()?;
+    let except = parts.collect::<Result<Vec<_>, _>>().ok()?;
+    Some((net, NonEmpty::try_from(except).ok()?))
 }
 
 fn default_port() -> u16 {
@@ -174,11 +1171,280 @@ fn default_ping_frequency() -> Duration {
     Duration::from_secs(60)
 }
 
-fn default_net() -> NonEmpty<Network> {
+fn default_reconnect_initial_delay() -> Duration {
+    Duration::from_secs(2)
+}
+
+fn default_reconnect_max_delay() -> Duration {
+    Duration::from_secs(64)
+}
+
+fn default_reconnect_multiplier() -> u32 {
+    2
+}
+
+fn default_keepalive_time() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_keepalive_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_keepalive_retries() -> u32 {
+    3
+}
+
+fn default_summary_interval() -> Duration {
+    Duration::from_secs(3600)
+}
+
+fn default_log_max_files() -> usize {
+    5
+}
+
+fn parse_byte_size(s: &str) -> Result<u64, String> {
+    util::serde::parse_byte_size(s).ok_or_else(|| format!("invalid byte size: {s}"))
+}
+
+fn default_net() -> NonEmpty<Rule> {
     let v = vec![
-        Network::Ip(Ipv4Net::new([0,0,0,0].into(), 0).expect("valid network").into()),
-        Network::Ip(Ipv6Net::new([0,0,0,0,0,0,0,0].into(), 0).expect("valid network").into()),
-        Network::Pat(DnsPattern::wildcard())
+        Rule::from(Network::Ip(Ipv4Net::new([0,0,0,0].into(), 0).expect("valid network").into())),
+        Rule::from(Network::Ip(Ipv6Net::new([0,0,0,0,0,0,0,0].into(), 0).expect("valid network").into())),
+        Rule::from(Network::Pat(DnsPattern::wildcard()))
     ];
     NonEmpty::try_from(v).expect("3 element vector is not empty")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, ConfigError, LogTarget, Network, Rotation, Rule};
+    use crate::{Allowlist, Error, Schedule};
+    use crate::policy;
+    use protocol::{Address, Scheme};
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use util::{HostName, NonEmpty};
+
+    #[test]
+    fn builder_validation() {
+        let errors = Config::builder().with_port(0).build().unwrap_err();
+        assert_eq!(vec![ConfigError::MissingSecretKey, ConfigError::MissingHost], errors);
+
+        let errors = Config::builder()
+            .with_secret_key(sealed_boxes::gen_secret_key())
+            .with_host(HostName::try_from("gateway.example.com").unwrap())
+            .with_port(0)
+            .with_ping_frequency(Duration::ZERO)
+            .with_reconnect_max_delay(Duration::from_secs(1))
+            .build()
+            .unwrap_err();
+        assert_eq!(vec![ConfigError::InvalidPort, ConfigError::ZeroPingFrequency, ConfigError::ReconnectDelayRange], errors);
+
+        let config = Config::builder()
+            .with_secret_key(sealed_boxes::gen_secret_key())
+            .with_host(HostName::try_from("gateway.example.com").unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(443, config.server.port)
+    }
+
+    #[test]
+    fn from_toml() {
+        let sk = util::base64::encode(sealed_boxes::gen_secret_key().to_bytes());
+        let toml = format!("secret-key = \"{sk}\"\nconnect-timeout = \"0s\"\n[server]\nhost = \"gateway.example.com\"\n");
+        match Config::from_toml(&toml) {
+            Err(Error::InvalidConfig(e)) => assert_eq!(vec![ConfigError::ZeroConnectTimeout], e),
+            other => panic!("unexpected result: {other:?}")
+        }
+    }
+
+    #[test]
+    fn from_json_and_yaml() {
+        let sk   = util::base64::encode(sealed_boxes::gen_secret_key().to_bytes());
+        let json = format!(r#"{{ "secret-key": "{sk}", "allowed-addresses": ["10.0.0.0/8"], "bind-address": "10.0.0.2", "server": {{ "host": "gateway.example.com", "port": 8443, "bind-address": "192.0.2.1" }} }}"#);
+        let yaml = format!("secret-key: {sk}\nallowed-addresses:\n  - 10.0.0.0/8\nbind-address: 10.0.0.2\nserver:\n  host: gateway.example.com\n  port: 8443\n  bind-address: 192.0.2.1\n");
+        for cfg in [Config::from_json(&json).unwrap(), Config::from_yaml(&yaml).unwrap()] {
+            assert_eq!("gateway.example.com", cfg.server.host.as_str());
+            assert_eq!(8443, cfg.server.port);
+            assert_eq!(Some([10, 0, 0, 2].into()), cfg.bind_address);
+            assert_eq!(Some([192, 0, 2, 1].into()), cfg.server.bind_address);
+            assert_eq!(1, cfg.allowed_addresses.len())
+        }
+    }
+
+    #[test]
+    fn custom_location() {
+        let sk   = util::base64::encode(sealed_boxes::gen_secret_key().to_bytes());
+        let toml = format!("secret-key = \"{sk}\"\n[server]\nlocation = \"ap-south\"\n");
+        let cfg  = Config::from_toml(&toml).unwrap();
+        assert_eq!("gateway.ap-south.cluvio.com", cfg.server.host.as_str());
+        assert!(Config::from_toml(&toml.replace("ap-south", "ap south")).is_err())
+    }
+
+    #[test]
+    fn host_overrides() {
+        let sk   = util::base64::encode(sealed_boxes::gen_secret_key().to_bytes());
+        let toml = format!("secret-key = \"{sk}\"\n[server]\nhost = \"gateway.example.com\"\n[hosts]\n\"db.internal\" = \"10.1.2.3:5432\"\n");
+        let cfg  = Config::from_toml(&toml).unwrap();
+        assert_eq!(Some(&SocketAddr::from(([10, 1, 2, 3], 5432))), cfg.hosts.get("db.internal"))
+    }
+
+    #[test]
+    fn log_settings() {
+        let sk   = util::base64::encode(sealed_boxes::gen_secret_key().to_bytes());
+        let toml = format!("secret-key = \"{sk}\"\n[server]\nhost = \"gateway.example.com\"\n");
+        let cfg  = Config::from_toml(&toml).unwrap();
+        assert_eq!((None, Rotation::Never, None, 5), (cfg.log.file, cfg.log.rotation, cfg.log.max_size, cfg.log.max_files));
+        let log  = "[log]\nfile = \"agent.log\"\nrotation = \"daily\"\nmax-size = \"10MiB\"\nmax-files = 3\n";
+        let cfg  = Config::from_toml(&format!("{toml}{log}")).unwrap();
+        assert_eq!(Some(PathBuf::from("agent.log")), cfg.log.file);
+        assert_eq!((Rotation::Daily, Some(10 << 20), 3), (cfg.log.rotation, cfg.log.max_size, cfg.log.max_files));
+        let cfg  = Config::from_toml(&format!("{toml}[log]\ntarget = \"journald\"\n")).unwrap();
+        assert_eq!(LogTarget::Journald, cfg.log.target);
+        assert!(Config::from_toml(&format!("{toml}[log]\ntarget = \"stderr\"\n")).is_err())
+    }
+
+    #[test]
+    fn network_exclusion() {
+        let rule = Rule::try_from("10.0.0.0/8 - 10.13.0.0/16 - 10.14.1.0/24").unwrap();
+        let list = Allowlist::new(NonEmpty::new(rule));
+        let addr = |s: &str| Address::Addr(SocketAddr::new(s.parse().unwrap(), 5432));
+        assert!(list.contains(&addr("10.1.2.3"), Scheme::Tcp));
+        assert!(list.contains(&addr("10.14.2.1"), Scheme::Tcp));
+        assert!(!list.contains(&addr("10.13.2.3"), Scheme::Tcp));
+        assert!(!list.contains(&addr("10.14.1.1"), Scheme::Tcp));
+        assert!(!list.contains(&addr("11.0.0.1"), Scheme::Tcp));
+
+        assert!(Rule::try_from("10.0.0.0/8 - 192.168.0.0/16").is_err());
+        assert!(matches!(Network::try_from("my-db.example.com"), Ok(Network::Dns(_))))
+    }
+
+    #[test]
+    fn config_dir() {
+        let dir = std::env::temp_dir().join(format!("cluvio-agent-conf.d-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let sk = util::base64::encode(sealed_boxes::gen_secret_key().to_bytes());
+        let base = format!("secret-key = \"{sk}\"\nallowed-addresses = [\"10.0.0.0/8\"]\n[server]\nhost = \"gateway.example.com\"\n");
+        std::fs::write(dir.join("10-base.toml"), base).unwrap();
+        std::fs::write(dir.join("20-team.toml"), "allowed-addresses = [\"db.example.com\"]\nping-frequency = \"10s\"\n").unwrap();
+        std::fs::write(dir.join("README"), "ignored").unwrap();
+        let cfg = Config::from_path(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let cfg = cfg.unwrap();
+        assert_eq!(2, cfg.allowed_addresses.len());
+        assert_eq!(Duration::from_secs(10), cfg.ping_frequency)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn secret_key_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("cluvio-agent-key-{}", std::process::id()));
+        let sk   = sealed_boxes::gen_secret_key();
+        std::fs::write(&path, util::base64::encode(sk.to_bytes()) + "\n").unwrap();
+        let toml = format!("secret-key-file = {:?}\n[server]\nhost = \"gateway.example.com\"\n", path);
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let insecure = Config::from_toml(&toml);
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        let secure = Config::from_toml(&toml);
+        let both = Config::from_toml(&format!("secret-key = \"x\"\n{toml}"));
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(insecure, Err(Error::InvalidConfig(e)) if matches!(e[..], [ConfigError::InsecureSecretKeyFile(_)])));
+        assert_eq!(sk.public_key(), secure.unwrap().secret_key.expose().public_key());
+        assert!(matches!(both, Err(Error::InvalidConfig(e)) if e == [ConfigError::SecretKeyConflict]))
+    }
+
+    #[test]
+    fn redacted_toml() {
+        let sk   = util::base64::encode(sealed_boxes::gen_secret_key().to_bytes());
+        let toml = format!("secret-key = \"{sk}\"\nallowed-addresses = [\"tls://*.example.com\", \"10.0.0.0/8 - 10.1.0.0/16\"]\n[server]\nhost = \"gateway.example.com\"\n");
+        let text = Config::from_toml(&toml).unwrap().to_toml().unwrap();
+        assert!(!text.contains(&sk));
+        assert!(text.contains("secret-key = \"********\""));
+        assert!(text.contains("\"tls://*.example.com\""));
+        assert!(text.contains("\"10.0.0.0/8 - 10.1.0.0/16\""));
+        assert!(text.contains("connect-timeout = \"30s\""))
+    }
+
+    #[test]
+    fn scheduled_rule() {
+        let sk   = util::base64::encode(sealed_boxes::gen_secret_key().to_bytes());
+        let rule = r#"{ address = "10.1.0.0/16", days = ["mon-fri"], hours = "08:00-20:00" }"#;
+        let toml = format!("secret-key = \"{sk}\"\nallowed-addresses = [{rule}]\n[server]\nhost = \"gateway.example.com\"\n");
+        let cfg  = Config::from_toml(&toml).unwrap();
+        let schedule = cfg.allowed_addresses[0].schedule.unwrap();
+        assert_eq!(Schedule::new().with_days("mon-fri".parse().unwrap()).with_hours("08:00-20:00".parse().unwrap()), schedule);
+        let again = Config::from_toml(&cfg.to_toml().unwrap().replace("********", &sk)).unwrap();
+        assert_eq!(Some(schedule), again.allowed_addresses[0].schedule);
+        let bad = toml.replace("mon-fri", "monday");
+        assert!(Config::from_toml(&bad).is_err())
+    }
+
+    #[test]
+    fn half_close_override() {
+        let sk   = util::base64::encode(sealed_boxes::gen_secret_key().to_bytes());
+        let list = r#"[{ address = "db.example.com", half-close = false }, "*.example.com"]"#;
+        let toml = format!("secret-key = \"{sk}\"\nallowed-addresses = {list}\n[server]\nhost = \"gateway.example.com\"\n");
+        let cfg  = Config::from_toml(&toml).unwrap();
+        let half_close = |name: &str| {
+            let addr = Address::Name(name.into(), 5432);
+            policy::setting(&cfg.allowed_addresses, &addr, Scheme::Tcp, |r| r.half_close)
+        };
+        assert_eq!(Some(false), half_close("db.example.com"));
+        assert_eq!(None, half_close("www.example.com"));
+        assert!(cfg.to_toml().unwrap().contains("half-close = false"))
+    }
+
+    #[test]
+    fn rule_scheme() {
+        let r = Rule::try_from("tls://*.example.com").unwrap();
+        assert_eq!(Some(Scheme::Tls), r.scheme);
+        assert_eq!("tls://*.example.com", r.to_string());
+        assert_eq!(Some(Scheme::Tcp), Rule::try_from("TCP://10.0.0.0/8").unwrap().scheme);
+        assert_eq!(None, Rule::try_from("10.0.0.0/8").unwrap().scheme);
+        assert!(Rule::try_from("unix:///run/db.sock").is_err());
+        assert!(Rule::try_from("unix://10.0.0.0/8").is_err());
+        assert!(Rule::try_from("ftp://10.0.0.0/8").is_err())
+    }
+
+    #[test]
+    fn scheme_filter() {
+        let rules = [
+            Rule::try_from("tls://10.1.0.0/16").unwrap(),
+            Rule::try_from("udp://*.example.com").unwrap(),
+            Rule::try_from("10.2.0.0/16").unwrap()
+        ];
+        let ip   = |s: &str| Address::Addr(SocketAddr::new(s.parse().unwrap(), 443));
+        let name = |s: &str| Address::Name(s.into(), 53);
+        assert!(policy::applies(&rules, &ip("10.1.2.3"), Scheme::Tls));
+        assert!(!policy::applies(&rules, &ip("10.1.2.3"), Scheme::Tcp));
+        assert!(policy::applies(&rules, &name("dns.example.com"), Scheme::Udp));
+        assert!(!policy::applies(&rules, &name("dns.example.com"), Scheme::Tls));
+        for scheme in [Scheme::Tcp, Scheme::Tls, Scheme::Udp] {
+            assert!(policy::applies(&rules, &ip("10.2.2.3"), scheme))
+        }
+    }
+
+    #[test]
+    fn connect_timeout_override() {
+        let sk   = util::base64::encode(sealed_boxes::gen_secret_key().to_bytes());
+        let list = r#"[{ address = "10.8.0.0/16", connect-timeout = "60s" }, "10.0.0.0/8"]"#;
+        let toml = format!("secret-key = \"{sk}\"\nconnect-timeout = \"5s\"\nallowed-addresses = {list}\n[server]\nhost = \"gateway.example.com\"\n");
+        let cfg  = Config::from_toml(&toml).unwrap();
+        let timeout = |ip: &str| {
+            let addr = Address::Addr(SocketAddr::new(ip.parse().unwrap(), 5432));
+            policy::setting(&cfg.allowed_addresses, &addr, Scheme::Tcp, |r| r.connect_timeout)
+        };
+        assert_eq!(Some(Duration::from_secs(60)), timeout("10.8.1.2"));
+        assert_eq!(None, timeout("10.9.1.2"));
+        match Config::from_toml(&toml.replace("60s", "0s")) {
+            Err(Error::InvalidConfig(e)) => assert_eq!(vec![ConfigError::ZeroConnectTimeout], e),
+            other => panic!("unexpected result: {other:?}")
+        }
+    }
+}
@@ -15,7 +15,8 @@ impl DnsPattern {
 
     /// Check if the given domain name matches this pattern.
     ///
-    /// The matching follows the rules of [RFC 6265][1].
+    /// The matching follows the rules of [RFC 6265][1], ignoring case and
+    /// a trailing dot of the domain name.
     ///
     /// [1]: https://datatracker.ietf.org/doc/html/rfc6265#section-5.1.3
     pub fn matches(&self, domain: &str) -> bool {
@@ -26,6 +27,7 @@ impl DnsPattern {
                 return true
             };
 
+        let domain = domain.strip_suffix('.').unwrap_or(domain);
         let mut theirs = domain.chars().rev();
 
         for ours in ours.chars().rev() {
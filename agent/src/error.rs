@@ -1,4 +1,5 @@
-use protocol::{Id, Reason};
+use crate::config::ConfigError;
+use protocol::{AgentId, Id, Reason};
 use std::io;
 use thiserror::Error;
 use tokio::time::error::Elapsed;
@@ -25,6 +26,9 @@ pub enum Error {
     #[error("host {0} not reachable")]
     Unreachable(String),
 
+    #[error("no resolved address of {0} is allowed")]
+    NotAllowed(String),
+
     #[error("agent is terminated, reason: {0:?}")]
     Terminated(Reason),
 
@@ -35,6 +39,22 @@ pub enum Error {
     Version(#[source] Box<dyn std::error::Error + Send + Sync>),
 
     #[error("unknown message type: {0}")]
-    UnknownMessageType(Id)
+    UnknownMessageType(Id),
+
+    #[error("duplicate agent: {0}")]
+    DuplicateAgent(AgentId),
+
+    #[error("config error: {0}")]
+    Config(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("invalid config: {}", join(.0))]
+    InvalidConfig(Vec<ConfigError>),
+
+    #[error("secret manager error: {0}")]
+    SecretManager(String)
+}
+
+fn join(errors: &[ConfigError]) -> String {
+    errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ")
 }
 
@@ -0,0 +1,50 @@
+use protocol::{Address, Id, Reason};
+use std::time::Duration;
+
+/// Notable state changes of a running agent.
+///
+/// See [`crate::Agent::events`] and [`crate::Handle::events`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Event {
+    /// The agent connected to the gateway.
+    Connected,
+    /// The connection to the gateway was lost or closed.
+    Disconnected,
+    /// A stream to an internal address has been established.
+    StreamOpened {
+        id: Id,
+        addr: Address<'static>
+    },
+    /// A stream to an internal address has finished.
+    StreamClosed {
+        id: Id,
+        addr: Address<'static>,
+        sent: u64,
+        received: u64,
+        duration: Duration
+    },
+    /// A new config has been applied.
+    ConfigReloaded,
+    /// The gateway terminated the agent.
+    Terminated(Reason)
+}
+
+/// The connection status of an agent.
+///
+/// See [`crate::Agent::status`] and [`crate::Handle::status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Status {
+    /// The agent is (re-)connecting to the gateway.
+    Connecting,
+    /// The agent is connected to the gateway.
+    Online,
+    /// The agent drains streams, either because it switches to a new
+    /// connection or because it shuts down.
+    Draining,
+    /// The gateway terminated the agent.
+    Terminated(Reason),
+    /// The agent has been shut down.
+    Stopped
+}
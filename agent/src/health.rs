@@ -0,0 +1,156 @@
+//! Local HTTP endpoint for health checks (see [`crate::config::Health`]).
+
+use crate::agent::PingRtt;
+use crate::event::Status;
+use crate::metrics::Metrics;
+use protocol::{AgentId, Version};
+use serde_json::json;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::time::{sleep, timeout};
+
+/// Max. time to read a request and write the response.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Max. size of a request head.
+const MAX_REQUEST: usize = 4096;
+
+/// The agent state reported by the endpoint.
+pub(crate) struct State {
+    pub(crate) id: AgentId,
+    pub(crate) version: Version,
+    pub(crate) status: watch::Receiver<Status>,
+    pub(crate) authenticated: Arc<AtomicBool>,
+    pub(crate) ping_rtt: Arc<PingRtt>,
+    pub(crate) metrics: Metrics
+}
+
+impl State {
+    fn is_ready(&self) -> bool {
+        *self.status.borrow() == Status::Online && self.authenticated.load(Ordering::Relaxed)
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let status = match *self.status.borrow() {
+            Status::Connecting    => "connecting",
+            Status::Online        => "online",
+            Status::Draining      => "draining",
+            Status::Terminated(_) => "terminated",
+            Status::Stopped       => "stopped"
+        };
+        let metrics = self.metrics.snapshot();
+        let destinations: serde_json::Map<String, serde_json::Value> = self.metrics.destinations()
+            .into_iter()
+            .map(|(dest, d)| {
+                let terminations: serde_json::Map<String, serde_json::Value> = d.terminations.iter()
+                    .map(|(t, n)| (t.to_string(), json!(n)))
+                    .collect();
+                let stats = json!({
+                    "streams": d.streams,
+                    "active-streams": d.active_streams,
+                    "bytes-sent": d.bytes_sent,
+                    "bytes-received": d.bytes_received,
+                    "millis": u64::try_from(d.duration.as_millis()).unwrap_or(u64::MAX),
+                    "terminations": terminations
+                });
+                (dest, stats)
+            })
+            .collect();
+        json!({
+            "agent": self.id.to_string(),
+            "fingerprint": format!("{:#}", self.id),
+            "version": self.version.to_string(),
+            "status": status,
+            "authenticated": self.authenticated.load(Ordering::Relaxed),
+            "active-streams": metrics.active_streams,
+            "ping-rtt-ms": self.ping_rtt.get().map(|d| d.as_secs_f64() * 1000.0),
+            "destinations": destinations
+        })
+    }
+}
+
+/// Bind the listener of the endpoint.
+///
+/// Must be called within a tokio runtime.
+pub(crate) fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener)
+}
+
+/// Answer health check requests until the task is aborted.
+pub(crate) async fn serve(listener: TcpListener, state: Arc<State>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = timeout(TIMEOUT, respond(stream, &state)).await.unwrap_or_else(|e| Err(e.into())) {
+                        log::debug!("health request failed: {}", e)
+                    }
+                });
+            }
+            Err(e) => {
+                log::warn!("failed to accept health connection: {}", e);
+                sleep(Duration::from_millis(100)).await
+            }
+        }
+    }
+}
+
+/// Read a request head and write the response.
+async fn respond(mut stream: TcpStream, state: &State) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(1024);
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        if buf.len() >= MAX_REQUEST {
+            return write(&mut stream, "431 Request Header Fields Too Large", "text/plain", "request too large\n").await
+        }
+        let mut chunk = [0; 1024];
+        match stream.read(&mut chunk).await? {
+            0 => return Ok(()),
+            n => buf.extend_from_slice(&chunk[.. n])
+        }
+    }
+    let line = buf.split(|b| *b == b'\r').next().unwrap_or_default();
+    let line = String::from_utf8_lossy(line);
+    let mut parts = line.split(' ');
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let path = path.split('?').next().unwrap_or_default();
+
+    if method != "GET" {
+        return write(&mut stream, "405 Method Not Allowed", "text/plain", "method not allowed\n").await
+    }
+    match path {
+        "/healthz" => write(&mut stream, "200 OK", "text/plain", "ok\n").await,
+        "/readyz"  =>
+            if state.is_ready() {
+                write(&mut stream, "200 OK", "text/plain", "ready\n").await
+            } else {
+                write(&mut stream, "503 Service Unavailable", "text/plain", "not ready\n").await
+            }
+        "/status"  => {
+            let body = state.to_json().to_string();
+            write(&mut stream, "200 OK", "application/json", &body).await
+        }
+        _ => write(&mut stream, "404 Not Found", "text/plain", "not found\n").await
+    }
+}
+
+async fn write(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    let head = format! {
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    };
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,35 @@
+use protocol::{Address, Id};
+use std::time::Duration;
+
+/// Callbacks invoked during the lifetime of streams.
+///
+/// All methods have empty default implementations. They are called from
+/// the stream tasks and should return quickly.
+pub trait StreamHook: Send + Sync + 'static {
+    /// The address of a new stream has been allowed.
+    fn accepted(&self, _id: Id, _addr: &Address<'_>) {}
+
+    /// The connection to the address has been established.
+    fn connected(&self, _id: Id, _addr: &Address<'_>) {}
+
+    /// Data has been transferred.
+    ///
+    /// The amounts are bytes since the previous call. `sent` is data sent
+    /// to the gateway, `received` is data received from the gateway.
+    fn progress(&self, _id: Id, _sent: u64, _received: u64) {}
+
+    /// A connected stream has finished.
+    fn closed(&self, _id: Id, _addr: &Address<'_>, _stats: &StreamStats) {}
+}
+
+/// Summary of a finished stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct StreamStats {
+    /// Bytes sent to the gateway.
+    pub sent: u64,
+    /// Bytes received from the gateway.
+    pub received: u64,
+    /// Time from connection establishment until the end of the transfer.
+    pub duration: Duration
+}
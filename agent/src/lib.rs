@@ -2,13 +2,27 @@
 
 mod address;
 mod agent;
+mod bandwidth;
 mod dns_pattern;
 mod error;
+mod event;
+mod health;
+mod hook;
+mod limit;
+mod metrics;
+mod policy;
+mod schedule;
+mod schema;
+mod secrets;
+mod set;
 mod stream;
 mod tls;
 
 pub mod config;
 
+#[cfg(feature = "test-util")]
+pub mod test_util;
+
 /// Version of this crate.
 pub fn version() -> Result<protocol::Version, Error> {
     let parse = |s: &str| s.parse().map_err(|e| Error::Version(Box::new(e)));
@@ -20,12 +34,23 @@ pub fn version() -> Result<protocol::Version, Error> {
 
 use futures::io;
 use minicbor_io::{AsyncReader, AsyncWriter};
+use std::time::Duration;
+
+/// Max. time to send a message or to receive an expected message.
+pub(crate) const IO_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub(crate) type Reader = AsyncReader<io::ReadHalf<yamux::Stream>>;
 pub(crate) type Writer = AsyncWriter<io::WriteHalf<yamux::Stream>>;
 
-pub use self::agent::Agent;
-pub use self::config::{Config, Options};
+pub use self::agent::{Agent, Builder, Handle, Reloader};
+pub use self::config::{Config, ConfigBuilder, ConfigError, Options};
 pub use self::dns_pattern::DnsPattern;
+pub use self::event::{Event, Status};
+pub use self::metrics::{DestinationStats, Metrics, MetricsSnapshot, OTHER_DESTINATIONS, Termination};
+pub use self::hook::{StreamHook, StreamStats};
+pub use self::policy::{AddressPolicy, Allowlist, Denylist};
+pub use self::schedule::{Days, Hours, Schedule};
+pub use self::schema::config_schema;
+pub use self::set::{AgentSet, SetHandle};
 pub use error::Error;
 
@@ -0,0 +1,172 @@
+use protocol::Address;
+use std::cmp::max;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Upper bound of the duration of a single rate limit advisory.
+const MAX_DURATION: Duration = Duration::from_secs(24 * 3600);
+
+/// Limits of the rate of new streams, as advised by the gateway.
+#[derive(Debug, Default)]
+pub struct RateLimits {
+    /// Limit applying to all streams.
+    global: Option<Limit>,
+    /// Limits applying to streams to specific addresses.
+    by_addr: HashMap<Address<'static>, Limit>
+}
+
+/// A temporary limit of new streams per second.
+#[derive(Debug)]
+struct Limit {
+    /// The minimum time between two streams.
+    interval: Duration,
+    /// The earliest point in time for the next stream.
+    next: Instant,
+    /// The point in time when the limit expires.
+    until: Instant
+}
+
+impl Limit {
+    fn new(rate: u32, duration: Duration, now: Instant) -> Self {
+        Limit {
+            interval: Duration::from_secs(1) / rate,
+            next: now,
+            until: now + duration.min(MAX_DURATION)
+        }
+    }
+
+    /// Reserve the next stream slot and return how long to wait for it.
+    ///
+    /// No slots are reserved beyond the expiry of the limit, i.e. a stream
+    /// waits at most until the limit expires.
+    fn reserve(&mut self, now: Instant) -> Duration {
+        let t = max(self.next, now);
+        if t >= self.until {
+            return self.until.saturating_duration_since(now)
+        }
+        self.next = t + self.interval;
+        t - now
+    }
+}
+
+impl RateLimits {
+    pub fn new() -> Self {
+        RateLimits::default()
+    }
+
+    /// Apply an advisory for the given address (or all streams if `None`).
+    ///
+    /// A rate of 0 lifts the current limit.
+    pub fn advise(&mut self, addr: Option<Address<'static>>, rate: u32, duration: Duration) {
+        let now = Instant::now();
+        match (addr, rate) {
+            (None, 0) => {
+                self.global = None
+            }
+            (None, r) => {
+                self.global = Some(Limit::new(r, duration, now))
+            }
+            (Some(a), 0) => {
+                self.by_addr.remove(&a);
+            }
+            (Some(a), r) => {
+                self.by_addr.insert(a, Limit::new(r, duration, now));
+            }
+        }
+    }
+
+    /// Get the delay to apply before opening a new stream to the given address.
+    pub fn delay(&mut self, addr: &Address<'_>) -> Duration {
+        let now = Instant::now();
+        self.expire(now);
+        let g = self.global.as_mut().map(|l| l.reserve(now)).unwrap_or_default();
+        let a = self.by_addr.get_mut(&addr.to_owned()).map(|l| l.reserve(now)).unwrap_or_default();
+        max(g, a)
+    }
+
+    /// Remove expired limits.
+    fn expire(&mut self, now: Instant) {
+        if self.global.as_ref().map(|l| l.until <= now).unwrap_or(false) {
+            self.global = None
+        }
+        self.by_addr.retain(|_, l| l.until > now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::Address;
+    use std::borrow::Cow;
+    use std::time::Duration;
+    use super::RateLimits;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn addr(name: &'static str) -> Address<'static> {
+        Address::Name(Cow::Borrowed(name), 5432)
+    }
+
+    /// The delay is at most the full interval and more than half of it.
+    fn assert_delay(interval: Duration, d: Duration) {
+        assert!(d <= interval && d > interval / 2, "{d:?} not close to {interval:?}")
+    }
+
+    #[test]
+    fn advise_delay() {
+        let mut r = RateLimits::new();
+        assert_eq!(Duration::ZERO, r.delay(&addr("a")));
+        r.advise(None, 10, MINUTE);
+        assert_eq!(Duration::ZERO, r.delay(&addr("a")));
+        assert_delay(Duration::from_millis(100), r.delay(&addr("a")));
+        assert_delay(Duration::from_millis(200), r.delay(&addr("b")))
+    }
+
+    #[test]
+    fn expiry() {
+        let mut r = RateLimits::new();
+        r.advise(None, 1, Duration::from_millis(10));
+        r.advise(Some(addr("a")), 1, Duration::from_millis(10));
+        assert_eq!(Duration::ZERO, r.delay(&addr("a")));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(Duration::ZERO, r.delay(&addr("a")));
+        assert_eq!(Duration::ZERO, r.delay(&addr("a")))
+    }
+
+    #[test]
+    fn burst_beyond_expiry() {
+        let mut r = RateLimits::new();
+        r.advise(Some(addr("a")), 10, Duration::from_secs(1));
+        let delays: Vec<Duration> = (0 .. 50).map(|_| r.delay(&addr("a"))).collect();
+        assert_delay(Duration::from_millis(900), delays[9]);
+        assert!(delays.iter().all(|d| *d <= Duration::from_secs(1)));
+        assert_delay(Duration::from_secs(1), delays[49])
+    }
+
+    #[test]
+    fn global_and_per_destination() {
+        let mut r = RateLimits::new();
+        r.advise(Some(addr("a")), 10, MINUTE);
+        assert_eq!(Duration::ZERO, r.delay(&addr("a")));
+        assert_delay(Duration::from_millis(100), r.delay(&addr("a")));
+        assert_eq!(Duration::ZERO, r.delay(&addr("b")));
+        assert_eq!(Duration::ZERO, r.delay(&addr("b")));
+
+        // The longer of both delays applies.
+        r.advise(None, 2, MINUTE);
+        assert_delay(Duration::from_millis(200), r.delay(&addr("a")));
+        assert_delay(Duration::from_millis(500), r.delay(&addr("b")))
+    }
+
+    #[test]
+    fn zero_rate_lifts_limit() {
+        let mut r = RateLimits::new();
+        r.advise(None, 1, MINUTE);
+        r.advise(Some(addr("a")), 1, MINUTE);
+        r.delay(&addr("a"));
+        r.advise(None, 0, MINUTE);
+        assert_delay(Duration::from_secs(1), r.delay(&addr("a")));
+        assert_eq!(Duration::ZERO, r.delay(&addr("b")));
+        r.advise(Some(addr("a")), 0, MINUTE);
+        assert_eq!(Duration::ZERO, r.delay(&addr("a")))
+    }
+}
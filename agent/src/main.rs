@@ -1,14 +1,24 @@
 use clap::Parser;
-use cluvio_agent::{self, Agent, Config, Options};
+use cluvio_agent::{self, Agent, Config, Options, Status};
+#[cfg(unix)]
+use cluvio_agent::Reloader;
+use cluvio_agent::config::{Log, LogTarget, Rotation};
 use directories::BaseDirs;
-use std::env;
+use protocol::AgentId;
+use rolling_file::{BasicRollingFileAppender, RollingConditionBasic};
+use std::{env, io};
 use std::path::{Path, PathBuf};
-use util::{base64, exit};
+use tokio::runtime::{self, Runtime};
+use tokio_util::sync::CancellationToken;
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use tracing_subscriber::{EnvFilter, Layer, Registry, reload};
+use tracing_subscriber::layer::{Layered, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use util::{base64, exit, service};
 
 const CONFIG_FILE_NAME: &str = "cluvio-agent.toml";
 
-#[tokio::main]
-async fn main() {
+fn main() {
     let opts = Options::parse();
 
     if opts.version {
@@ -16,50 +26,302 @@ async fn main() {
         return
     }
 
-    let subscriber = tracing_subscriber::fmt()
-        .with_env_filter(opts.log.unwrap_or_else(|| "cluvio_agent=info".to_string()))
-        .with_ansi(cfg!(not(windows)));
+    if opts.gen_keypair {
+        print_keypair();
+        return
+    }
 
-    if opts.json {
-        subscriber.json().init();
-    } else {
-        subscriber.init();
+    if opts.dump_config_schema {
+        let schema = cluvio_agent::config_schema();
+        println!("{}", serde_json::to_string_pretty(&schema).unwrap_or_else(exit("schema")));
+        return
     }
 
-    if opts.gen_keypair {
-        print_keypair();
+    let path = opts.config
+        .or_else(find_config)
+        .ok_or_else(|| concat!("see `", env!("CARGO_PKG_NAME"), " --help` for details").to_string())
+        .unwrap_or_else(exit("config file not found"));
+
+    // Until the configured log output is known, messages go to stdout.
+    let log_filter = opts.log.unwrap_or_else(|| "cluvio_agent=info".to_string());
+    let cfg = with_stdout_log(&log_filter, opts.json, || Config::from_path(&path)).unwrap_or_else(exit("config"));
+
+    if opts.print_config {
+        println!("# {}\n{}", path.display(), cfg.to_toml().unwrap_or_else(exit("config")));
         return
     }
 
-    let cfg: Config = {
-        let path = opts.config
-            .or_else(find_config)
-            .ok_or_else(|| concat!("see `", env!("CARGO_PKG_NAME"), " --help` for details").to_string())
-            .unwrap_or_else(exit("config file not found"));
-        log::info!(?path, "configuration");
-        config::Config::builder()
-            .add_source(config::File::from(path))
-            .add_source(config::Environment::with_prefix("CLUVIO_AGENT").separator("_"))
+    let mut logging = cfg.log.clone();
+    logging.target    = opts.log_target.unwrap_or(logging.target);
+    logging.file      = opts.log_file.or(logging.file);
+    logging.rotation  = opts.log_rotation.unwrap_or(logging.rotation);
+    logging.max_size  = opts.log_max_size.or(logging.max_size);
+    logging.max_files = opts.log_max_files.unwrap_or(logging.max_files);
+
+    if logging.file.is_some() && logging.target != LogTarget::Stdout {
+        exit("log file")("a log file requires log target `stdout`")
+    }
+
+    #[cfg(unix)]
+    let pid_file = opts.pid_file.map(absolute).transpose().unwrap_or_else(exit("pid file"));
+
+    // The daemon must be started before the log writer thread, which would
+    // not survive the fork.
+    #[cfg(unix)]
+    let path = if opts.daemon {
+        // The daemon changes its working directory to `/`.
+        let path = absolute(path).unwrap_or_else(exit("config"));
+        logging.file = logging.file.map(absolute).transpose().unwrap_or_else(exit("log file"));
+        daemonize(pid_file.as_deref()).unwrap_or_else(exit("daemon"));
+        path
+    } else {
+        path
+    };
+
+    let (output, log_guard) = log_output(&logging, opts.json).unwrap_or_else(exit("log target"));
+
+    let (filter, filter_handle) = reload::Layer::new(EnvFilter::new(&log_filter));
+    let log_levels = LogLevels::new(log_filter, filter_handle);
+
+    tracing_subscriber::registry().with(filter).with(output).init();
+
+    log::info!(?path, "configuration");
+
+    #[cfg(windows)]
+    if opts.service {
+        return scm::start(cfg, log_levels)
+    }
+
+    let runtime = runtime().unwrap_or_else(exit("runtime"));
+
+    let status = runtime.block_on(async {
+        let service = service::detect();
+        log::debug!(manager = %service.manager(), "service");
+
+        let agent = Agent::builder(cfg)
+            .with_service(service.into())
             .build()
-            .unwrap_or_else(exit("config"))
-            .try_deserialize()
-            .unwrap_or_else(exit("config"))
+            .unwrap_or_else(exit("agent"));
+
+        #[cfg(unix)]
+        tokio::spawn(reload_on_sighup(path, agent.reloader()));
+
+        #[cfg(unix)]
+        tokio::spawn(cycle_log_level_on_sigusr1(log_levels));
+
+        let token = CancellationToken::new();
+        tokio::spawn({
+            let token = token.clone();
+            async move {
+                shutdown_signal().await;
+                log::info!("received shutdown signal");
+                token.cancel()
+            }
+        });
+
+        agent.run_until(token).await
+    });
+
+    #[cfg(unix)]
+    if let Some(p) = pid_file {
+        let _ = std::fs::remove_file(p);
+    }
+
+    // Write pending log messages before exiting.
+    drop(log_guard);
+
+    if let Status::Terminated(reason) = status {
+        exit("agent was terminated by gateway")(reason)
+    }
+}
+
+fn runtime() -> io::Result<Runtime> {
+    runtime::Builder::new_multi_thread().enable_all().build()
+}
+
+/// The subscriber to which the log output layer is added.
+type Filtered = Layered<reload::Layer<EnvFilter, Registry>, Registry>;
+
+/// Create the layer which sends log messages to the configured target.
+///
+/// The JSON format does not apply to journald, which has structured fields.
+fn log_output(log: &Log, json: bool) -> io::Result<(Box<dyn Layer<Filtered> + Send + Sync>, Option<WorkerGuard>)> {
+    match log.target {
+        LogTarget::Stdout => {
+            let (writer, guard) = log_writer(log)?;
+            let format = tracing_subscriber::fmt::layer()
+                .with_ansi(cfg!(not(windows)) && log.file.is_none())
+                .with_writer(writer);
+            let layer = if json { format.json().boxed() } else { format.boxed() };
+            Ok((layer, Some(guard)))
+        }
+        #[cfg(unix)]
+        LogTarget::Syslog => {
+            // The syslog daemon adds timestamps.
+            let format = tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .without_time()
+                .with_writer(syslog::Syslog::connect(env!("CARGO_PKG_NAME"))?);
+            let layer = if json { format.json().boxed() } else { format.boxed() };
+            Ok((layer, None))
+        }
+        #[cfg(not(unix))]
+        LogTarget::Syslog => Err(io::Error::other("syslog is not supported on this platform")),
+        #[cfg(feature = "journald")]
+        LogTarget::Journald => {
+            let layer = tracing_journald::layer()?.with_syslog_identifier(env!("CARGO_PKG_NAME").to_string());
+            Ok((layer.boxed(), None))
+        }
+        #[cfg(not(feature = "journald"))]
+        LogTarget::Journald => Err(io::Error::other("journald requires feature `journald`"))
+    }
+}
+
+/// Create the writer of log messages.
+///
+/// Messages are written by a background thread, which flushes them when the
+/// returned guard is dropped.
+fn log_writer(log: &Log) -> io::Result<(NonBlocking, WorkerGuard)> {
+    let Some(path) = &log.file else {
+        return Ok(tracing_appender::non_blocking(io::stdout()))
     };
+    let mut condition = RollingConditionBasic::new();
+    match log.rotation {
+        Rotation::Never  => {}
+        Rotation::Hourly => condition = condition.hourly(),
+        Rotation::Daily  => condition = condition.daily()
+    }
+    if let Some(n) = log.max_size {
+        condition = condition.max_size(n)
+    }
+    let file = BasicRollingFileAppender::new(path, condition, log.max_files)?;
+    Ok(tracing_appender::non_blocking(file))
+}
+
+/// Run `f` with log messages going to stdout.
+fn with_stdout_log<T, F: FnOnce() -> T>(filter: &str, json: bool, f: F) -> T {
+    let format = tracing_subscriber::fmt()
+        .with_ansi(cfg!(not(windows)))
+        .with_env_filter(EnvFilter::new(filter));
+    if json {
+        log::subscriber::with_default(format.json().finish(), f)
+    } else {
+        log::subscriber::with_default(format.finish(), f)
+    }
+}
 
-    let reason = Agent::new(cfg)
-        .unwrap_or_else(exit("agent"))
-        .go()
-        .await;
+/// Detach from the terminal and continue in the background.
+///
+/// Standard output and error go to `/dev/null`, the log file (if any) is
+/// written by the log writer only. The PID file (if any) is created and
+/// locked.
+#[cfg(unix)]
+fn daemonize(pid_file: Option<&Path>) -> io::Result<()> {
+    let mut daemon = daemonize::Daemonize::new();
+    if let Some(p) = pid_file {
+        daemon = daemon.pid_file(p)
+    }
+    daemon.start().map_err(io::Error::other)
+}
 
-    exit("agent was terminated by gateway")(reason)
+/// Make the given path absolute (without resolving symlinks).
+#[cfg(unix)]
+fn absolute(path: PathBuf) -> io::Result<PathBuf> {
+    Ok(env::current_dir()?.join(path))
+}
+
+/// Re-read the config file whenever SIGHUP is received.
+#[cfg(unix)]
+async fn reload_on_sighup(path: PathBuf, reloader: Reloader) {
+    use tokio::signal::unix::{SignalKind, signal};
+    let mut hup = match signal(SignalKind::hangup()) {
+        Ok(s)  => s,
+        Err(e) => {
+            log::warn!("failed to install SIGHUP handler: {}", e);
+            return
+        }
+    };
+    while hup.recv().await.is_some() {
+        log::info!(?path, "received SIGHUP, reloading configuration");
+        // Loading may read files and run secret manager CLIs.
+        let p = path.clone();
+        match tokio::task::spawn_blocking(move || Config::from_path(&p)).await {
+            Ok(Ok(cfg)) => reloader.reload(cfg),
+            Ok(Err(e))  => log::error!("failed to reload configuration: {}", e),
+            Err(e)      => log::error!("configuration reload task failed: {}", e)
+        }
+    }
+}
+
+/// Cycle the log level whenever SIGUSR1 is received.
+#[cfg(unix)]
+async fn cycle_log_level_on_sigusr1(mut levels: LogLevels) {
+    use tokio::signal::unix::{SignalKind, signal};
+    let mut usr1 = match signal(SignalKind::user_defined1()) {
+        Ok(s)  => s,
+        Err(e) => {
+            log::warn!("failed to install SIGUSR1 handler: {}", e);
+            return
+        }
+    };
+    while usr1.recv().await.is_some() {
+        levels.cycle()
+    }
+}
+
+/// Cycles the log filter through debug, trace and back to the initial filter.
+struct LogLevels {
+    filters: [String; 3],
+    current: usize,
+    handle: reload::Handle<EnvFilter, Registry>
+}
+
+impl LogLevels {
+    fn new(initial: String, handle: reload::Handle<EnvFilter, Registry>) -> Self {
+        let filters = [initial, "cluvio_agent=debug".to_string(), "cluvio_agent=trace".to_string()];
+        LogLevels { filters, current: 0, handle }
+    }
+
+    /// Switch to the next log filter.
+    fn cycle(&mut self) {
+        self.current = (self.current + 1) % self.filters.len();
+        let filter = &self.filters[self.current];
+        match self.handle.reload(EnvFilter::new(filter)) {
+            Ok(()) => log::warn!(%filter, "log filter changed"),
+            Err(e) => log::error!("failed to change log filter: {}", e)
+        }
+    }
+}
+
+/// Wait for Ctrl-C or (on Unix) SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = term.recv() => {}
+            },
+            Err(e) => {
+                log::warn!("failed to install SIGTERM handler: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
 }
 
 /// Print a newly generated keypair to stdout.
 fn print_keypair() {
     let s = sealed_boxes::gen_secret_key();
+    let i = AgentId::from(s.public_key());
     let p = base64::encode(s.public_key().as_bytes());
     let s = base64::encode(s.to_bytes());
-    println!("public-key: {}\nsecret-key: {}", p, s)
+    println!("public-key: {}\nsecret-key: {}\nfingerprint: {:#}", p, s, i)
 }
 
 /// Try to find the config file in certain well-known locations.
@@ -104,3 +366,138 @@ fn find_config() -> Option<PathBuf> {
         None
     }
 }
+
+/// Sending log messages to the local syslog daemon.
+#[cfg(unix)]
+mod syslog {
+    use log::Metadata;
+    use std::io;
+    use tracing_subscriber::fmt::MakeWriter;
+    use util::syslog::{Facility, Message};
+
+    /// Creates a syslog message with facility `daemon` per log event.
+    #[derive(Debug, Clone)]
+    pub struct Syslog(util::syslog::Syslog);
+
+    impl Syslog {
+        /// Connect to the syslog socket.
+        pub fn connect(ident: &str) -> io::Result<Self> {
+            util::syslog::Syslog::connect(ident, Facility::Daemon).map(Syslog)
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Syslog {
+        type Writer = Message<'a>;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.0.message(log::Level::INFO.into())
+        }
+
+        fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+            self.0.message((*meta.level()).into())
+        }
+    }
+}
+
+/// Running as a Windows service.
+///
+/// The service must be registered with the name [`scm::SERVICE_NAME`] and
+/// the `--service` argument, e.g. using `sc.exe create`.
+#[cfg(windows)]
+mod scm {
+    use cluvio_agent::{Agent, Config, Status};
+    use super::LogLevels;
+    use std::ffi::OsString;
+    use std::sync::{Arc, Mutex};
+    use tokio_util::sync::CancellationToken;
+    use util::exit;
+    use util::service::{Scm, Service};
+    use windows_service::define_windows_service;
+    use windows_service::service::ServiceControl;
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::service_dispatcher;
+
+    pub const SERVICE_NAME: &str = "cluvio-agent";
+
+    /// The custom control code to cycle the log level, e.g. `sc.exe control cluvio-agent 128`.
+    const CYCLE_LOG_LEVEL: u32 = 128;
+
+    /// The config and log levels handed over to `service_main`.
+    static STATE: Mutex<Option<(Config, LogLevels)>> = Mutex::new(None);
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    /// Hand control to the service control manager.
+    ///
+    /// Blocks until the service has stopped.
+    pub fn start(cfg: Config, levels: LogLevels) {
+        *STATE.lock().unwrap_or_else(|e| e.into_inner()) = Some((cfg, levels));
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main).unwrap_or_else(exit("service"))
+    }
+
+    fn service_main(_: Vec<OsString>) {
+        let Some((cfg, mut levels)) = STATE.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+            log::error!("service started without configuration");
+            return
+        };
+
+        let token = CancellationToken::new();
+
+        let handler = {
+            let token = token.clone();
+            move |control| match control {
+                ServiceControl::Stop | ServiceControl::Shutdown => {
+                    log::info!(?control, "received service control");
+                    token.cancel();
+                    ServiceControlHandlerResult::NoError
+                }
+                ServiceControl::UserEvent(code) if code.to_raw() == CYCLE_LOG_LEVEL => {
+                    levels.cycle();
+                    ServiceControlHandlerResult::NoError
+                }
+                ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+                _                           => ServiceControlHandlerResult::NotImplemented
+            }
+        };
+
+        let scm = match service_control_handler::register(SERVICE_NAME, handler) {
+            Ok(h)  => Scm::new(h),
+            Err(e) => {
+                log::error!("failed to register service control handler: {}", e);
+                return
+            }
+        };
+
+        // The SCM expects the service to be running shortly after it has been
+        // started, regardless of whether the gateway can be reached.
+        if let Err(e) = scm.ready() {
+            log::warn!("failed to notify service manager: {}", e)
+        }
+
+        let code = match super::runtime() {
+            Ok(rt) => rt.block_on(async {
+                match Agent::builder(cfg).with_service(Arc::new(scm)).build() {
+                    Ok(agent) => match agent.run_until(token).await {
+                        Status::Terminated(reason) => {
+                            log::error!(%reason, "agent was terminated by gateway");
+                            1
+                        }
+                        _ => 0
+                    }
+                    Err(e) => {
+                        log::error!("failed to create agent: {}", e);
+                        1
+                    }
+                }
+            }),
+            Err(e) => {
+                log::error!("failed to create runtime: {}", e);
+                1
+            }
+        };
+
+        if let Err(e) = scm.stopped(code) {
+            log::warn!("failed to notify service manager: {}", e)
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Max. number of destinations to keep statistics for.
+const MAX_DESTINATIONS: usize = 1024;
+
+/// The destination under which streams beyond `MAX_DESTINATIONS` are accounted.
+pub const OTHER_DESTINATIONS: &str = "*";
+
+/// Counters of one or more agents.
+///
+/// Cloning is cheap and all clones refer to the same counters.
+/// See [`crate::Agent::metrics`] and [`crate::Builder::with_metrics`].
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Counters>
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    streams: AtomicU64,
+    active: AtomicU64,
+    denied: AtomicU64,
+    rejected: AtomicU64,
+    failed: AtomicU64,
+    errors: AtomicU64,
+    sent: AtomicU64,
+    received: AtomicU64,
+    reconnects: AtomicU64,
+    destinations: Mutex<HashMap<String, DestinationStats>>
+}
+
+impl Counters {
+    /// Update the statistics of the given destination.
+    fn destination<F: FnOnce(&mut DestinationStats)>(&self, dest: &str, f: F) {
+        let mut map = self.destinations.lock().expect("destinations lock");
+        if let Some(d) = map.get_mut(dest) {
+            return f(d)
+        }
+        let dest = if map.len() >= MAX_DESTINATIONS { OTHER_DESTINATIONS } else { dest };
+        f(map.entry(dest.to_string()).or_default())
+    }
+}
+
+/// Statistics of the streams to one destination address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DestinationStats {
+    /// Number of finished streams.
+    pub streams: u64,
+    /// Number of streams currently transferring data.
+    pub active_streams: u64,
+    /// Bytes sent to the gateway.
+    pub bytes_sent: u64,
+    /// Bytes received from the gateway.
+    pub bytes_received: u64,
+    /// Total duration of data transfers.
+    pub duration: Duration,
+    /// Number of finished streams per cause.
+    pub terminations: BTreeMap<Termination, u64>
+}
+
+/// Why a stream has finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Termination {
+    /// The address was denied by policy.
+    Denied,
+    /// The connection to the address could not be established.
+    ConnectFailed,
+    /// The data transfer completed.
+    Closed,
+    /// The data transfer failed with an I/O error.
+    Error,
+    /// The stream was aborted, e.g. because the agent shut down.
+    Aborted
+}
+
+impl Termination {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Termination::Denied        => "denied",
+            Termination::ConnectFailed => "connect-failed",
+            Termination::Closed        => "closed",
+            Termination::Error         => "error",
+            Termination::Aborted       => "aborted"
+        }
+    }
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The values of [`Metrics`] at some point in time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MetricsSnapshot {
+    /// Number of streams connected to an internal address.
+    pub streams: u64,
+    /// Number of streams currently transferring data.
+    pub active_streams: u64,
+    /// Number of streams rejected because of the address policy.
+    pub denied_streams: u64,
+    /// Number of streams rejected because of `max-concurrent-streams`.
+    pub rejected_streams: u64,
+    /// Number of streams which failed to connect to the internal address.
+    pub failed_streams: u64,
+    /// Number of stream handlers which finished with an error.
+    pub stream_errors: u64,
+    /// Bytes sent to the gateway.
+    pub bytes_sent: u64,
+    /// Bytes received from the gateway.
+    pub bytes_received: u64,
+    /// Number of reconnects to the gateway.
+    pub reconnects: u64
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// Get the current counter values.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let c = &*self.inner;
+        MetricsSnapshot {
+            streams: c.streams.load(Ordering::Relaxed),
+            active_streams: c.active.load(Ordering::Relaxed),
+            denied_streams: c.denied.load(Ordering::Relaxed),
+            rejected_streams: c.rejected.load(Ordering::Relaxed),
+            failed_streams: c.failed.load(Ordering::Relaxed),
+            stream_errors: c.errors.load(Ordering::Relaxed),
+            bytes_sent: c.sent.load(Ordering::Relaxed),
+            bytes_received: c.received.load(Ordering::Relaxed),
+            reconnects: c.reconnects.load(Ordering::Relaxed)
+        }
+    }
+
+    /// Get the statistics per destination address.
+    ///
+    /// Statistics are kept for up to 1024 destinations, streams to further
+    /// destinations are accounted under [`OTHER_DESTINATIONS`].
+    pub fn destinations(&self) -> BTreeMap<String, DestinationStats> {
+        let map = self.inner.destinations.lock().expect("destinations lock");
+        map.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+
+    /// Count a connected stream which is active until the guard is dropped.
+    ///
+    /// Unless [`ActiveStream::finish`] is called, the stream counts as aborted.
+    pub(crate) fn stream_connected(&self, dest: String) -> ActiveStream {
+        self.inner.streams.fetch_add(1, Ordering::Relaxed);
+        self.inner.active.fetch_add(1, Ordering::Relaxed);
+        self.inner.destination(&dest, |d| d.active_streams += 1);
+        ActiveStream { counters: self.inner.clone(), dest, start: Instant::now(), end: None }
+    }
+
+    pub(crate) fn stream_denied(&self, dest: &str) {
+        self.inner.denied.fetch_add(1, Ordering::Relaxed);
+        self.stream_ended(dest, Termination::Denied)
+    }
+
+    pub(crate) fn stream_rejected(&self) {
+        self.inner.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stream_failed(&self, dest: &str) {
+        self.inner.failed.fetch_add(1, Ordering::Relaxed);
+        self.stream_ended(dest, Termination::ConnectFailed)
+    }
+
+    /// Count a stream which finished without being connected.
+    fn stream_ended(&self, dest: &str, t: Termination) {
+        self.inner.destination(dest, |d| {
+            d.streams += 1;
+            *d.terminations.entry(t).or_default() += 1
+        })
+    }
+
+    pub(crate) fn stream_error(&self) {
+        self.inner.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn transferred(&self, sent: u64, received: u64) {
+        self.inner.sent.fetch_add(sent, Ordering::Relaxed);
+        self.inner.received.fetch_add(received, Ordering::Relaxed);
+    }
+
+    pub(crate) fn reconnect(&self) {
+        self.inner.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Decrements the number of active streams when dropped.
+pub(crate) struct ActiveStream {
+    counters: Arc<Counters>,
+    dest: String,
+    start: Instant,
+    end: Option<(u64, u64, Termination)>
+}
+
+impl ActiveStream {
+    /// The stream has finished after sending and receiving the given bytes.
+    pub(crate) fn finish(mut self, sent: u64, received: u64, t: Termination) {
+        self.end = Some((sent, received, t))
+    }
+}
+
+impl Drop for ActiveStream {
+    fn drop(&mut self) {
+        self.counters.active.fetch_sub(1, Ordering::Relaxed);
+        let (sent, received, t) = self.end.unwrap_or((0, 0, Termination::Aborted));
+        let duration = self.start.elapsed();
+        self.counters.destination(&self.dest, |d| {
+            d.streams += 1;
+            d.active_streams -= 1;
+            d.bytes_sent += sent;
+            d.bytes_received += received;
+            d.duration += duration;
+            *d.terminations.entry(t).or_default() += 1
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use super::{MAX_DESTINATIONS, Metrics, OTHER_DESTINATIONS, Termination};
+
+    #[test]
+    fn active_streams() {
+        let m = Metrics::new();
+        let a = m.stream_connected("db:5432".to_string());
+        let b = m.clone().stream_connected("db:5432".to_string());
+        m.transferred(3, 4);
+        assert_eq!(2, m.snapshot().active_streams);
+        drop((a, b));
+        let s = m.snapshot();
+        assert_eq!((2, 0), (s.streams, s.active_streams));
+        assert_eq!((3, 4), (s.bytes_sent, s.bytes_received))
+    }
+
+    #[test]
+    fn destinations() {
+        let m = Metrics::new();
+        let a = m.stream_connected("db:5432".to_string());
+        let b = m.stream_connected("db:5432".to_string());
+        m.stream_denied("mail:25");
+        m.stream_failed("db:5432");
+        assert_eq!(2, m.destinations()["db:5432"].active_streams);
+        a.finish(10, 20, Termination::Closed);
+        drop(b);
+        let d = m.destinations();
+        let db = &d["db:5432"];
+        assert_eq!((3, 0), (db.streams, db.active_streams));
+        assert_eq!((10, 20), (db.bytes_sent, db.bytes_received));
+        let causes = [(Termination::ConnectFailed, 1), (Termination::Closed, 1), (Termination::Aborted, 1)];
+        assert_eq!(BTreeMap::from(causes), db.terminations);
+        assert_eq!(Some(&1), d["mail:25"].terminations.get(&Termination::Denied))
+    }
+
+    #[test]
+    fn max_destinations() {
+        let m = Metrics::new();
+        for i in 0 ..= MAX_DESTINATIONS {
+            m.stream_denied(&format!("host-{i}:80"))
+        }
+        let d = m.destinations();
+        assert_eq!(MAX_DESTINATIONS + 1, d.len());
+        assert_eq!(1, d[OTHER_DESTINATIONS].streams)
+    }
+}
@@ -0,0 +1,100 @@
+use crate::config::{Network, Rule};
+use futures::future::{self, BoxFuture};
+use protocol::{Address, Scheme};
+use util::NonEmpty;
+
+/// Decides which addresses the agent may connect to.
+///
+/// Addresses are normalized before they are passed to the policy, i.e.
+/// domain names are in lowercase ASCII form without trailing dot.
+pub trait AddressPolicy: Send + Sync + 'static {
+    /// Check if a connection to the given address and scheme is allowed.
+    fn allows<'a>(&'a self, addr: &'a Address<'_>, scheme: Scheme) -> BoxFuture<'a, bool>;
+}
+
+/// The default policy based on the `allowed-addresses` of the config file.
+#[derive(Debug, Clone)]
+pub struct Allowlist {
+    rules: NonEmpty<Rule>
+}
+
+impl Allowlist {
+    pub fn new(rules: NonEmpty<Rule>) -> Self {
+        Allowlist { rules }
+    }
+
+    /// Check if any rule applies to the given address and scheme.
+    pub fn contains(&self, addr: &Address<'_>, scheme: Scheme) -> bool {
+        applies(&self.rules, addr, scheme)
+    }
+}
+
+impl AddressPolicy for Allowlist {
+    fn allows<'a>(&'a self, addr: &'a Address<'_>, scheme: Scheme) -> BoxFuture<'a, bool> {
+        Box::pin(future::ready(self.contains(addr, scheme)))
+    }
+}
+
+/// Addresses which must never be connected to.
+///
+/// The denylist is checked before any [`AddressPolicy`]. It is based on the
+/// `denied-addresses` of the config file. Rules match addresses as given,
+/// i.e. a denied domain name does not deny its IP addresses.
+#[derive(Debug, Clone, Default)]
+pub struct Denylist {
+    rules: Vec<Rule>
+}
+
+impl Denylist {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Denylist { rules }
+    }
+
+    /// Check if any rule applies to the given address and scheme.
+    pub fn contains(&self, addr: &Address<'_>, scheme: Scheme) -> bool {
+        applies(&self.rules, addr, scheme)
+    }
+}
+
+/// Check if any of the rules applies to the given address and scheme.
+pub(crate) fn applies(rules: &[Rule], addr: &Address<'_>, scheme: Scheme) -> bool {
+    rules.iter().any(|rule| rule_applies(rule, addr, scheme))
+}
+
+/// Check if the rule applies to the given address and scheme at the current time.
+pub(crate) fn rule_applies(rule: &Rule, addr: &Address<'_>, scheme: Scheme) -> bool {
+    rule.allows_scheme(scheme) && matches(&rule.network, addr) && rule.is_active()
+}
+
+/// Get a setting of the first rule which applies to the given address and scheme and has it.
+pub(crate) fn setting<'r, T, F>(rules: &'r [Rule], addr: &Address<'_>, scheme: Scheme, f: F) -> Option<T>
+where
+    F: Fn(&'r Rule) -> Option<T>
+{
+    rules.iter()
+        .filter(|rule| rule_applies(rule, addr, scheme))
+        .find_map(f)
+}
+
+/// Check if the given address is part of the network.
+fn matches(net: &Network, addr: &Address<'_>) -> bool {
+    match addr {
+        Address::Addr(addr) => {
+            match net {
+                Network::Ip(net) => net.contains(&addr.ip()),
+                Network::IpExcept(net, except) => {
+                    net.contains(&addr.ip()) && !except.iter().any(|x| x.contains(&addr.ip()))
+                }
+                Network::Dns(_) | Network::Pat(_) => false
+            }
+        }
+        Address::Name(addr, _) => {
+            match net {
+                Network::Ip(_) | Network::IpExcept(..) => false,
+                Network::Dns(n) => n.as_str() == addr,
+                Network::Pat(p) => p.matches(addr)
+            }
+        }
+        Address::Path(_) => false
+    }
+}
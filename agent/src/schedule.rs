@@ -0,0 +1,253 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de;
+use serde::ser::SerializeSeq;
+use std::fmt;
+use std::str::FromStr;
+use util::time::UnixTime;
+
+pub(crate) const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// A weekly time window during which a rule applies.
+///
+/// Days and hours are in UTC. A window ending before it starts extends
+/// past midnight, e.g. `22:00-06:00` on `fri` covers Saturday morning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Schedule {
+    days: Days,
+    hours: Option<Hours>
+}
+
+/// Set of weekdays (bit 0 = Monday).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Days(u8);
+
+/// A time span within a day, in minutes since midnight.
+///
+/// Syntax: `<hh:mm>-<hh:mm>`, e.g. `08:00-20:00`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hours {
+    start: u16,
+    end: u16
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Schedule::default()
+    }
+
+    pub fn with_days(mut self, d: Days) -> Self {
+        self.days = d;
+        self
+    }
+
+    pub fn with_hours(mut self, h: Hours) -> Self {
+        self.hours = Some(h);
+        self
+    }
+
+    /// Create a schedule from lists of days (or day ranges) and hours.
+    pub(crate) fn parse(days: &[String], hours: Option<&str>) -> Result<Self, String> {
+        let mut schedule = Schedule::new();
+        if !days.is_empty() {
+            schedule = schedule.with_days(days.iter().try_fold(Days(0), |d, s| d.parse_into(s))?)
+        }
+        if let Some(h) = hours {
+            schedule = schedule.with_hours(h.parse()?)
+        }
+        Ok(schedule)
+    }
+
+    /// Check if the schedule includes the current time.
+    pub fn is_active(&self) -> bool {
+        UnixTime::now().map(|t| self.contains(t)).unwrap_or(false)
+    }
+
+    /// Check if the schedule includes the given time.
+    pub fn contains(&self, t: UnixTime) -> bool {
+        let days   = t.seconds() / 86400;
+        let minute = (t.seconds() % 86400 / 60) as u16;
+        // 1970-01-01 was a Thursday.
+        let today = ((days + 3) % 7) as u8;
+        let Some(h) = self.hours else {
+            return self.days.contains(today)
+        };
+        if h.start < h.end {
+            self.days.contains(today) && (h.start .. h.end).contains(&minute)
+        } else if minute >= h.start {
+            self.days.contains(today)
+        } else {
+            minute < h.end && self.days.contains((today + 6) % 7)
+        }
+    }
+}
+
+impl Days {
+    pub fn all() -> Self {
+        Days(0x7f)
+    }
+
+    fn contains(self, day: u8) -> bool {
+        self.0 & (1 << day) != 0
+    }
+
+    /// Parse a day name (`mon`, ..., `sun`) or an inclusive range like `mon-fri`.
+    fn parse_into(self, s: &str) -> Result<Self, String> {
+        let day = |s: &str| {
+            DAY_NAMES.iter()
+                .position(|d| d.eq_ignore_ascii_case(s.trim()))
+                .ok_or_else(|| format!("invalid day {s:?}, expected one of {}", DAY_NAMES.join(", ")))
+        };
+        let (a, b) = match s.split_once('-') {
+            Some((a, b)) => (day(a)?, day(b)?),
+            None         => { let a = day(s)?; (a, a) }
+        };
+        let mut bits = self.0;
+        let mut d = a;
+        loop {
+            bits |= 1 << d;
+            if d == b {
+                break
+            }
+            d = (d + 1) % 7
+        }
+        Ok(Days(bits))
+    }
+}
+
+impl Default for Days {
+    fn default() -> Self {
+        Days::all()
+    }
+}
+
+impl FromStr for Days {
+    type Err = String;
+
+    /// Parse a comma-separated list of days or day ranges, e.g. `mon-fri,sun`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',').try_fold(Days(0), |d, s| d.parse_into(s))
+    }
+}
+
+impl FromStr for Hours {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let time = |s: &str| {
+            let (h, m) = s.trim().split_once(':').ok_or_else(|| format!("invalid time {s:?}, expected hh:mm"))?;
+            match (h.parse::<u16>(), m.parse::<u16>()) {
+                (Ok(h), Ok(m)) if h < 24 && m < 60 => Ok(h * 60 + m),
+                (Ok(24), Ok(0))                    => Ok(24 * 60),
+                _                                  => Err(format!("invalid time {s:?}, expected hh:mm"))
+            }
+        };
+        let (a, b) = s.split_once('-').ok_or_else(|| format!("invalid hours {s:?}, expected hh:mm-hh:mm"))?;
+        let (start, end) = (time(a)?, time(b)?);
+        if start == end || start == 24 * 60 {
+            return Err(format!("invalid hours {s:?}, empty time span"))
+        }
+        Ok(Hours { start, end })
+    }
+}
+
+impl fmt::Display for Hours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}-{:02}:{:02}", self.start / 60, self.start % 60, self.end / 60, self.end % 60)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScheduleSpec {
+    #[serde(default)]
+    days: Vec<String>,
+    hours: Option<String>
+}
+
+impl<'de> Deserialize<'de> for Schedule {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let spec = ScheduleSpec::deserialize(d)?;
+        Schedule::parse(&spec.days, spec.hours.as_deref()).map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Schedule {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Spec<'a> {
+            #[serde(skip_serializing_if = "Option::is_none")]
+            days: Option<DayNames>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            hours: Option<&'a Hours>
+        }
+        let days = (self.days != Days::all()).then_some(DayNames(self.days));
+        Spec { days, hours: self.hours.as_ref() }.serialize(s)
+    }
+}
+
+impl Serialize for Hours {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+/// Serializes [`Days`] as a list of day names.
+struct DayNames(Days);
+
+impl Serialize for DayNames {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut seq = s.serialize_seq(None)?;
+        for (i, name) in DAY_NAMES.iter().enumerate() {
+            if self.0.contains(i as u8) {
+                seq.serialize_element(name)?
+            }
+        }
+        seq.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Days, Hours, Schedule};
+    use std::time::Duration;
+    use util::time::UnixTime;
+
+    /// Monday, 2024-01-01 00:00 UTC.
+    const MONDAY: u64 = 1704067200;
+
+    fn at(day: u64, h: u64, m: u64) -> UnixTime {
+        UnixTime::from(Duration::from_secs(MONDAY + day * 86400 + h * 3600 + m * 60))
+    }
+
+    #[test]
+    fn business_hours() {
+        let s = Schedule::new()
+            .with_days("mon-fri".parse().unwrap())
+            .with_hours("08:00-20:00".parse().unwrap());
+        assert!(s.contains(at(0, 8, 0)));
+        assert!(s.contains(at(4, 19, 59)));
+        assert!(!s.contains(at(0, 7, 59)));
+        assert!(!s.contains(at(2, 20, 0)));
+        assert!(!s.contains(at(5, 12, 0)))
+    }
+
+    #[test]
+    fn overnight() {
+        let s = Schedule::new()
+            .with_days("fri".parse().unwrap())
+            .with_hours("22:00-06:00".parse().unwrap());
+        assert!(s.contains(at(4, 23, 0)));
+        assert!(s.contains(at(5, 5, 59)));
+        assert!(!s.contains(at(4, 5, 0)));
+        assert!(!s.contains(at(5, 22, 0)))
+    }
+
+    #[test]
+    fn syntax() {
+        assert_eq!("fri-mon".parse::<Days>().unwrap(), "fri,sat,sun,mon".parse().unwrap());
+        assert_eq!("08:00-24:00", "8:00-24:00".parse::<Hours>().unwrap().to_string());
+        assert!("tues".parse::<Days>().is_err());
+        assert!("08:00-08:00".parse::<Hours>().is_err());
+        assert!("08:00-25:00".parse::<Hours>().is_err())
+    }
+}
@@ -0,0 +1,287 @@
+//! JSON Schema of the config file.
+
+use serde_json::{Value, json};
+
+/// The JSON Schema (draft 2020-12) of the config file.
+///
+/// Config files in TOML or YAML format can be validated against it after
+/// conversion to JSON. Secret manager references and the environment
+/// overrides of [`crate::Config::from_path`] are not reflected.
+pub fn config_schema() -> Value {
+    let duration = json!({
+        "type": "string",
+        "description": "Duration like `30s`, `5min` or `1h 30m`."
+    });
+    let day = day_pattern();
+    let ip = json!({ "type": "string", "anyOf": [{ "format": "ipv4" }, { "format": "ipv6" }] });
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "cluvio-agent config",
+        "type": "object",
+        "additionalProperties": false,
+        "oneOf": [
+            { "required": ["secret-key"] },
+            { "required": ["secret-key-file"] }
+        ],
+        "required": ["server"],
+        "properties": {
+            "secret-key": {
+                "type": "string",
+                "description": "The base64-encoded private key of this agent or a secret manager reference."
+            },
+            "secret-key-file": {
+                "type": "string",
+                "description": "File containing the base64-encoded private key."
+            },
+            "connect-timeout": duration_with("The timeout of connects.", "30s", &duration),
+            "ping-frequency": duration_with("How often to check if the server is still there.", "60s", &duration),
+            "reconnect-initial-delay": duration_with("The delay before the first reconnect attempt.", "2s", &duration),
+            "reconnect-max-delay": duration_with("The max. delay between reconnect attempts.", "64s", &duration),
+            "reconnect-multiplier": {
+                "type": "integer",
+                "minimum": 1,
+                "default": 2,
+                "description": "The factor by which the reconnect delay grows with each failed attempt."
+            },
+            "bind-address": with_description(&ip, "Local IP address to connect to internal addresses from."),
+            "keepalive-time": duration_with("Idle time before TCP keepalive probes are sent (zero disables keepalive).", "30s", &duration),
+            "keepalive-interval": duration_with("The interval between TCP keepalive probes.", "10s", &duration),
+            "keepalive-retries": {
+                "type": "integer",
+                "minimum": 0,
+                "default": 3,
+                "description": "The number of unanswered TCP keepalive probes before a connection is considered dead."
+            },
+            "heartbeat-interval": duration_with("How often to log the connection status and ping round-trip time (zero disables the heartbeat).", "0s", &duration),
+            "summary-interval": duration_with("How often to log the stream statistics per destination (zero disables the summary).", "1h", &duration),
+            "max-concurrent-streams": {
+                "type": "integer",
+                "minimum": 1,
+                "description": "The max. number of concurrent data streams."
+            },
+            "max-bandwidth": {
+                "type": "string",
+                "description": "The max. data rate of all streams, e.g. `50MB/s`."
+            },
+            "allowed-addresses": {
+                "type": "array",
+                "minItems": 1,
+                "items": { "$ref": "#/$defs/rule" },
+                "description": "List of allowed domains or IPv4/IPv6 networks."
+            },
+            "strict-ip-check": {
+                "type": "boolean",
+                "default": false,
+                "description": "Check every IP address a DNS name resolves to against the IP entries of `allowed-addresses`."
+            },
+            "denied-addresses": {
+                "type": "array",
+                "items": { "$ref": "#/$defs/rule" },
+                "description": "List of denied domains or IPv4/IPv6 networks, checked before `allowed-addresses`."
+            },
+            "hosts": {
+                "type": "object",
+                "additionalProperties": { "type": "string", "description": "Socket address like `10.1.2.3:5432`." },
+                "description": "Static host name overrides, consulted before DNS resolution."
+            },
+            "server": {
+                "type": "object",
+                "additionalProperties": false,
+                "anyOf": [
+                    { "required": ["host"] },
+                    { "required": ["location"] }
+                ],
+                "properties": {
+                    "host": { "type": "string", "description": "The hostname of the gateway." },
+                    "location": {
+                        "type": "string",
+                        "pattern": "^[a-zA-Z0-9-]+$",
+                        "description": "The gateway location, e.g. `eu` or `us`."
+                    },
+                    "port": { "type": "integer", "minimum": 1, "maximum": 65535, "default": 443 },
+                    "trust": { "type": "string", "description": "PEM-encoded certificates to add as trusted." },
+                    "bind-address": with_description(&ip, "Local IP address to connect to the gateway from.")
+                }
+            },
+            "audit": {
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {
+                    "file": { "type": "string", "description": "Append audit events as JSON lines to this file." },
+                    "syslog": { "type": "boolean", "default": false, "description": "Send audit events to the local syslog daemon." },
+                    "webhook": { "type": "string", "format": "uri", "description": "Post audit events to this URL." }
+                }
+            },
+            "health": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["listen"],
+                "properties": {
+                    "listen": {
+                        "type": "string",
+                        "description": "Socket address to serve `/healthz`, `/readyz` and `/status` on, e.g. `127.0.0.1:9000`."
+                    }
+                }
+            },
+            "log": {
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {
+                    "target": {
+                        "enum": ["stdout", "syslog", "journald"],
+                        "default": "stdout",
+                        "description": "Where to send log messages (`file` requires `stdout`)."
+                    },
+                    "file": { "type": "string", "description": "Append log messages to this file instead of printing them to stdout." },
+                    "rotation": {
+                        "enum": ["never", "hourly", "daily"],
+                        "default": "never",
+                        "description": "When to start a new log file."
+                    },
+                    "max-size": {
+                        "type": ["string", "integer"],
+                        "description": "Start a new log file when the current one exceeds this size, e.g. `10MB`."
+                    },
+                    "max-files": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "default": 5,
+                        "description": "The number of rotated log files to keep."
+                    }
+                }
+            }
+        },
+        "$defs": {
+            "rule": {
+                "oneOf": [
+                    { "$ref": "#/$defs/address" },
+                    {
+                        "type": "object",
+                        "additionalProperties": false,
+                        "required": ["address"],
+                        "properties": {
+                            "address": { "$ref": "#/$defs/address" },
+                            "days": {
+                                "type": "array",
+                                "items": {
+                                    "type": "string",
+                                    "pattern": format!("^\\s*{day}\\s*(-\\s*{day}\\s*)?$")
+                                },
+                                "description": "Days (UTC) like `mon` or ranges like `mon-fri`."
+                            },
+                            "hours": {
+                                "type": "string",
+                                "pattern": "^\\d{1,2}:\\d{2}-\\d{1,2}:\\d{2}$",
+                                "description": "Time span (UTC) like `08:00-20:00`."
+                            },
+                            "half-close": {
+                                "type": "boolean",
+                                "description": "Use (or do not use) half-close regardless of the gateway's request."
+                            },
+                            "connect-timeout": with_description(&duration, "The timeout of connects to matching addresses."),
+                            "max-bandwidth": {
+                                "type": "string",
+                                "description": "The max. data rate of all streams to matching addresses, e.g. `10MB/s`."
+                            },
+                            "global-bandwidth": {
+                                "type": "boolean",
+                                "default": true,
+                                "description": "Whether the global `max-bandwidth` applies to matching addresses."
+                            }
+                        }
+                    }
+                ]
+            },
+            "address": {
+                "type": "string",
+                "description": "`[<scheme>://]<network>` where scheme is `tcp`, `tls` or `udp` and network is an IP network (optionally minus subnets), a DNS name or a DNS name pattern."
+            }
+        }
+    })
+}
+
+/// Alternatives of the day names, matched case-insensitively like the parser does.
+///
+/// JSON Schema patterns have no inline flags, hence each letter is a class.
+fn day_pattern() -> String {
+    let names: Vec<String> = crate::schedule::DAY_NAMES.iter()
+        .map(|d| d.chars().map(|c| format!("[{}{}]", c, c.to_ascii_uppercase())).collect())
+        .collect();
+    format!("({})", names.join("|"))
+}
+
+fn duration_with(description: &str, default: &str, duration: &Value) -> Value {
+    let mut v = with_description(duration, description);
+    v["default"] = Value::from(default);
+    v
+}
+
+fn with_description(v: &Value, description: &str) -> Value {
+    let mut v = v.clone();
+    v["description"] = Value::from(description);
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Config;
+    use serde_json::Value;
+    use super::{config_schema, day_pattern};
+    use util::HostName;
+
+    #[test]
+    fn all_fields_described() {
+        let host = HostName::try_from("gateway.example.com").unwrap();
+        let mut config = Config::new(sealed_boxes::gen_secret_key(), host, 443);
+        config.server_mut().bind_address = Some([127, 0, 0, 1].into());
+        config.health = Some(crate::config::Health { listen: ([127, 0, 0, 1], 9000).into() });
+        config.log.file = Some("agent.log".into());
+        config.log.max_size = Some(10 << 20);
+        let config = serde_json::to_value(&config).unwrap();
+        let schema = config_schema();
+        for (key, value) in config.as_object().unwrap() {
+            let props = &schema["properties"][key];
+            assert!(props.is_object(), "{key} is missing");
+            if let Some(fields) = value.as_object() {
+                for field in fields.keys() {
+                    assert!(props["properties"][field].is_object(), "{key}.{field} is missing")
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn day_pattern_ignores_case() {
+        let p = day_pattern();
+        assert!(p.starts_with("([mM][oO][nN]|[tT][uU][eE]|"));
+        assert!(p.ends_with("|[sS][uU][nN])"))
+    }
+
+    #[test]
+    fn defaults_match_config() {
+        let host = HostName::try_from("gateway.example.com").unwrap();
+        let config = Config::new(sealed_boxes::gen_secret_key(), host, 443);
+        let config = serde_json::to_value(&config).unwrap();
+        check_defaults("", &config_schema()["properties"], &config)
+    }
+
+    fn check_defaults(prefix: &str, props: &Value, config: &Value) {
+        for (key, prop) in props.as_object().unwrap() {
+            let path = format!("{prefix}{key}");
+            if let Some(default) = prop.get("default") {
+                let value = &config[key];
+                assert!(!value.is_null(), "{path} is missing");
+                if let (Some(d), Some(v)) = (default.as_str(), value.as_str()) {
+                    if let (Ok(d), Ok(v)) = (humantime::parse_duration(d), humantime::parse_duration(v)) {
+                        assert_eq!(d, v, "{path}");
+                        continue
+                    }
+                }
+                assert_eq!(default, value, "{path}")
+            }
+            if let Some(props) = prop.get("properties") {
+                check_defaults(&format!("{path}."), props, &config[key])
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Secret manager references in place of secret values.
+//!
+//! Supported references:
+//!
+//! - `aws-sm://<name>`: AWS Secrets Manager, resolved with the `aws` CLI.
+//! - `gcp-sm://<project>/<name>[/<version>]`: Google Cloud Secret Manager,
+//!   resolved with the `gcloud` CLI (default version is `latest`).
+//!
+//! Both CLIs use their standard credential chains, e.g. instance roles or
+//! workload identities. Resolution requires feature `cloud-secrets` and the
+//! respective CLI on `PATH`. Neither is part of the Docker image.
+//!
+//! The CLIs are run synchronously, so config loading must not happen on an
+//! async runtime thread (see `reload_on_sighup` in `main.rs`).
+
+use crate::error::Error;
+
+/// Resolve the given value if it is a secret manager reference.
+///
+/// Returns `None` if the value is not a reference.
+pub fn resolve(value: &str) -> Result<Option<String>, Error> {
+    let Some((scheme, path)) = value.split_once("://") else {
+        return Ok(None)
+    };
+    match scheme {
+        "aws-sm" => aws(path).map(Some),
+        "gcp-sm" => gcp(path).map(Some),
+        _        => Ok(None)
+    }
+}
+
+#[cfg(feature = "cloud-secrets")]
+fn aws(name: &str) -> Result<String, Error> {
+    run("aws", &[
+        "secretsmanager", "get-secret-value",
+        "--secret-id", name,
+        "--query", "SecretString",
+        "--output", "text"
+    ])
+}
+
+#[cfg(feature = "cloud-secrets")]
+fn gcp(path: &str) -> Result<String, Error> {
+    let mut parts = path.splitn(3, '/');
+    let (Some(project), Some(name)) = (parts.next(), parts.next()) else {
+        return Err(Error::SecretManager(format!("invalid reference gcp-sm://{path}, expected gcp-sm://<project>/<name>")))
+    };
+    let version = parts.next().unwrap_or("latest");
+    run("gcloud", &[
+        "secrets", "versions", "access", version,
+        &format!("--secret={name}"),
+        &format!("--project={project}")
+    ])
+}
+
+#[cfg(not(feature = "cloud-secrets"))]
+fn aws(_: &str) -> Result<String, Error> {
+    Err(Error::SecretManager("aws-sm references require feature `cloud-secrets`".to_string()))
+}
+
+#[cfg(not(feature = "cloud-secrets"))]
+fn gcp(_: &str) -> Result<String, Error> {
+    Err(Error::SecretManager("gcp-sm references require feature `cloud-secrets`".to_string()))
+}
+
+/// Run a CLI and return its trimmed standard output.
+#[cfg(feature = "cloud-secrets")]
+fn run(program: &str, args: &[&str]) -> Result<String, Error> {
+    log::debug!(%program, "resolving secret");
+    let output = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| Error::SecretManager(format!("failed to run {program}: {e}")))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::SecretManager(format!("{program} failed: {}", stderr.trim())))
+    }
+    let secret = String::from_utf8(output.stdout)
+        .map_err(|_| Error::SecretManager(format!("{program} returned a non-utf8 secret")))?;
+    Ok(secret.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::resolve;
+
+    #[test]
+    fn plain_values() {
+        assert!(resolve("b0xJ3qTf1xWZ6UsM8h6h1Yc2n+D5c7vJgRkVqfRcy2M=").unwrap().is_none());
+        assert!(resolve("vault://secret").unwrap().is_none())
+    }
+
+    #[cfg(not(feature = "cloud-secrets"))]
+    #[test]
+    fn feature_required() {
+        assert!(resolve("aws-sm://agent-key").is_err());
+        assert!(resolve("gcp-sm://project/agent-key").is_err())
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{Agent, Builder, Config, Error, Handle, Metrics};
+use futures::future;
+use protocol::{AgentId, Reason};
+use std::sync::Arc;
+use util::dns::{self, Resolver};
+
+/// Multiple agents with different identities running in one process.
+///
+/// All agents share the same resolver and metrics. Each agent runs in its own task
+/// and its log output is recorded within an `agent` span carrying its ID.
+pub struct AgentSet {
+    resolver: Arc<dyn Resolver>,
+    metrics: Metrics,
+    agents: Vec<Agent>
+}
+
+impl Default for AgentSet {
+    fn default() -> Self {
+        AgentSet::new()
+    }
+}
+
+impl AgentSet {
+    pub fn new() -> Self {
+        AgentSet { resolver: Arc::new(dns::System), metrics: Metrics::new(), agents: Vec::new() }
+    }
+
+    /// Set the resolver shared by all agents added afterwards.
+    pub fn with_resolver(mut self, r: Arc<dyn Resolver>) -> Self {
+        self.resolver = r;
+        self
+    }
+
+    /// The metrics of all agents.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    /// Add an agent for the given config.
+    ///
+    /// Must be called within a tokio runtime.
+    pub fn add(&mut self, cfg: Config) -> Result<&AgentId, Error> {
+        self.add_builder(Builder::new(cfg))
+    }
+
+    /// Add an agent from a builder.
+    ///
+    /// The resolver and metrics of the builder are replaced by the shared ones.
+    /// Must be called within a tokio runtime.
+    pub fn add_builder(&mut self, b: Builder) -> Result<&AgentId, Error> {
+        let agent = b.with_resolver(self.resolver.clone())
+            .with_metrics(self.metrics.clone())
+            .build()?;
+        if self.agents.iter().any(|a| a.id() == agent.id()) {
+            return Err(Error::DuplicateAgent(agent.id().clone()))
+        }
+        self.agents.push(agent);
+        Ok(self.agents[self.agents.len() - 1].id())
+    }
+
+    pub fn len(&self) -> usize {
+        self.agents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.agents.is_empty()
+    }
+
+    pub fn agents(&self) -> impl Iterator<Item = &Agent> {
+        self.agents.iter()
+    }
+
+    /// Run every agent in a new task.
+    pub fn spawn(self) -> SetHandle {
+        let handles = self.agents.into_iter()
+            .map(|a| {
+                let span = log::info_span!("agent", id = %a.id());
+                a.spawn_in(span)
+            })
+            .collect();
+        SetHandle { handles }
+    }
+
+    /// Run all agents until each one has been terminated by the gateway.
+    pub async fn run(self) -> Vec<(AgentId, Option<Reason>)> {
+        self.spawn().join().await
+    }
+}
+
+/// Handles to the agents of an [`AgentSet`].
+///
+/// Dropping the handle does not stop the agents.
+pub struct SetHandle {
+    handles: Vec<Handle>
+}
+
+impl SetHandle {
+    pub fn handles(&self) -> &[Handle] {
+        &self.handles
+    }
+
+    /// Stop all agents and wait for them to finish.
+    ///
+    /// Returns the termination reason per agent (see [`Handle::shutdown`]).
+    pub async fn shutdown(self) -> Vec<(AgentId, Option<Reason>)> {
+        future::join_all(self.handles.into_iter().map(|h| async move {
+            let id = h.id().clone();
+            (id, h.shutdown().await)
+        }))
+        .await
+    }
+
+    /// Wait for all agents to finish.
+    pub async fn join(self) -> Vec<(AgentId, Option<Reason>)> {
+        future::join_all(self.handles.into_iter().map(|h| async move {
+            let id = h.id().clone();
+            (id, h.join().await)
+        }))
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Config, Error};
+    use super::AgentSet;
+    use util::HostName;
+
+    #[tokio::test]
+    async fn duplicate_agents() {
+        let sk   = sealed_boxes::gen_secret_key();
+        let host = HostName::try_from("localhost").unwrap();
+        let mut set = AgentSet::new();
+        set.add(Config::new(sk.clone(), host.clone(), 443)).unwrap();
+        set.add(Config::new(sealed_boxes::gen_secret_key(), host.clone(), 443)).unwrap();
+        assert!(matches!(set.add(Config::new(sk, host, 443)), Err(Error::DuplicateAgent(_))));
+        assert_eq!(2, set.len())
+    }
+}
@@ -1,17 +1,32 @@
-use crate::{Error, Reader, Writer};
-use crate::address::CheckedAddr;
-use crate::config::{Config, Network};
+use crate::{Error, IO_TIMEOUT, Reader, Writer};
+use crate::address::{CheckedAddr, Rejected};
+use crate::bandwidth::{self, Bandwidth};
+use crate::config::Config;
+use crate::event::Event;
+use crate::hook::{StreamHook, StreamStats};
+use crate::limit::RateLimits;
+use crate::metrics::{Metrics, Termination};
+use crate::policy::{self, AddressPolicy, Denylist};
 use either::Either;
-use protocol::{Address, ErrorCode, Id, Message, Connect};
+use protocol::{Address, ConnectOptions, ConnectV2, ErrorCode, Id, Message, Scheme};
 use socket2::{Socket, TcpKeepalive};
-use std::net::SocketAddr;
-use std::sync::Arc;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{self, Poll, ready};
 use std::time::{Duration, Instant};
-use tokio::net::{self, TcpStream};
-use tokio::io::{self, AsyncWriteExt};
-use tokio::time::timeout;
+use tokio::net::TcpStream;
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+use tokio::sync::broadcast;
+use tokio::time::{sleep, timeout};
 use tokio_util::compat::{FuturesAsyncReadCompatExt, FuturesAsyncWriteCompatExt};
-use util::io::{send, recv};
+use util::audit::{AuditEvent, AuditKind, AuditSink};
+use util::dns::Resolver;
+use util::io::{recv_timeout, send_timeout, tcp_connect};
+
+/// Buffer size of rate limited copies.
+const COPY_BUFFER_SIZE: usize = 16 * 1024;
 
 /// Data sent and received.
 struct SendRecv {
@@ -19,18 +34,78 @@ struct SendRecv {
     recv: Option<io::Result<u64>>
 }
 
+impl SendRecv {
+    fn sent_bytes(&self) -> u64 {
+        self.sent.as_ref().and_then(|r| r.as_ref().ok().copied()).unwrap_or(0)
+    }
+
+    fn recv_bytes(&self) -> u64 {
+        self.recv.as_ref().and_then(|r| r.as_ref().ok().copied()).unwrap_or(0)
+    }
+}
+
+/// State shared by all stream handlers.
+pub struct Context {
+    pub config: RwLock<Arc<Config>>,
+    pub limits: Mutex<RateLimits>,
+    pub bandwidth: RwLock<bandwidth::Limits>,
+    pub resolver: Arc<dyn Resolver>,
+    pub policy: RwLock<Arc<dyn AddressPolicy>>,
+    pub denylist: RwLock<Arc<Denylist>>,
+    pub audit: Arc<dyn AuditSink>,
+    pub hooks: Vec<Arc<dyn StreamHook>>,
+    pub metrics: Metrics,
+    pub events: broadcast::Sender<Event>
+}
+
+impl Context {
+    /// The current config.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().expect("config lock").clone()
+    }
+
+    /// The bandwidth limits which apply to streams to the given address.
+    pub fn bandwidth(&self, addr: &CheckedAddr<'_>) -> Vec<Arc<Bandwidth>> {
+        // Read config and limits under the same lock as both are replaced together.
+        let limits = self.bandwidth.read().expect("bandwidth lock");
+        limits.select(&self.config(), addr.addr(), addr.scheme())
+    }
+
+    /// The current address policy.
+    pub fn policy(&self) -> Arc<dyn AddressPolicy> {
+        self.policy.read().expect("policy lock").clone()
+    }
+
+    /// The current denylist.
+    pub fn denylist(&self) -> Arc<Denylist> {
+        self.denylist.read().expect("denylist lock").clone()
+    }
+}
+
 /// Handles a single Yamux stream.
-pub async fn streamer(config: Arc<Config>, stream: yamux::Stream) -> Result<(), Error> {
+pub async fn streamer(ctx: Arc<Context>, stream: yamux::Stream) -> Result<(), Error> {
     let (r, w)     = futures::io::AsyncReadExt::split(stream);
     let mut reader = Reader::new(r);
     let mut writer = Writer::new(w);
 
-    let (id, addr, use_half_close) = match recv(&mut reader).await? {
-        Some(Message { id, data: Some(Connect { addr, use_half_close }), .. }) => {
-            match check_addr(addr, &config.allowed_addresses) {
-                Ok(addr)  => (id, addr, use_half_close.unwrap_or(false)),
+    let (id, addr, use_half_close, options) = match recv_timeout(&mut reader, IO_TIMEOUT).await? {
+        Some(Message { id, data: Some(ConnectV2 { addr, use_half_close, scheme, options }), .. }) => {
+            let dest = addr.to_string();
+            match check_addr(addr, scheme.unwrap_or_default(), &ctx).await {
+                Ok(addr)  => {
+                    for h in &ctx.hooks {
+                        h.accepted(id, addr.addr())
+                    }
+                    (id, addr, use_half_close.unwrap_or(false), options)
+                }
                 Err(code) => {
-                    send(&mut writer, Message::new(Err::<(), _>(code))).await?;
+                    ctx.metrics.stream_denied(&dest);
+                    ctx.audit.record(&AuditEvent::new(AuditKind::StreamDenied {
+                        stream: id.to_string(),
+                        destination: dest,
+                        reason: code.to_string()
+                    }));
+                    send_timeout(&mut writer, Message::new(Err::<(), _>(code)), IO_TIMEOUT).await?;
                     return Ok(())
                 }
             }
@@ -39,46 +114,134 @@ pub async fn streamer(config: Arc<Config>, stream: yamux::Stream) -> Result<(),
         None => return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()))
     };
 
+    log_unsupported(id, &options);
+
+    let use_half_close = {
+        let cfg = ctx.config();
+        match policy::setting(&cfg.allowed_addresses, addr.addr(), addr.scheme(), |r| r.half_close) {
+            Some(b) if b != use_half_close => {
+                log::debug!(%id, to = %addr.addr(), "overriding half-close request with {}", b);
+                b
+            }
+            _ => use_half_close
+        }
+    };
+
+    let keepalive = options.keepalive.map(Duration::from_secs);
+
+    let delay = ctx.limits.lock().expect("rate limits lock").delay(addr.addr());
+    if !delay.is_zero() {
+        log::debug!(%id, to = %addr.addr(), "rate limit advisory, delaying stream by {:?}", delay);
+        sleep(delay).await
+    }
+
     let socket =
-        match connect(id, &config, &addr).await {
+        match connect(id, &ctx, &addr, keepalive).await {
             Ok(socket) => {
                 log::debug!(%id, "connected to {}", addr.addr());
                 socket
             }
+            Err(error @ Error::NotAllowed(_)) => {
+                log::warn!(%id, "failed to connect to {}: {}", addr.addr(), error);
+                ctx.metrics.stream_denied(&addr.addr().to_string());
+                ctx.audit.record(&AuditEvent::new(AuditKind::StreamDenied {
+                    stream: id.to_string(),
+                    destination: addr.addr().to_string(),
+                    reason: ErrorCode::AddressNotAllowed.to_string()
+                }));
+                send_timeout(&mut writer, Message::new(Err::<(), _>(ErrorCode::AddressNotAllowed)), IO_TIMEOUT).await?;
+                return Ok(())
+            }
             Err(error) => {
                 log::warn!(%id, "failed to connect to {}: {}", addr.addr(), error);
-                send(&mut writer, Message::new(Err::<(), _>(ErrorCode::CouldNotConnect))).await?;
-                return Err(error)
+                // Counted as failed stream, not as stream error.
+                ctx.metrics.stream_failed(&addr.addr().to_string());
+                send_timeout(&mut writer, Message::new(Err::<(), _>(ErrorCode::CouldNotConnect)), IO_TIMEOUT).await?;
+                return Ok(())
             }
         };
 
-    send(&mut writer, Message::new(Ok::<_, ErrorCode>(()))).await?;
+    send_timeout(&mut writer, Message::new(Ok::<_, ErrorCode>(())), IO_TIMEOUT).await?;
+    let active = ctx.metrics.stream_connected(addr.addr().to_string());
+
+    ctx.audit.record(&AuditEvent::new(AuditKind::StreamOpened {
+        stream: id.to_string(),
+        destination: addr.addr().to_string()
+    }));
+    let _ = ctx.events.send(Event::StreamOpened { id, addr: addr.addr().to_owned() });
+    for h in &ctx.hooks {
+        h.connected(id, addr.addr())
+    }
 
     let reader = reader.into_parts().0.compat();
     let writer = writer.into_parts().0.compat_write();
+    let counts = Arc::new(Counts::default());
+    let socket = Metered { id, socket, counts: counts.clone(), ctx: ctx.clone() };
+    let limits = ctx.bandwidth(&addr);
     let start  = Instant::now();
     let result =
         if use_half_close {
-            transfer_hc(socket, reader, writer).await?
+            transfer_hc(socket, reader, writer, &limits).await
         } else {
-            transfer_fc(socket, reader, writer).await?
+            transfer_fc(socket, reader, writer, &limits).await
         };
-
-    log::debug! {
-        id   = %id,
-        to   = %addr.addr(),
-        recv = ?result.recv,
-        sent = ?result.sent,
-        time = %start.elapsed().as_secs_f32(),
-        "data transfer finished"
+    let (stats, cause, result) = match result {
+        Ok(result) => {
+            log::debug! {
+                id   = %id,
+                to   = %addr.addr(),
+                recv = ?result.recv,
+                sent = ?result.sent,
+                time = %start.elapsed().as_secs_f32(),
+                "data transfer finished"
+            };
+            let stats = StreamStats {
+                sent: result.sent_bytes(),
+                received: result.recv_bytes(),
+                duration: start.elapsed()
+            };
+            let failed = matches!(result.sent, Some(Err(_))) || matches!(result.recv, Some(Err(_)));
+            let cause  = if failed { Termination::Error } else { Termination::Closed };
+            (stats, cause, Ok(()))
+        }
+        Err(e) => {
+            log::debug!(%id, to = %addr.addr(), "data transfer failed: {}", e);
+            let stats = StreamStats {
+                sent: counts.sent(),
+                received: counts.received(),
+                duration: start.elapsed()
+            };
+            (stats, Termination::Error, Err(e.into()))
+        }
     };
 
-    Ok(())
+    active.finish(stats.sent, stats.received, cause);
+
+    ctx.audit.record(&AuditEvent::new(AuditKind::StreamClosed {
+        stream: id.to_string(),
+        destination: addr.addr().to_string(),
+        sent: stats.sent,
+        received: stats.received,
+        millis: u64::try_from(stats.duration.as_millis()).unwrap_or(u64::MAX)
+    }));
+    for h in &ctx.hooks {
+        h.closed(id, addr.addr(), &stats)
+    }
+    let _ = ctx.events.send(Event::StreamClosed {
+        id,
+        addr: addr.addr().to_owned(),
+        sent: stats.sent,
+        received: stats.received,
+        duration: stats.duration
+    });
+
+    result
 }
 
 /// Transfer with half-close.
-async fn transfer_hc<R, W>(tcp: TcpStream, mut stream_r: R, mut stream_w: W) -> io::Result<SendRecv>
+async fn transfer_hc<S, R, W>(tcp: S, mut stream_r: R, mut stream_w: W, limits: &[Arc<Bandwidth>]) -> io::Result<SendRecv>
 where
+    S: io::AsyncRead + io::AsyncWrite,
     R: io::AsyncRead + Unpin,
     W: io::AsyncWrite + Unpin
 {
@@ -87,13 +250,13 @@ where
     let result = tokio::join! {
         // send to gateway
         async {
-            let result = io::copy(&mut socket_r, &mut stream_w).await;
+            let result = copy(&mut socket_r, &mut stream_w, limits).await;
             stream_w.shutdown().await?;
             result
         },
         // receive from gateway
         async {
-            let result = io::copy(&mut stream_r, &mut socket_w).await;
+            let result = copy(&mut stream_r, &mut socket_w, limits).await;
             socket_w.shutdown().await?;
             result
         }
@@ -103,8 +266,9 @@ where
 }
 
 /// Transfer with full-close.
-async fn transfer_fc<R, W>(tcp: TcpStream, mut stream_r: R, mut stream_w: W) -> io::Result<SendRecv>
+async fn transfer_fc<S, R, W>(tcp: S, mut stream_r: R, mut stream_w: W, limits: &[Arc<Bandwidth>]) -> io::Result<SendRecv>
 where
+    S: io::AsyncRead + io::AsyncWrite,
     R: io::AsyncRead + Unpin,
     W: io::AsyncWrite + Unpin
 {
@@ -112,70 +276,238 @@ where
 
     let result = tokio::select! {
         // send to gateway
-        r = io::copy(&mut socket_r, &mut stream_w) => SendRecv { sent: Some(r), recv: None },
+        r = copy(&mut socket_r, &mut stream_w, limits) => SendRecv { sent: Some(r), recv: None },
         // receive from gateway
-        r = io::copy(&mut stream_r, &mut socket_w) => SendRecv { sent: None, recv: Some(r) }
+        r = copy(&mut stream_r, &mut socket_w, limits) => SendRecv { sent: None, recv: Some(r) }
     };
 
     stream_w.shutdown().await?;
     Ok(result)
 }
 
-/// Check that an address is whitelisted.
-pub fn check_addr<'a>(addr: Address<'_>, whitelist: &[Network]) -> Result<CheckedAddr<'a>, ErrorCode> {
-    match CheckedAddr::check(addr.into_owned(), whitelist) {
-        Ok(addr)  => Ok(addr),
-        Err(addr) => {
-            log::error!(address = %addr, "address not allowed");
+/// Copy all data from reader to writer, taking it from the given bandwidth limits.
+async fn copy<R, W>(r: &mut R, w: &mut W, limits: &[Arc<Bandwidth>]) -> io::Result<u64>
+where
+    R: io::AsyncRead + Unpin + ?Sized,
+    W: io::AsyncWrite + Unpin + ?Sized
+{
+    if limits.is_empty() {
+        return io::copy(r, w).await
+    }
+    let mut buf = vec![0; COPY_BUFFER_SIZE];
+    let mut total = 0;
+    loop {
+        let n = r.read(&mut buf).await?;
+        if n == 0 {
+            w.flush().await?;
+            return Ok(total)
+        }
+        for b in limits {
+            b.take(n).await
+        }
+        w.write_all(&buf[.. n]).await?;
+        total += n as u64
+    }
+}
+
+/// A socket which reports the bytes transferred to metrics and stream hooks.
+struct Metered {
+    id: Id,
+    socket: TcpStream,
+    counts: Arc<Counts>,
+    ctx: Arc<Context>
+}
+
+/// The bytes a [`Metered`] socket transferred so far.
+#[derive(Debug, Default)]
+struct Counts {
+    sent: AtomicU64,
+    received: AtomicU64
+}
+
+impl Counts {
+    fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+}
+
+impl Metered {
+    fn progress(&self, sent: usize, received: usize) {
+        if sent > 0 || received > 0 {
+            self.counts.sent.fetch_add(sent as u64, Ordering::Relaxed);
+            self.counts.received.fetch_add(received as u64, Ordering::Relaxed);
+            self.ctx.metrics.transferred(sent as u64, received as u64);
+            for h in &self.ctx.hooks {
+                h.progress(self.id, sent as u64, received as u64)
+            }
+        }
+    }
+}
+
+impl io::AsyncRead for Metered {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>, buf: &mut io::ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let len = buf.filled().len();
+        ready!(Pin::new(&mut self.socket).poll_read(cx, buf))?;
+        self.progress(buf.filled().len() - len, 0);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl io::AsyncWrite for Metered {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let n = ready!(Pin::new(&mut self.socket).poll_write(cx, buf))?;
+        self.progress(0, n);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.socket).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.socket).poll_shutdown(cx)
+    }
+}
+
+/// Answer the connect request of a stream with an error code.
+pub async fn reject(stream: yamux::Stream, code: ErrorCode) -> Result<(), Error> {
+    let (r, w)     = futures::io::AsyncReadExt::split(stream);
+    let mut reader = Reader::new(r);
+    let mut writer = Writer::new(w);
+    match recv_timeout(&mut reader, IO_TIMEOUT).await? {
+        Some(Message { id, data: Some(ConnectV2 { addr, .. }), .. }) => {
+            log::debug!(%id, to = %addr, "rejecting stream: {}", code);
+            send_timeout(&mut writer, Message::new(Err::<(), _>(code)), IO_TIMEOUT).await?;
+            Ok(())
+        }
+        Some(Message { id, data: None, .. }) => Err(Error::UnknownMessageType(id)),
+        None => Err(Error::Io(io::ErrorKind::UnexpectedEof.into()))
+    }
+}
+
+/// Check that an address is not denied, allowed by the policy and its scheme is supported.
+pub async fn check_addr<'a>(addr: Address<'_>, scheme: Scheme, ctx: &Context) -> Result<CheckedAddr<'a>, ErrorCode> {
+    match CheckedAddr::check(addr.into_owned(), scheme, &ctx.denylist(), &*ctx.policy()).await {
+        Ok(addr) => {
+            if scheme != Scheme::Tcp {
+                log::error!(address = %addr.addr(), %scheme, "scheme not supported");
+                return Err(ErrorCode::UnsupportedScheme)
+            }
+            Ok(addr)
+        }
+        Err(Rejected::Denied(addr)) => {
+            log::error!(address = %addr, %scheme, "address denied");
+            Err(ErrorCode::AddressDenied)
+        }
+        Err(Rejected::NotAllowed(addr)) => {
+            log::error!(address = %addr, %scheme, "address not allowed");
             Err(ErrorCode::AddressNotAllowed)
         }
     }
 }
 
-/// Connect to an internal address and return the open TCP socket.
-pub async fn connect(re: Id, cfg: &Config, addr: &CheckedAddr<'_>) -> Result<TcpStream, Error> {
-    // TCP keepalive settings used for data transfer connections.
-    #[cfg(unix)]
-    const KEEPALIVE_SETTINGS: TcpKeepalive = TcpKeepalive::new()
-            .with_time(Duration::from_secs(30))
-            .with_interval(Duration::from_secs(10))
-            .with_retries(3);
-
-    // TCP keepalive settings used for data transfer connections.
-    #[cfg(windows)]
-    const KEEPALIVE_SETTINGS: TcpKeepalive = TcpKeepalive::new()
-            .with_time(Duration::from_secs(30))
-            .with_interval(Duration::from_secs(10));
+/// Log connect options this agent does not support.
+fn log_unsupported(id: Id, opts: &ConnectOptions) {
+    let options = [
+        ("compression", opts.compression.is_some()),
+        ("priority", opts.priority.is_some()),
+        ("proxy-protocol", opts.proxy_protocol.is_some()),
+        ("idle-timeout", opts.idle_timeout.is_some())
+    ];
+    for (name, _) in options.iter().filter(|(_, is_set)| *is_set) {
+        log::debug!(%id, option = %name, "ignoring unsupported connect option")
+    }
+}
 
+/// Connect to an internal address and return the open TCP socket.
+///
+/// The keepalive time overrides the configured one (zero disables keepalive).
+/// With `strict-ip-check`, resolved IP addresses which are denied or not
+/// allowed by the address policy are skipped.
+pub async fn connect(re: Id, ctx: &Context, addr: &CheckedAddr<'_>, keepalive: Option<Duration>) -> Result<TcpStream, Error> {
     log::debug!(id = %re, "connecting to internal address {}", addr.addr());
-    let iter = resolve(addr).await?;
-    let sock = timeout(cfg.connect_timeout, connect_any(iter, addr)).await??;
+    let cfg = ctx.config();
+    let mut addrs: Vec<SocketAddr> = resolve(&cfg, &*ctx.resolver, addr).await?.collect();
+    if cfg.strict_ip_check && matches!(addr.addr(), Address::Name(..)) {
+        let (deny, policy) = (ctx.denylist(), ctx.policy());
+        let mut checked = Vec::with_capacity(addrs.len());
+        for a in addrs {
+            match CheckedAddr::check(Address::Addr(a), addr.scheme(), &deny, &*policy).await {
+                Ok(_)  => checked.push(a),
+                Err(_) => log::debug!(id = %re, "resolved address {} of {} is not allowed", a, addr.addr())
+            }
+        }
+        if checked.is_empty() {
+            return Err(Error::NotAllowed(addr.addr().to_string()))
+        }
+        addrs = checked
+    }
+    let limit = policy::setting(&cfg.allowed_addresses, addr.addr(), addr.scheme(), |r| r.connect_timeout)
+        .unwrap_or(cfg.connect_timeout);
+    let sock = timeout(limit, connect_any(addrs.into_iter(), addr, cfg.bind_address)).await??;
     let sock = Socket::from(sock.into_std()?);
-    sock.set_tcp_keepalive(&KEEPALIVE_SETTINGS)?;
+    let time = keepalive.unwrap_or(cfg.keepalive_time);
+    if time.is_zero() {
+        sock.set_keepalive(false)?
+    } else {
+        sock.set_tcp_keepalive(&keepalive_settings(cfg, time))?
+    }
     Ok(TcpStream::from_std(sock.into())?)
 }
 
+/// TCP keepalive settings used for data transfer connections.
+#[cfg(unix)]
+fn keepalive_settings(cfg: &Config, time: Duration) -> TcpKeepalive {
+    TcpKeepalive::new()
+        .with_time(time)
+        .with_interval(cfg.keepalive_interval)
+        .with_retries(cfg.keepalive_retries)
+}
+
+/// TCP keepalive settings used for data transfer connections.
+#[cfg(windows)]
+fn keepalive_settings(cfg: &Config, time: Duration) -> TcpKeepalive {
+    TcpKeepalive::new()
+        .with_time(time)
+        .with_interval(cfg.keepalive_interval)
+}
+
 /// Resolve an address.
-async fn resolve<'a>(addr: &'a CheckedAddr<'_>) -> Result<impl Iterator<Item = SocketAddr> + 'a, Error> {
+///
+/// Host names configured in `hosts` take precedence over DNS.
+async fn resolve(cfg: &Config, resolver: &dyn Resolver, addr: &CheckedAddr<'_>) -> Result<impl Iterator<Item = SocketAddr>, Error> {
     match addr.addr() {
         Address::Addr(socketaddr) => Ok(Either::Left(std::iter::once(*socketaddr))),
         Address::Name(host, port) => {
-            let mut iter = net::lookup_host((host.as_ref(), *port)).await?.peekable();
-            if iter.peek().is_none() {
-                return Err(Error::Unreachable(host.as_ref().into()))
+            if let Some(a) = host_override(cfg, host) {
+                return Ok(Either::Left(std::iter::once(a)))
+            }
+            match resolver.resolve(host, *port).await {
+                Ok(addrs) => Ok(Either::Right(addrs.into_iter())),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Err(Error::Unreachable(host.as_ref().into())),
+                Err(e) => Err(e.into())
             }
-            Ok(Either::Right(iter))
         }
+        Address::Path(path) => Err(Error::Unreachable(path.as_ref().into()))
     }
 }
 
+/// Look up a host name in the configured `hosts` (ignoring ASCII case).
+fn host_override(cfg: &Config, host: &str) -> Option<SocketAddr> {
+    cfg.hosts.iter().find(|(name, _)| name.eq_ignore_ascii_case(host)).map(|(_, a)| *a)
+}
+
 /// Connect to any of the given IP addresses.
-async fn connect_any<I>(iter: I, dest: &Address<'_>) -> io::Result<TcpStream>
+async fn connect_any<I>(iter: I, dest: &Address<'_>, local: Option<IpAddr>) -> io::Result<TcpStream>
 where
     I: Iterator<Item = SocketAddr>
 {
     for addr in iter {
-        match TcpStream::connect(addr).await {
+        match tcp_connect(addr, local).await {
             Ok(s)  => return Ok(s),
             Err(e) => log::debug!("failed to connect to {} ({}): {}", addr, dest, e)
         }
@@ -0,0 +1,277 @@
+//! An in-process gateway for tests.
+//!
+//! [`Gateway`] accepts TLS connections from agents on a local port and
+//! speaks enough of the gateway protocol to authenticate agents and to
+//! script `Test` and `Connect` requests:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), cluvio_agent::Error> {
+//! use cluvio_agent::{Agent, test_util::Gateway};
+//!
+//! let mut gateway = Gateway::start().await?;
+//! let agent = Agent::new(gateway.config(sealed_boxes::gen_secret_key()))?.spawn();
+//! let mut session = gateway.accept().await?;
+//! assert!(session.challenge().await?);
+//! session.accept().await?;
+//! agent.shutdown().await;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`connected_agent`] does the same in one step.
+//!
+//! Enabled with feature `test-util`.
+
+use crate::{Agent, Config, Error, Handle, IO_TIMEOUT, Reader, Writer};
+use minicbor_io::{AsyncReader, AsyncWriter};
+use protocol::{Address, Client, ConnectOptions, ConnectV2, ErrorCode, Id, Message, Reason, Scheme, Server, Version};
+use sealed_boxes::{PublicKey, SecretKey};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::{self, ServerConfig};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+use util::io::{recv_timeout, send_timeout};
+use util::{HostName, NonEmpty};
+
+/// The host name of the gateway certificate.
+const HOST: &str = "localhost";
+
+/// A minimal gateway listening on localhost.
+pub struct Gateway {
+    addr: SocketAddr,
+    cert: CertificateDer<'static>,
+    sessions: mpsc::Receiver<Session>,
+    task: JoinHandle<()>
+}
+
+impl Drop for Gateway {
+    fn drop(&mut self) {
+        self.task.abort()
+    }
+}
+
+impl Gateway {
+    /// Start a gateway on a random port with a fresh self-signed certificate.
+    pub async fn start() -> Result<Self, Error> {
+        Self::start_with(yamux::Config::default()).await
+    }
+
+    /// Like [`Gateway::start`] but with a custom multiplexer config.
+    pub async fn start_with(mux: yamux::Config) -> Result<Self, Error> {
+        let ck   = rcgen::generate_simple_self_signed(vec![HOST.to_string()]).map_err(io::Error::other)?;
+        let cert = ck.cert.der().clone();
+        let key  = PrivatePkcs8KeyDer::from(ck.key_pair.serialize_der());
+        let cfg  = ServerConfig::builder_with_protocol_versions(&[&rustls::version::TLS13])
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.clone()], key.into())?;
+        let acceptor = TlsAcceptor::from(Arc::new(cfg));
+        let listener = TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).await?;
+        let addr     = listener.local_addr()?;
+        let (tx, rx) = mpsc::channel(16);
+        let task     = tokio::spawn(async move {
+            loop {
+                let (sock, _) = match listener.accept().await {
+                    Ok(s)  => s,
+                    Err(e) => {
+                        log::warn!("gateway failed to accept connection: {}", e);
+                        continue
+                    }
+                };
+                let acceptor = acceptor.clone();
+                let mux = mux.clone();
+                let tx  = tx.clone();
+                tokio::spawn(async move {
+                    match Session::new(acceptor, mux, sock).await {
+                        Ok(s)  => { let _ = tx.send(s).await; }
+                        Err(e) => log::warn!("gateway failed to establish session: {}", e)
+                    }
+                });
+            }
+        });
+        Ok(Gateway { addr, cert, sessions: rx, task })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The certificate agents need to trust.
+    pub fn certificate(&self) -> &CertificateDer<'static> {
+        &self.cert
+    }
+
+    /// Create an agent config which connects to this gateway.
+    pub fn config(&self, sk: SecretKey) -> Config {
+        let host = HostName::try_from(HOST).expect("valid host name");
+        let mut cfg = Config::new(sk, host, self.addr.port());
+        cfg.server_mut().trust = Some(NonEmpty::new(self.cert.clone()));
+        cfg
+    }
+
+    /// Wait for the next agent to connect and send its `Hello`.
+    pub async fn accept(&mut self) -> Result<Session, Error> {
+        self.sessions.recv().await.ok_or_else(|| Error::Io(io::ErrorKind::BrokenPipe.into()))
+    }
+}
+
+/// The gateway side of an agent connection.
+pub struct Session {
+    pubkey: PublicKey,
+    version: Version,
+    ctrl: yamux::Control,
+    reader: Reader,
+    writer: Writer,
+    task: JoinHandle<Result<(), yamux::ConnectionError>>
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.task.abort()
+    }
+}
+
+impl Session {
+    async fn new(acceptor: TlsAcceptor, mux: yamux::Config, sock: tokio::net::TcpStream) -> Result<Self, Error> {
+        let tls      = acceptor.accept(sock).await?;
+        let mut conn = yamux::Connection::new(tls.compat(), mux, yamux::Mode::Server);
+        let ctrl     = conn.control();
+        let (tx, mut rx) = mpsc::channel(1);
+        let task = tokio::spawn(async move {
+            while let Some(s) = conn.next_stream().await? {
+                // Only the first inbound stream (the control stream) is of interest.
+                let _ = tx.try_send(s);
+            }
+            Ok(())
+        });
+        let stream = rx.recv().await.ok_or_else(|| Error::Io(io::ErrorKind::UnexpectedEof.into()))?;
+        let (r, w) = futures::io::AsyncReadExt::split(stream);
+        let mut reader = Reader::new(r);
+        let (pubkey, version) = match recv_timeout(&mut reader, IO_TIMEOUT).await? {
+            Some(Message::<Client> { data: Some(Client::Hello { pubkey, agent_version, .. }), .. }) => {
+                let bytes = <[u8; 32]>::try_from(&pubkey[..])
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid public key length"))?;
+                (PublicKey::from(bytes), agent_version)
+            }
+            _ => return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidData, "expected hello")))
+        };
+        Ok(Session { pubkey, version, ctrl, reader, writer: Writer::new(w), task })
+    }
+
+    /// The public key the agent announced.
+    pub fn public_key(&self) -> &PublicKey {
+        &self.pubkey
+    }
+
+    /// The version the agent announced.
+    pub fn agent_version(&self) -> Version {
+        self.version
+    }
+
+    /// Send an arbitrary control message and return its ID.
+    pub async fn send(&mut self, data: Server<'_>) -> Result<Id, Error> {
+        let msg = Message::new(data);
+        let id  = msg.id;
+        send_timeout(&mut self.writer, msg, IO_TIMEOUT).await?;
+        Ok(id)
+    }
+
+    /// Challenge the agent to prove possession of its secret key.
+    pub async fn challenge(&mut self) -> Result<bool, Error> {
+        let plain: [u8; 32] = sealed_boxes::fresh_array();
+        let text = sealed_boxes::encrypt(&self.pubkey, plain)?;
+        let id   = self.send(Server::Challenge { text: Box::new(text.into()) }).await?;
+        self.expect(|m| match m.data {
+            Some(Client::Response { re, text }) if re == id => Some(text[..] == plain[..]),
+            Some(Client::Error { re, .. }) if re == id => Some(false),
+            _ => None
+        })
+        .await
+    }
+
+    /// Tell the agent it has been accepted.
+    pub async fn accept(&mut self) -> Result<(), Error> {
+        self.send(Server::Accepted).await?;
+        Ok(())
+    }
+
+    /// Ask the agent to test the reachability of an address.
+    pub async fn test(&mut self, addr: Address<'_>, scheme: Option<Scheme>) -> Result<Option<ErrorCode>, Error> {
+        let id = self.send(Server::Test { addr, scheme }).await?;
+        self.expect(|m| match m.data {
+            Some(Client::Test { re, code }) if re == id => Some(code),
+            _ => None
+        })
+        .await
+    }
+
+    /// Terminate the agent.
+    pub async fn terminate(&mut self, reason: Reason) -> Result<(), Error> {
+        self.send(Server::Terminate { reason }).await?;
+        Ok(())
+    }
+
+    /// Ask the agent to connect to an address.
+    ///
+    /// On success, the returned stream transfers data to and from the address.
+    pub async fn connect(&mut self, addr: Address<'_>) -> Result<Result<yamux::Stream, ErrorCode>, Error> {
+        let mut stream = self.ctrl.open_stream().await?;
+        let request = ConnectV2 {
+            addr,
+            use_half_close: Some(true),
+            scheme: None,
+            options: ConnectOptions::default()
+        };
+        send_timeout(&mut AsyncWriter::new(&mut stream), Message::new(request), IO_TIMEOUT).await?;
+        let mut reader = AsyncReader::new(&mut stream);
+        let answer = match recv_timeout::<Message<Result<(), ErrorCode>>, _>(&mut reader, IO_TIMEOUT).await? {
+            Some(Message { data: Some(answer), .. }) => answer,
+            _ => return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()))
+        };
+        Ok(answer.map(|()| stream))
+    }
+
+    /// Read control messages until `f` accepts one, answering pings meanwhile.
+    async fn expect<T, F>(&mut self, mut f: F) -> Result<T, Error>
+    where
+        F: FnMut(Message<Client<'_>>) -> Option<T>
+    {
+        loop {
+            let msg: Message<Client> = match recv_timeout(&mut self.reader, IO_TIMEOUT).await? {
+                Some(m) => m,
+                None    => return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()))
+            };
+            if let Some(Client::Ping) = msg.data {
+                send_timeout(&mut self.writer, Message::new(Server::Pong { re: msg.id }), IO_TIMEOUT).await?;
+                continue
+            }
+            if let Some(t) = f(msg) {
+                return Ok(t)
+            }
+        }
+    }
+}
+
+/// Start a gateway and an agent whose config is adjusted by `f`.
+///
+/// Returns once the gateway has authenticated and accepted the agent.
+pub async fn connected_agent<F>(f: F) -> Result<(Gateway, Session, Handle), Error>
+where
+    F: FnOnce(&mut Config)
+{
+    let mut gateway = Gateway::start().await?;
+    let mut cfg = gateway.config(sealed_boxes::gen_secret_key());
+    f(&mut cfg);
+    let agent = Agent::new(cfg)?.spawn();
+    let mut session = gateway.accept().await?;
+    if !session.challenge().await? {
+        return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidData, "challenge failed")))
+    }
+    session.accept().await?;
+    Ok((gateway, session, agent))
+}
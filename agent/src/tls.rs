@@ -1,19 +1,22 @@
 use crate::Error;
 use std::fmt;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use tokio::io;
 use tokio::net::TcpStream;
 use tokio_rustls::rustls::{self, ClientConfig};
 use tokio_rustls::TlsConnector;
 use util::HostName;
+use util::io::tcp_connect;
 
 pub use tokio_rustls::client::TlsStream as Stream;
 
 /// A TLS client.
 #[derive(Clone)]
 pub struct Client {
-    config: Arc<ClientConfig>
+    config: Arc<ClientConfig>,
+    /// Local address to connect from.
+    bind: Option<IpAddr>
 }
 
 impl fmt::Debug for Client {
@@ -48,7 +51,7 @@ impl Client {
             .with_root_certificates(root_store)
             .with_no_client_auth();
 
-        Ok(Client { config: Arc::new(cfg) })
+        Ok(Client { config: Arc::new(cfg), bind: config.server.bind_address })
     }
 
     /// Connect with this client to the given address.
@@ -56,7 +59,7 @@ impl Client {
     /// Server name is checked against the given hostname.
     pub async fn connect(&self, addr: SocketAddr, hostname: &HostName) -> io::Result<Stream<TcpStream>> {
         let conn = TlsConnector::from(self.config.clone());
-        let sock = TcpStream::connect(&addr).await?;
+        let sock = tcp_connect(addr, self.bind).await?;
         conn.connect(hostname.as_server_name().clone(), sock).await
     }
 
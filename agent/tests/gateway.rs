@@ -0,0 +1,436 @@
+//! Tests of the agent against the in-process gateway of feature `test-util`.
+
+use cluvio_agent::{Agent, Config, Event, Status, StreamHook, StreamStats, Termination};
+use cluvio_agent::config::{Health, Rule};
+use cluvio_agent::test_util::{Gateway, connected_agent};
+use futures::{AsyncReadExt, AsyncWriteExt};
+use protocol::{Address, ErrorCode, Id};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use util::{NonEmpty, Secret};
+use util::service::Service;
+
+async fn echo_server() -> SocketAddr {
+    let echo = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let echo_addr = echo.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut s, _) = echo.accept().await.unwrap();
+        let (mut r, mut w) = s.split();
+        tokio::io::copy(&mut r, &mut w).await.unwrap();
+    });
+    echo_addr
+}
+
+#[tokio::test]
+async fn end_to_end() {
+    let echo_addr = echo_server().await;
+
+    let mut gateway = Gateway::start().await.unwrap();
+    let agent = Agent::new(gateway.config(sealed_boxes::gen_secret_key())).unwrap();
+    let mut events = agent.events();
+    let agent  = agent.spawn();
+    let status = agent.status();
+
+    let mut session = gateway.accept().await.unwrap();
+    assert!(matches!(events.recv().await, Ok(Event::Connected)));
+    assert_eq!(Status::Online, *status.borrow());
+    assert!(session.challenge().await.unwrap());
+    session.accept().await.unwrap();
+
+    assert_eq!(None, session.test(Address::Addr(echo_addr), None).await.unwrap());
+
+    let mut stream = session.connect(Address::Addr(echo_addr)).await.unwrap().unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    let mut buf = [0; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"hello", &buf);
+
+    assert!(agent.shutdown().await.is_none());
+    assert_eq!(Status::Stopped, *status.borrow())
+}
+
+#[derive(Default)]
+struct Counter {
+    calls: Mutex<Vec<&'static str>>,
+    bytes: Mutex<(u64, u64)>,
+    stats: Mutex<Option<StreamStats>>
+}
+
+impl StreamHook for Counter {
+    fn accepted(&self, _: Id, _: &Address<'_>) {
+        self.calls.lock().unwrap().push("accepted")
+    }
+
+    fn connected(&self, _: Id, _: &Address<'_>) {
+        self.calls.lock().unwrap().push("connected")
+    }
+
+    fn progress(&self, _: Id, sent: u64, received: u64) {
+        let mut b = self.bytes.lock().unwrap();
+        b.0 += sent;
+        b.1 += received
+    }
+
+    fn closed(&self, _: Id, _: &Address<'_>, stats: &StreamStats) {
+        self.calls.lock().unwrap().push("closed");
+        *self.stats.lock().unwrap() = Some(*stats)
+    }
+}
+
+#[tokio::test]
+async fn stream_hooks() {
+    let echo_addr = echo_server().await;
+    let counter   = Arc::new(Counter::default());
+
+    let mut gateway = Gateway::start().await.unwrap();
+    let agent = Agent::builder(gateway.config(sealed_boxes::gen_secret_key()))
+        .with_stream_hook(counter.clone())
+        .build()
+        .unwrap();
+    let mut events = agent.events();
+    let agent = agent.spawn();
+
+    let mut session = gateway.accept().await.unwrap();
+    assert!(session.challenge().await.unwrap());
+    session.accept().await.unwrap();
+
+    let mut stream = session.connect(Address::Addr(echo_addr)).await.unwrap().unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    let mut buf = [0; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    stream.close().await.unwrap();
+
+    while !matches!(events.recv().await, Ok(Event::StreamClosed { .. })) {}
+
+    assert_eq!(vec!["accepted", "connected", "closed"], *counter.calls.lock().unwrap());
+    assert_eq!((5, 5), *counter.bytes.lock().unwrap());
+    let stats = counter.stats.lock().unwrap().unwrap();
+    assert_eq!((5, 5), (stats.sent, stats.received));
+    let metrics = agent.metrics().snapshot();
+    assert_eq!(1, metrics.streams);
+    assert_eq!((5, 5), (metrics.bytes_sent, metrics.bytes_received));
+    let dest = &agent.metrics().destinations()[&echo_addr.to_string()];
+    assert_eq!((1, 0), (dest.streams, dest.active_streams));
+    assert_eq!(Some(&1), dest.terminations.get(&Termination::Closed));
+
+    assert!(agent.shutdown().await.is_none())
+}
+
+#[tokio::test]
+async fn run_until_cancelled() {
+    let mut gateway = Gateway::start().await.unwrap();
+    let agent = Agent::new(gateway.config(sealed_boxes::gen_secret_key())).unwrap();
+    let token = tokio_util::sync::CancellationToken::new();
+    let task  = tokio::spawn(agent.run_until(token.clone()));
+
+    let mut session = gateway.accept().await.unwrap();
+    assert!(session.challenge().await.unwrap());
+    token.cancel();
+    assert_eq!(Status::Stopped, task.await.unwrap())
+}
+
+#[tokio::test]
+async fn cancel_while_reconnecting() {
+    let mut gateway = Gateway::start().await.unwrap();
+    let agent   = Agent::new(gateway.config(sealed_boxes::gen_secret_key())).unwrap();
+    let metrics = agent.metrics();
+    let token   = tokio_util::sync::CancellationToken::new();
+    let task    = tokio::spawn(agent.run_until(token.clone()));
+
+    let mut session = gateway.accept().await.unwrap();
+    assert!(session.challenge().await.unwrap());
+    session.accept().await.unwrap();
+
+    // Without a gateway, the agent keeps trying to reconnect.
+    drop((session, gateway));
+    while metrics.snapshot().reconnects == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    token.cancel();
+    let status = tokio::time::timeout(Duration::from_secs(5), task).await.expect("agent stops when cancelled");
+    assert_eq!(Status::Stopped, status.unwrap())
+}
+
+#[tokio::test]
+async fn reload_allowlist() {
+    let echo_addr = echo_server().await;
+    let sk = sealed_boxes::gen_secret_key();
+
+    let (gateway, mut session, agent) = connected_agent(|cfg| {
+        cfg.secret_key = Secret::self_zeroizing(sk.clone())
+    })
+    .await
+    .unwrap();
+    let mut events = agent.events();
+    assert_eq!(None, session.test(Address::Addr(echo_addr), None).await.unwrap());
+
+    let mut cfg = gateway.config(sk.clone());
+    *cfg.allowed_addresses_mut() = NonEmpty::new(Rule::try_from("192.0.2.0/24").unwrap());
+    agent.reloader().reload(cfg);
+    while !matches!(events.recv().await, Ok(Event::ConfigReloaded)) {}
+
+    let code = session.test(Address::Addr(echo_addr), None).await.unwrap();
+    assert_eq!(Some(ErrorCode::AddressNotAllowed), code);
+
+    let mut cfg = gateway.config(sk.clone());
+    cfg.denied_addresses_mut().push(Rule::try_from("127.0.0.0/8").unwrap());
+    agent.reloader().reload(cfg);
+    while !matches!(events.recv().await, Ok(Event::ConfigReloaded)) {}
+
+    let code = session.test(Address::Addr(echo_addr), None).await.unwrap();
+    assert_eq!(Some(ErrorCode::AddressDenied), code);
+
+    assert!(agent.shutdown().await.is_none())
+}
+
+#[tokio::test]
+async fn strict_ip_check() {
+    let echo_addr = echo_server().await;
+    let echo_name = || Address::Name("echo.internal".into(), echo_addr.port());
+    let sk = sealed_boxes::gen_secret_key();
+
+    let configure = |cfg: &mut Config, net: &str| {
+        cfg.hosts.insert("echo.internal".to_string(), echo_addr);
+        cfg.allowed_addresses = NonEmpty::try_from(vec![
+            Rule::try_from("echo.internal").unwrap(),
+            Rule::try_from(net).unwrap()
+        ]).unwrap();
+        cfg.strict_ip_check = true
+    };
+
+    let (gateway, mut session, agent) = connected_agent(|cfg| {
+        cfg.secret_key = Secret::self_zeroizing(sk.clone());
+        configure(cfg, "192.0.2.0/24")
+    })
+    .await
+    .unwrap();
+    let mut events = agent.events();
+    assert!(matches!(session.connect(echo_name()).await.unwrap(), Err(ErrorCode::AddressNotAllowed)));
+
+    let mut allowed = gateway.config(sk.clone());
+    configure(&mut allowed, "127.0.0.0/8");
+    agent.reloader().reload(allowed);
+    while !matches!(events.recv().await, Ok(Event::ConfigReloaded)) {}
+    assert!(session.connect(echo_name()).await.unwrap().is_ok());
+
+    assert!(agent.shutdown().await.is_none())
+}
+
+#[tokio::test]
+async fn strict_ip_check_denied() {
+    let echo_addr = echo_server().await;
+    let echo_name = || Address::Name("echo.internal".into(), echo_addr.port());
+
+    let (_gateway, mut session, agent) = connected_agent(|cfg| {
+        cfg.hosts.insert("echo.internal".to_string(), echo_addr);
+        cfg.denied_addresses = vec![Rule::try_from("127.0.0.0/8").unwrap()];
+        cfg.strict_ip_check = true
+    })
+    .await
+    .unwrap();
+    assert!(matches!(session.connect(echo_name()).await.unwrap(), Err(ErrorCode::AddressNotAllowed)));
+    assert_eq!(Some(ErrorCode::CouldNotConnect), session.test(echo_name(), None).await.unwrap());
+
+    assert!(agent.shutdown().await.is_none())
+}
+
+#[tokio::test]
+async fn connect_failure() {
+    let closed = {
+        let l = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        l.local_addr().unwrap()
+    };
+
+    let (_gateway, mut session, agent) = connected_agent(|_| ()).await.unwrap();
+    assert!(matches!(session.connect(Address::Addr(closed)).await.unwrap(), Err(ErrorCode::CouldNotConnect)));
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let metrics = agent.metrics().snapshot();
+    assert_eq!((1, 0), (metrics.failed_streams, metrics.stream_errors));
+    assert!(agent.shutdown().await.is_none())
+}
+
+#[tokio::test]
+async fn max_concurrent_streams() {
+    let echo_addr = echo_server().await;
+
+    let (_gateway, mut session, agent) = connected_agent(|cfg| cfg.max_concurrent_streams = Some(1)).await.unwrap();
+
+    let stream = session.connect(Address::Addr(echo_addr)).await.unwrap().unwrap();
+    let result = session.connect(Address::Addr(echo_addr)).await.unwrap();
+    assert!(matches!(result, Err(ErrorCode::TooManyStreams)));
+    assert_eq!(1, agent.metrics().snapshot().rejected_streams);
+
+    drop(stream);
+    assert!(agent.shutdown().await.is_none())
+}
+
+#[derive(Default)]
+struct Recorder {
+    calls: Mutex<Vec<&'static str>>,
+    watchdogs: Mutex<usize>
+}
+
+impl Service for Recorder {
+    fn manager(&self) -> &'static str {
+        "test"
+    }
+
+    fn ready(&self) -> io::Result<()> {
+        self.calls.lock().unwrap().push("ready");
+        Ok(())
+    }
+
+    fn stopping(&self) -> io::Result<()> {
+        self.calls.lock().unwrap().push("stopping");
+        Ok(())
+    }
+
+    fn reloading(&self) -> io::Result<()> {
+        self.calls.lock().unwrap().push("reloading");
+        Ok(())
+    }
+
+    fn status(&self, _: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn watchdog(&self) -> io::Result<()> {
+        *self.watchdogs.lock().unwrap() += 1;
+        Ok(())
+    }
+
+    fn watchdog_interval(&self) -> Option<Duration> {
+        Some(Duration::from_millis(20))
+    }
+}
+
+#[tokio::test]
+async fn service_notifications() {
+    let echo_addr = echo_server().await;
+    let recorder  = Arc::new(Recorder::default());
+    let sk = sealed_boxes::gen_secret_key();
+
+    let mut gateway = Gateway::start().await.unwrap();
+    let agent = Agent::builder(gateway.config(sk.clone()))
+        .with_service(recorder.clone())
+        .build()
+        .unwrap();
+    let mut events = agent.events();
+    let agent = agent.spawn();
+
+    let mut session = gateway.accept().await.unwrap();
+    assert!(session.challenge().await.unwrap());
+    assert!(recorder.calls.lock().unwrap().is_empty());
+    session.accept().await.unwrap();
+    assert_eq!(None, session.test(Address::Addr(echo_addr), None).await.unwrap());
+    assert_eq!(vec!["ready"], *recorder.calls.lock().unwrap());
+
+    agent.reloader().reload(gateway.config(sk));
+    while !matches!(events.recv().await, Ok(Event::ConfigReloaded)) {}
+    assert_eq!(vec!["ready", "reloading", "ready"], *recorder.calls.lock().unwrap());
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(agent.shutdown().await.is_none());
+    assert_eq!(vec!["ready", "reloading", "ready", "stopping"], *recorder.calls.lock().unwrap());
+    assert!(*recorder.watchdogs.lock().unwrap() > 0)
+}
+
+#[tokio::test]
+async fn ready_when_gateway_unreachable() {
+    let recorder = Arc::new(Recorder::default());
+
+    let gateway = Gateway::start().await.unwrap();
+    let cfg = gateway.config(sealed_boxes::gen_secret_key());
+    drop(gateway);
+
+    let agent = Agent::builder(cfg)
+        .with_service(recorder.clone())
+        .build()
+        .unwrap()
+        .spawn();
+
+    for _ in 0 .. 200 {
+        if !recorder.calls.lock().unwrap().is_empty() {
+            break
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(vec!["ready"], *recorder.calls.lock().unwrap());
+    assert!(agent.shutdown().await.is_none())
+}
+
+async fn http_get(addr: SocketAddr, path: &str) -> String {
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+    let mut s = tokio::net::TcpStream::connect(addr).await.unwrap();
+    s.write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes()).await.unwrap();
+    let mut response = String::new();
+    s.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn ping_round_trip() {
+    let echo_addr = echo_server().await;
+
+    let (_gateway, mut session, agent) = connected_agent(|cfg| cfg.ping_frequency = Duration::from_millis(200)).await.unwrap();
+
+    // The session answers pings while awaiting test results.
+    for _ in 0 .. 200 {
+        if agent.ping_rtt().is_some() {
+            break
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(None, session.test(Address::Addr(echo_addr), None).await.unwrap())
+    }
+    assert!(agent.ping_rtt().is_some_and(|rtt| rtt < Duration::from_secs(1)));
+
+    // The round-trip time is reset when the connection is lost.
+    let mut events = agent.events();
+    drop(session);
+    while !matches!(events.recv().await, Ok(Event::Disconnected)) {}
+    assert_eq!(None, agent.ping_rtt());
+    assert!(agent.shutdown().await.is_none())
+}
+
+#[tokio::test]
+async fn health_endpoint() {
+    let mut gateway = Gateway::start().await.unwrap();
+    let mut cfg = gateway.config(sealed_boxes::gen_secret_key());
+    cfg.health = Some(Health::new(([127, 0, 0, 1], 0).into()));
+    cfg.ping_frequency = Duration::from_millis(200);
+    let agent = Agent::new(cfg).unwrap();
+    let addr  = agent.health_address().unwrap();
+    let agent = agent.spawn();
+
+    assert!(http_get(addr, "/healthz").await.starts_with("HTTP/1.1 200"));
+
+    let mut session = gateway.accept().await.unwrap();
+    assert!(session.challenge().await.unwrap());
+    assert!(http_get(addr, "/readyz").await.starts_with("HTTP/1.1 503"));
+    session.accept().await.unwrap();
+    assert_eq!(None, session.test(Address::Addr(addr), None).await.unwrap());
+    assert!(http_get(addr, "/readyz").await.starts_with("HTTP/1.1 200"));
+
+    // The session answers pings while awaiting test results.
+    while agent.ping_rtt().is_none() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(None, session.test(Address::Addr(addr), None).await.unwrap())
+    }
+
+    let response = http_get(addr, "/status").await;
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    let status: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!("online", status["status"]);
+    assert_eq!(true, status["authenticated"]);
+    assert_eq!(0, status["active-streams"]);
+    assert!(status["destinations"].is_object());
+    assert!(status["ping-rtt-ms"].as_f64().is_some_and(|ms| ms > 0.0));
+
+    assert!(http_get(addr, "/metrics").await.starts_with("HTTP/1.1 404"));
+    assert!(agent.shutdown().await.is_none())
+}
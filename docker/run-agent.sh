@@ -2,38 +2,52 @@
 
 # Start script for an agent inside a linux docker container.
 #
-# The script expects the environment variables `AGENT_SECRET_KEY` and
-# `LOCATION` (or `AGENT_GATEWAY_HOST`) to be set and renders a proper
-# TOML configuration file inside the container before starting the agent.
+# The script expects the environment variables `AGENT_SECRET_KEY` (or
+# `AGENT_SECRET_KEY_FILE`, e.g. a mounted secret) and `LOCATION` (or
+# `AGENT_GATEWAY_HOST`) to be set and renders a proper TOML configuration
+# file inside the container before starting the agent.
 
 set -e
 
-if [ -z "$AGENT_SECRET_KEY" ]; then
-  echo "AGENT_SECRET_KEY must be set"
+if [ -n "$AGENT_SECRET_KEY" ] && [ -n "$AGENT_SECRET_KEY_FILE" ]; then
+  echo "AGENT_SECRET_KEY and AGENT_SECRET_KEY_FILE are mutually exclusive"
   exit 1
 fi
 
-if [ -n "$LOCATION" ]; then
-  if [ "$LOCATION" = "eu" ]; then
-    AGENT_GATEWAY_HOST="gateway.eu.cluvio.com"
-  elif [ "$LOCATION" = "us" ]; then
-    AGENT_GATEWAY_HOST="gateway.us.cluvio.com"
-  else
-    echo "Invalid location: $LOCATION"
-    exit 1
-  fi
+if [ -n "$AGENT_SECRET_KEY_FILE" ]; then
+  SECRET_KEY_LINE="secret-key-file = \"$AGENT_SECRET_KEY_FILE\""
+elif [ -n "$AGENT_SECRET_KEY" ]; then
+  SECRET_KEY_LINE="secret-key = \"$AGENT_SECRET_KEY\""
+else
+  echo "AGENT_SECRET_KEY or AGENT_SECRET_KEY_FILE must be set"
+  exit 1
 fi
 
-if [ -z "$AGENT_GATEWAY_HOST" ]; then
+if [ -n "$LOCATION" ]; then
+  # Any location is accepted; unknown ones resolve to gateway.<location>.cluvio.com
+  # unless overridden with `CLUVIO_LOCATIONS`.
+  case "$LOCATION" in
+    *[!a-zA-Z0-9-]*)
+      echo "Invalid location: $LOCATION"
+      exit 1
+      ;;
+  esac
+  SERVER_LINE="location = \"$LOCATION\""
+elif [ -n "$AGENT_GATEWAY_HOST" ]; then
+  SERVER_LINE="host = \"$AGENT_GATEWAY_HOST\""
+else
   echo "LOCATION or AGENT_GATEWAY_HOST must be set"
   exit 1
 fi
 
+# The config contains the secret key and must only be readable by the agent user.
+umask 077
+
 cat << EOF > /opt/cluvio/cluvio-agent.toml
-secret-key = "$AGENT_SECRET_KEY"
+$SECRET_KEY_LINE
 
 [server]
-host = "$AGENT_GATEWAY_HOST"
+$SERVER_LINE
 EOF
 
 # To ensure process signals (e.g. as sent by ctrl+c) are forwarded to the agent,
@@ -1,10 +1,11 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
 use sealed_boxes::PublicKey;
 use serde::{Serialize, Deserialize};
 use serde::{Deserializer, Serializer, de::Error};
-use std::borrow::{Borrow, Cow};
+use std::borrow::Cow;
 use std::fmt;
 use std::sync::Arc;
-use util::base64;
 
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 #[serde(transparent)]
@@ -15,17 +16,25 @@ pub struct AgentId {
 
 impl AgentId {
     pub fn from_base64(s: &str) -> Option<Self> {
-        let b = base64::decode(s)?;
+        let b = BASE64.decode(s).ok()?;
         Some(AgentId::from(&*b))
     }
 
     pub fn to_base64(&self) -> String {
-        base64::encode(&self.val)
+        BASE64.encode(&self.val)
     }
 
     pub fn as_bytes(&self) -> &[u8] {
         &*self.val
     }
+
+    /// A short, human-friendly fingerprint of this ID.
+    ///
+    /// The fingerprint consists of 8 hex digits of the blake2b hash of the ID.
+    pub fn fingerprint(&self) -> String {
+        let h = blake2b_simd::Params::new().hash_length(4).hash(&self.val);
+        h.to_hex().to_string()
+    }
 }
 
 impl From<PublicKey> for AgentId {
@@ -42,23 +51,27 @@ impl From<&[u8]> for AgentId {
 
 impl fmt::Debug for AgentId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str(&base64::encode(&self.val))
+        f.write_str(&BASE64.encode(&self.val))
     }
 }
 
+/// The alternate format (`{:#}`) shows the fingerprint instead of the full ID.
 impl fmt::Display for AgentId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return f.write_str(&self.fingerprint())
+        }
         fmt::Debug::fmt(self, f)
     }
 }
 
 fn serialize<S: Serializer>(val: &Arc<[u8]>, s: S) -> Result<S::Ok, S::Error> {
-    base64::encode(val).serialize(s)
+    BASE64.encode(val).serialize(s)
 }
 
 fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Arc<[u8]>, D::Error> {
     let s = <Cow<'de, str>>::deserialize(d)?;
-    let v = base64::decode(s.borrow()).ok_or_else(|| Error::custom("invalid base64"))?;
+    let v = BASE64.decode(&*s).map_err(|_| Error::custom("invalid base64"))?;
     Ok(Arc::from(v))
 }
 
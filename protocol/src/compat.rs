@@ -0,0 +1,115 @@
+//! Wire-format versioning and helpers to decode older encodings.
+
+use minicbor::data::Type;
+use minicbor::decode::{Decoder, Error};
+use std::convert::TryFrom;
+
+/// The current protocol version.
+///
+/// 1. Initial protocol.
+/// 2. Message sequence numbers, address schemes and the protocol version
+///    in `Client::Hello`. `Connect` is decoded from array and map encodings.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Decode the fields of a value which is encoded either as CBOR array or map.
+///
+/// For arrays the element position is used as field index, for maps the key.
+/// The given function is applied to each field index and must decode or skip
+/// the field value.
+pub fn decode_fields<'b, F>(d: &mut Decoder<'b>, mut f: F) -> Result<(), Error>
+where
+    F: FnMut(u32, &mut Decoder<'b>) -> Result<(), Error>
+{
+    let p = d.position();
+    match d.datatype()? {
+        Type::Array | Type::ArrayIndef => {
+            if let Some(n) = d.array()? {
+                for i in 0 .. n {
+                    let i = u32::try_from(i).map_err(|_| Error::message("array too large").at(p))?;
+                    f(i, d)?
+                }
+            } else {
+                let mut i = 0;
+                while Type::Break != d.datatype()? {
+                    f(i, d)?;
+                    i += 1
+                }
+                d.skip()?
+            }
+        }
+        Type::Map | Type::MapIndef => {
+            if let Some(n) = d.map()? {
+                for _ in 0 .. n {
+                    let i = d.u32()?;
+                    f(i, d)?
+                }
+            } else {
+                while Type::Break != d.datatype()? {
+                    let i = d.u32()?;
+                    f(i, d)?
+                }
+                d.skip()?
+            }
+        }
+        t => return Err(Error::type_mismatch(t).with_message("expected array or map").at(p))
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Address, Client, Connect, Id, Message, Seq};
+    use std::borrow::Cow;
+
+    /// `Message { id: 1, data: Client::Ping }`
+    const PING: &[u8] = &[0x82, 0x01, 0x82, 0x01, 0x80];
+
+    /// `Message { id: 1, data: Client::Ping, seq: 7 }`
+    const PING_SEQ: &[u8] = &[0x83, 0x01, 0x82, 0x01, 0x80, 0x07];
+
+    /// `Connect { addr: "db:5432", use_half_close: true }` (map encoding)
+    const CONNECT_MAP: &[u8] = &[
+        0xa2, 0x00, 0x82, 0x01, 0x82, 0x62, 0x64, 0x62, 0x19, 0x15, 0x38, 0x01, 0xf5
+    ];
+
+    /// `Connect { addr: "db:5432", use_half_close: true }` (array encoding)
+    const CONNECT_ARRAY: &[u8] = &[
+        0x82, 0x82, 0x01, 0x82, 0x62, 0x64, 0x62, 0x19, 0x15, 0x38, 0xf5
+    ];
+
+    #[test]
+    fn message_encoding() {
+        let m = Message::new_with_id(Id::from(1), Client::Ping);
+        assert_eq!(PING, &minicbor::to_vec(&m).unwrap()[..]);
+        let m = m.with_seq(Seq::from(7));
+        assert_eq!(PING_SEQ, &minicbor::to_vec(&m).unwrap()[..])
+    }
+
+    #[test]
+    fn message_without_seq() {
+        let m: Message<Client> = minicbor::decode(PING).unwrap();
+        assert_eq!(Id::from(1), m.id);
+        assert!(matches!(m.data, Some(Client::Ping)));
+        assert!(m.seq.is_none())
+    }
+
+    #[test]
+    fn connect_encoding() {
+        let c = Connect {
+            addr: Address::Name(Cow::Borrowed("db"), 5432),
+            use_half_close: Some(true),
+            scheme: None
+        };
+        assert_eq!(CONNECT_MAP, &minicbor::to_vec(&c).unwrap()[..])
+    }
+
+    #[test]
+    fn connect_array_and_map() {
+        for bytes in [CONNECT_MAP, CONNECT_ARRAY] {
+            let c: Connect = minicbor::decode(bytes).unwrap();
+            assert_eq!(Address::Name(Cow::Borrowed("db"), 5432), c.addr);
+            assert_eq!(Some(true), c.use_half_close);
+            assert!(c.scheme.is_none())
+        }
+    }
+}
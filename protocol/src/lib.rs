@@ -1,7 +1,14 @@
 mod agentid;
+mod seq;
+
+pub mod compat;
+
+#[cfg(any(test, feature = "testing"))]
+mod testing;
 
 use sealed_boxes::Data;
-use minicbor::{Decode, Encode};
+use minicbor::{Decode, Decoder, Encode};
+use minicbor::decode;
 use minicbor::bytes::ByteSlice;
 use rand_core::{OsRng, RngCore};
 use serde::Serialize;
@@ -11,29 +18,39 @@ use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
 
 pub use agentid::AgentId;
+pub use compat::PROTOCOL_VERSION;
+pub use seq::{Order, Seq, SeqCheck, SeqGen};
 
 /// A generic message.
-#[derive(Debug, Decode, Encode)]
+#[derive(Debug, Clone, Decode, Encode)]
 #[non_exhaustive]
 pub struct Message<D> {
     /// The identifier of this message.
     #[n(0)] pub id: Id,
     /// The payload data of this message.
-    #[n(1)] pub data: Option<D>
+    #[n(1)] pub data: Option<D>,
+    /// The optional sequence number of this message.
+    #[n(2)] pub seq: Option<Seq>
 }
 
 impl<D> Message<D> {
     pub fn new(data: D) -> Self {
-        Message { id: Id::fresh(), data: Some(data) }
+        Message { id: Id::fresh(), data: Some(data), seq: None }
     }
 
     pub fn new_with_id(id: Id, data: D) -> Self {
-        Message { id, data: Some(data) }
+        Message { id, data: Some(data), seq: None }
+    }
+
+    /// Set the sequence number of this message.
+    pub fn with_seq(mut self, seq: Seq) -> Self {
+        self.seq = Some(seq);
+        self
     }
 }
 
 /// Payload of a server control message.
-#[derive(Decode, Encode)]
+#[derive(Clone, Decode, Encode)]
 pub enum Server<'a> {
     /// Ask the client to answer with a `Pong`.
     #[n(0)] Ping,
@@ -60,7 +77,9 @@ pub enum Server<'a> {
     /// Test reachability of upstream system.
     #[n(4)] Test {
         /// The upstream address.
-        #[b(0)] addr: Address<'a>
+        #[b(0)] addr: Address<'a>,
+        /// The transport scheme to use (None = tcp).
+        #[n(1)] scheme: Option<Scheme>
     },
 
     /// Open a new connection and drain the existing one.
@@ -73,7 +92,20 @@ pub enum Server<'a> {
     },
 
     /// The server has accepted the client.
-    #[n(7)] Accepted
+    #[n(7)] Accepted,
+
+    /// Advise the client to limit the rate of new streams.
+    ///
+    /// Sent by the gateway as back-pressure mechanism. A new advisory for
+    /// the same address (or all addresses) replaces the previous one.
+    #[n(8)] RateLimitAdvisory {
+        /// The max. number of new streams per second (0 = lift the limit).
+        #[n(0)] rate: u32,
+        /// The number of seconds the limit applies.
+        #[n(1)] seconds: u64,
+        /// Only limit streams to this address (None = all streams).
+        #[b(2)] addr: Option<Address<'a>>
+    }
 }
 
 // Custom impl to skip over sensitive data.
@@ -88,27 +120,38 @@ impl fmt::Debug for Server<'_> {
                 f.debug_struct("Challenge").finish(),
             Server::Terminate { reason } =>
                 f.debug_struct("Terminate").field("reason", reason).finish(),
-            Server::Test { addr } =>
-                f.debug_struct("Test").field("addr", addr).finish(),
+            Server::Test { addr, scheme } =>
+                f.debug_struct("Test")
+                 .field("addr", addr)
+                 .field("scheme", scheme)
+                 .finish(),
             Server::SwitchToNewConnection =>
                 f.debug_struct("SwitchToNewConnection").finish(),
             Server::Error { msg } =>
                 f.debug_struct("Error").field("msg", msg).finish(),
             Server::Accepted =>
-                f.debug_tuple("Accepted").finish()
+                f.debug_tuple("Accepted").finish(),
+            Server::RateLimitAdvisory { rate, seconds, addr } =>
+                f.debug_struct("RateLimitAdvisory")
+                 .field("rate", rate)
+                 .field("seconds", seconds)
+                 .field("addr", addr)
+                 .finish()
         }
     }
 }
 
 /// Payload of a client control message.
-#[derive(Decode, Encode)]
+#[derive(Clone, Decode, Encode)]
 pub enum Client<'a> {
     /// Initial client message.
     #[n(0)] Hello {
         /// The client's public key.
         #[b(0)] pubkey: Cow<'a, ByteSlice>,
         /// The version of this agent.
-        #[n(1)] agent_version: Version
+        #[n(1)] agent_version: Version,
+        /// The protocol version of this agent (None = 1).
+        #[n(2)] protocol_version: Option<u32>
     },
 
     /// Ask the server to answer with a `Pong`.
@@ -162,8 +205,11 @@ impl fmt::Debug for Client<'_> {
                 f.debug_tuple("Ping").finish(),
             Client::Pong { re } =>
                 f.debug_struct("Pong").field("re", re).finish(),
-            Client::Hello { agent_version, pubkey: _ } =>
-                f.debug_struct("Hello").field("agent_version", agent_version).finish(),
+            Client::Hello { agent_version, protocol_version, pubkey: _ } =>
+                f.debug_struct("Hello")
+                 .field("agent_version", agent_version)
+                 .field("protocol_version", protocol_version)
+                 .finish(),
             Client::Response { re, text: _ } =>
                 f.debug_struct("Response").field("re", re).finish(),
             Client::Error { re, code, msg } =>
@@ -186,13 +232,130 @@ impl fmt::Debug for Client<'_> {
 }
 
 /// Establish connection to the given address and transfer data back and forth.
-#[derive(Debug, Decode, Encode)]
+///
+/// Decodes from map and (older) array encodings.
+#[derive(Debug, Clone, Encode)]
 #[cbor(map)]
 pub struct Connect<'a> {
     /// The address to connect to.
     #[b(0)] pub addr: Address<'a>,
     /// The connection uses half-close (None = false).
-    #[n(1)] pub use_half_close: Option<bool>
+    #[n(1)] pub use_half_close: Option<bool>,
+    /// The transport scheme to use (None = tcp).
+    #[n(2)] pub scheme: Option<Scheme>
+}
+
+impl<'b, C> Decode<'b, C> for Connect<'b> {
+    fn decode(d: &mut Decoder<'b>, ctx: &mut C) -> Result<Self, decode::Error> {
+        let p = d.position();
+        let mut addr = None;
+        let mut use_half_close = None;
+        let mut scheme = None;
+        compat::decode_fields(d, |i, d| {
+            match i {
+                0 => addr = Some(d.decode_with(ctx)?),
+                1 => use_half_close = d.decode_with(ctx)?,
+                2 => scheme = d.decode_with(ctx)?,
+                _ => d.skip()?
+            }
+            Ok(())
+        })?;
+        Ok(Connect {
+            addr: addr.ok_or_else(|| decode::Error::missing_value(0).at(p))?,
+            use_half_close,
+            scheme
+        })
+    }
+}
+
+/// Second generation connect request with extensible options.
+///
+/// Its encoding is a superset of `Connect`, i.e. agents which only know
+/// `Connect` ignore the options. Unknown options are skipped when decoding,
+/// so new per-stream behaviour does not require new protocol variants.
+#[derive(Debug, Clone, Encode)]
+#[cbor(map)]
+pub struct ConnectV2<'a> {
+    /// The address to connect to.
+    #[b(0)] pub addr: Address<'a>,
+    /// The connection uses half-close (None = false).
+    #[n(1)] pub use_half_close: Option<bool>,
+    /// The transport scheme to use (None = tcp).
+    #[n(2)] pub scheme: Option<Scheme>,
+    /// Additional connection options.
+    #[n(3)] pub options: ConnectOptions
+}
+
+impl<'b, C> Decode<'b, C> for ConnectV2<'b> {
+    fn decode(d: &mut Decoder<'b>, ctx: &mut C) -> Result<Self, decode::Error> {
+        let p = d.position();
+        let mut addr = None;
+        let mut use_half_close = None;
+        let mut scheme = None;
+        let mut options = ConnectOptions::default();
+        compat::decode_fields(d, |i, d| {
+            match i {
+                0 => addr = Some(d.decode_with(ctx)?),
+                1 => use_half_close = d.decode_with(ctx)?,
+                2 => scheme = d.decode_with(ctx)?,
+                3 => options = d.decode_with(ctx)?,
+                _ => d.skip()?
+            }
+            Ok(())
+        })?;
+        Ok(ConnectV2 {
+            addr: addr.ok_or_else(|| decode::Error::missing_value(0).at(p))?,
+            use_half_close,
+            scheme,
+            options
+        })
+    }
+}
+
+impl<'a> From<Connect<'a>> for ConnectV2<'a> {
+    fn from(c: Connect<'a>) -> Self {
+        ConnectV2 {
+            addr: c.addr,
+            use_half_close: c.use_half_close,
+            scheme: c.scheme,
+            options: ConnectOptions::default()
+        }
+    }
+}
+
+/// Options of a `ConnectV2` request.
+///
+/// All options are optional and unknown ones are ignored.
+#[derive(Debug, Clone, Default, Decode, Encode, PartialEq, Eq)]
+#[cbor(map)]
+#[non_exhaustive]
+pub struct ConnectOptions {
+    /// Compress the data transferred.
+    #[n(0)] pub compression: Option<Compression>,
+    /// Relative priority of the stream (higher = more important).
+    #[n(1)] pub priority: Option<u8>,
+    /// TCP keepalive time in seconds (0 = disable keepalive).
+    #[n(2)] pub keepalive: Option<u64>,
+    /// Send a PROXY protocol header to the upstream system.
+    #[n(3)] pub proxy_protocol: Option<ProxyProtocol>,
+    /// Close the connection after this many seconds without data transfer.
+    #[n(4)] pub idle_timeout: Option<u64>
+}
+
+/// Compression algorithms.
+#[derive(Copy, Clone, Debug, Decode, Encode, PartialEq, Eq)]
+#[cbor(index_only)]
+pub enum Compression {
+    #[n(0)] Deflate,
+    #[n(1)] Zstd
+}
+
+/// PROXY protocol versions.
+#[derive(Copy, Clone, Debug, Decode, Encode, PartialEq, Eq)]
+#[cbor(index_only)]
+pub enum ProxyProtocol {
+    #[n(0)] V1,
+    #[n(1)] V2
 }
 
 /// A network address.
@@ -201,57 +364,130 @@ pub enum Address<'a> {
     /// IP address and port number.
     #[n(0)] Addr(#[n(0)] SocketAddr),
     /// A domain name to be resolved with optional port number.
-    #[n(1)] Name(#[b(0)] Cow<'a, str>, #[n(1)] u16)
+    #[n(1)] Name(#[b(0)] Cow<'a, str>, #[n(1)] u16),
+    /// A file system path, e.g. of a Unix domain socket.
+    #[n(2)] Path(#[b(0)] Cow<'a, str>)
 }
 
 impl<'a> Address<'a> {
     pub fn to_owned<'b>(&self) -> Address<'b> {
         match self {
             Address::Addr(a)    => Address::Addr(*a),
-            Address::Name(n, p) => Address::Name(Cow::Owned(n.as_ref().to_owned()), *p)
+            Address::Name(n, p) => Address::Name(Cow::Owned(n.as_ref().to_owned()), *p),
+            Address::Path(p)    => Address::Path(Cow::Owned(p.as_ref().to_owned()))
         }
     }
 
     pub fn into_owned<'b>(self) -> Address<'b> {
         match self {
             Address::Addr(a)    => Address::Addr(a),
-            Address::Name(n, p) => Address::Name(Cow::Owned(n.into_owned()), p)
+            Address::Name(n, p) => Address::Name(Cow::Owned(n.into_owned()), p),
+            Address::Path(p)    => Address::Path(Cow::Owned(p.into_owned()))
         }
     }
 
     pub fn borrow(&self) -> Address<'_> {
         match self {
             Address::Addr(a)    => Address::Addr(*a),
-            Address::Name(n, p) => Address::Name(Cow::Borrowed(n.borrow()), *p)
+            Address::Name(n, p) => Address::Name(Cow::Borrowed(n.borrow()), *p),
+            Address::Path(p)    => Address::Path(Cow::Borrowed(p.borrow()))
         }
     }
 
+    /// Parse an IP address or domain name (see [`normalize_name`]).
     pub fn read_owned<'b>(addr: String, port: u16) -> Address<'b> {
         if let Ok(ip) = IpAddr::from_str(&addr) {
             Address::Addr(SocketAddr::from((ip, port)))
         } else {
-            Address::Name(Cow::Owned(addr), port)
+            Address::Name(normalize_name(Cow::Owned(addr)), port)
         }
     }
 
+    /// Parse an IP address or domain name (see [`normalize_name`]).
     pub fn read_borrowed(addr: &'a str, port: u16) -> Address<'a> {
         if let Ok(ip) = IpAddr::from_str(addr) {
             Address::Addr(SocketAddr::from((ip, port)))
         } else {
-            Address::Name(Cow::Borrowed(addr), port)
+            Address::Name(normalize_name(Cow::Borrowed(addr)), port)
         }
     }
 }
 
+/// Normalize a domain name by removing a trailing dot and converting it to lowercase.
+///
+/// Already normalized names are returned unchanged without allocation.
+pub fn normalize_name(name: Cow<'_, str>) -> Cow<'_, str> {
+    let is_normal = !name.ends_with('.') && !name.bytes().any(|b| b.is_ascii_uppercase());
+    if is_normal {
+        return name
+    }
+    let trimmed = name.strip_suffix('.').unwrap_or(&name);
+    Cow::Owned(trimmed.to_ascii_lowercase())
+}
+
 impl fmt::Display for Address<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Address::Addr(a)    => a.fmt(f),
-            Address::Name(n, p) => write!(f, "{}:{}", n, p)
+            Address::Name(n, p) => write!(f, "{}:{}", n, p),
+            Address::Path(p)    => f.write_str(p)
         }
     }
 }
 
+/// The transport scheme used towards an address.
+#[derive(Copy, Clone, Debug, Default, Decode, Encode, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[cbor(index_only)]
+pub enum Scheme {
+    /// A TCP connection.
+    #[default]
+    #[n(0)] Tcp,
+    /// A TLS connection originated by the agent.
+    #[n(1)] Tls,
+    /// UDP datagrams.
+    #[n(2)] Udp,
+    /// A Unix domain socket connection.
+    #[n(3)] Unix
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scheme::Tcp  => f.write_str("tcp"),
+            Scheme::Tls  => f.write_str("tls"),
+            Scheme::Udp  => f.write_str("udp"),
+            Scheme::Unix => f.write_str("unix")
+        }
+    }
+}
+
+impl FromStr for Scheme {
+    type Err = InvalidScheme;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tcp"  => Ok(Scheme::Tcp),
+            "tls"  => Ok(Scheme::Tls),
+            "udp"  => Ok(Scheme::Udp),
+            "unix" => Ok(Scheme::Unix),
+            _      => Err(InvalidScheme(s.into()))
+        }
+    }
+}
+
+/// Error caused by parsing invalid or unknown schemes.
+#[derive(Clone, Debug)]
+pub struct InvalidScheme(String);
+
+impl fmt::Display for InvalidScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid scheme: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidScheme {}
+
 /// The challenge-response ciphertext used when authenticating clients.
 #[derive(Debug, Clone, Decode, Encode)]
 #[cbor(transparent)]
@@ -264,7 +500,7 @@ impl From<Data<32>> for CipherText {
 }
 
 /// Possible error codes.
-#[derive(Copy, Clone, Debug, Decode, Encode, Serialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Decode, Encode, Serialize)]
 #[serde(rename_all = "kebab-case")]
 #[cbor(index_only)]
 pub enum ErrorCode {
@@ -273,7 +509,13 @@ pub enum ErrorCode {
     /// The requested address is blocked by the client configuration.
     #[n(1)] AddressNotAllowed,
     /// The server challenge can not be decrypted.
-    #[n(2)] DecryptionFailed
+    #[n(2)] DecryptionFailed,
+    /// The requested transport scheme is not supported.
+    #[n(3)] UnsupportedScheme,
+    /// The requested address is explicitly denied by the client configuration.
+    #[n(4)] AddressDenied,
+    /// The client has reached its max. number of concurrent streams.
+    #[n(5)] TooManyStreams
 }
 
 impl fmt::Display for ErrorCode {
@@ -281,13 +523,16 @@ impl fmt::Display for ErrorCode {
         match self {
             ErrorCode::CouldNotConnect   => f.write_str("could not connect"),
             ErrorCode::AddressNotAllowed => f.write_str("address not allowed"),
-            ErrorCode::DecryptionFailed  => f.write_str("decryption failed")
+            ErrorCode::DecryptionFailed  => f.write_str("decryption failed"),
+            ErrorCode::UnsupportedScheme => f.write_str("unsupported scheme"),
+            ErrorCode::AddressDenied     => f.write_str("address denied"),
+            ErrorCode::TooManyStreams    => f.write_str("too many streams")
         }
     }
 }
 
 /// Possible reasons for connection termination.
-#[derive(Copy, Clone, Debug, Decode, Encode, Serialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Decode, Encode, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum Reason {
     /// The agent failed to authenticate itself.
@@ -0,0 +1,134 @@
+use minicbor::{Decode, Encode};
+use std::fmt;
+
+/// A message sequence number.
+#[derive(Copy, Clone, Decode, Encode, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cbor(transparent)]
+pub struct Seq(#[n(0)] u64);
+
+impl Seq {
+    /// Get the numeric value of this sequence number.
+    pub fn numeric(self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for Seq {
+    fn from(n: u64) -> Self {
+        Seq(n)
+    }
+}
+
+impl fmt::Display for Seq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl fmt::Debug for Seq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Generator of monotonically increasing sequence numbers.
+#[derive(Debug, Default)]
+pub struct SeqGen(u64);
+
+impl SeqGen {
+    pub fn new() -> Self {
+        SeqGen(0)
+    }
+
+    /// Get the next sequence number.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Seq {
+        let s = Seq(self.0);
+        self.0 = self.0.wrapping_add(1);
+        s
+    }
+}
+
+/// The result of checking a received sequence number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// The first sequence number seen.
+    First,
+    /// The sequence number directly follows the previous one.
+    Next,
+    /// Sequence numbers between `expected` and `actual` are missing.
+    Gap {
+        expected: Seq,
+        actual: Seq
+    },
+    /// The sequence number is not greater than the latest one seen.
+    Duplicate {
+        latest: Seq,
+        actual: Seq
+    }
+}
+
+/// Keeps track of received sequence numbers to detect gaps and duplicates.
+#[derive(Debug, Default)]
+pub struct SeqCheck {
+    latest: Option<Seq>
+}
+
+impl SeqCheck {
+    pub fn new() -> Self {
+        SeqCheck { latest: None }
+    }
+
+    /// Check the given sequence number against the latest one seen.
+    ///
+    /// Duplicates do not update the latest sequence number.
+    pub fn check(&mut self, actual: Seq) -> Order {
+        let order = match self.latest {
+            None => Order::First,
+            Some(latest) if actual <= latest => return Order::Duplicate { latest, actual },
+            Some(latest) => {
+                let expected = Seq(latest.0 + 1);
+                if actual == expected {
+                    Order::Next
+                } else {
+                    Order::Gap { expected, actual }
+                }
+            }
+        };
+        self.latest = Some(actual);
+        order
+    }
+
+    /// The latest sequence number seen (if any).
+    pub fn latest(&self) -> Option<Seq> {
+        self.latest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quickcheck::quickcheck;
+    use super::{Order, Seq, SeqCheck, SeqGen};
+
+    #[test]
+    fn generated_sequence_is_in_order() {
+        fn prop(n: u8) -> bool {
+            let mut g = SeqGen::new();
+            let mut c = SeqCheck::new();
+            let first = c.check(g.next()) == Order::First;
+            first && (0 .. n).all(|_| c.check(g.next()) == Order::Next)
+        }
+        quickcheck(prop as fn(_) -> bool)
+    }
+
+    #[test]
+    fn gaps_and_duplicates() {
+        let mut c = SeqCheck::new();
+        assert_eq!(Order::First, c.check(Seq(1)));
+        assert_eq!(Order::Gap { expected: Seq(2), actual: Seq(5) }, c.check(Seq(5)));
+        assert_eq!(Order::Duplicate { latest: Seq(5), actual: Seq(3) }, c.check(Seq(3)));
+        assert_eq!(Order::Duplicate { latest: Seq(5), actual: Seq(5) }, c.check(Seq(5)));
+        assert_eq!(Order::Next, c.check(Seq(6)));
+        assert_eq!(Some(Seq(6)), c.latest())
+    }
+}
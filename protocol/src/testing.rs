@@ -0,0 +1,251 @@
+//! `Arbitrary` instances of protocol types.
+//!
+//! Enabled with feature `testing`.
+
+use crate::{Address, AgentId, CipherText, Client, Compression, Connect, ConnectOptions};
+use crate::{ConnectV2, ErrorCode, Id, Message, ProxyProtocol, Reason, Scheme, Seq, Server};
+use crate::Version;
+use minicbor::bytes::ByteVec;
+use quickcheck::{Arbitrary, Gen};
+use sealed_boxes::Data;
+use std::borrow::Cow;
+use std::net::SocketAddr;
+
+impl Arbitrary for Id {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Id::from(u64::arbitrary(g))
+    }
+}
+
+impl Arbitrary for Seq {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Seq::from(u64::arbitrary(g))
+    }
+}
+
+impl Arbitrary for Version {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Version {
+            major: u64::arbitrary(g),
+            minor: u64::arbitrary(g),
+            patch: u64::arbitrary(g)
+        }
+    }
+}
+
+impl Arbitrary for AgentId {
+    fn arbitrary(g: &mut Gen) -> Self {
+        AgentId::from(&array::<32>(g)[..])
+    }
+}
+
+impl Arbitrary for Scheme {
+    fn arbitrary(g: &mut Gen) -> Self {
+        *g.choose(&[Scheme::Tcp, Scheme::Tls, Scheme::Udp, Scheme::Unix]).unwrap()
+    }
+}
+
+impl Arbitrary for ErrorCode {
+    fn arbitrary(g: &mut Gen) -> Self {
+        *g.choose(&[
+            ErrorCode::CouldNotConnect,
+            ErrorCode::AddressNotAllowed,
+            ErrorCode::DecryptionFailed,
+            ErrorCode::UnsupportedScheme,
+            ErrorCode::AddressDenied,
+            ErrorCode::TooManyStreams
+        ]).unwrap()
+    }
+}
+
+impl Arbitrary for Reason {
+    fn arbitrary(g: &mut Gen) -> Self {
+        *g.choose(&[
+            Reason::Unauthenticated,
+            Reason::Unauthorized,
+            Reason::UnsupportedVersion,
+            Reason::Disabled
+        ]).unwrap()
+    }
+}
+
+impl Arbitrary for Address<'static> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        match g.choose(&[0, 1, 2]).unwrap() {
+            0 => Address::Addr(SocketAddr::arbitrary(g)),
+            1 => Address::Name(Cow::Owned(String::arbitrary(g)), u16::arbitrary(g)),
+            _ => Address::Path(Cow::Owned(String::arbitrary(g)))
+        }
+    }
+}
+
+impl Arbitrary for CipherText {
+    fn arbitrary(g: &mut Gen) -> Self {
+        CipherText(Data { key: array(g), data: array(g), tag: array(g) })
+    }
+}
+
+impl Arbitrary for Connect<'static> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Connect {
+            addr: Address::arbitrary(g),
+            use_half_close: Option::arbitrary(g),
+            scheme: Option::arbitrary(g)
+        }
+    }
+}
+
+impl Arbitrary for Compression {
+    fn arbitrary(g: &mut Gen) -> Self {
+        *g.choose(&[Compression::Deflate, Compression::Zstd]).unwrap()
+    }
+}
+
+impl Arbitrary for ProxyProtocol {
+    fn arbitrary(g: &mut Gen) -> Self {
+        *g.choose(&[ProxyProtocol::V1, ProxyProtocol::V2]).unwrap()
+    }
+}
+
+impl Arbitrary for ConnectOptions {
+    fn arbitrary(g: &mut Gen) -> Self {
+        ConnectOptions {
+            compression: Option::arbitrary(g),
+            priority: Option::arbitrary(g),
+            keepalive: Option::arbitrary(g),
+            proxy_protocol: Option::arbitrary(g),
+            idle_timeout: Option::arbitrary(g)
+        }
+    }
+}
+
+impl Arbitrary for ConnectV2<'static> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        ConnectV2 {
+            addr: Address::arbitrary(g),
+            use_half_close: Option::arbitrary(g),
+            scheme: Option::arbitrary(g),
+            options: ConnectOptions::arbitrary(g)
+        }
+    }
+}
+
+impl Arbitrary for Server<'static> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        match g.choose(&[0, 1, 2, 3, 4, 5, 6, 7, 8]).unwrap() {
+            0 => Server::Ping,
+            1 => Server::Pong { re: Id::arbitrary(g) },
+            2 => Server::Challenge { text: Box::new(CipherText::arbitrary(g)) },
+            3 => Server::Terminate { reason: Reason::arbitrary(g) },
+            4 => Server::Test { addr: Address::arbitrary(g), scheme: Option::arbitrary(g) },
+            5 => Server::SwitchToNewConnection,
+            6 => Server::Error { msg: Cow::Owned(String::arbitrary(g)) },
+            7 => Server::Accepted,
+            _ => Server::RateLimitAdvisory {
+                rate: u32::arbitrary(g),
+                seconds: u64::arbitrary(g),
+                addr: Option::arbitrary(g)
+            }
+        }
+    }
+}
+
+impl Arbitrary for Client<'static> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        match g.choose(&[0, 1, 2, 3, 4, 5, 6]).unwrap() {
+            0 => Client::Hello {
+                pubkey: Cow::Owned(ByteVec::from(array::<32>(g).to_vec())),
+                agent_version: Version::arbitrary(g),
+                protocol_version: Option::arbitrary(g)
+            },
+            1 => Client::Ping,
+            2 => Client::Pong { re: Id::arbitrary(g) },
+            3 => Client::Response {
+                re: Id::arbitrary(g),
+                text: Cow::Owned(ByteVec::from(Vec::arbitrary(g)))
+            },
+            4 => Client::Error {
+                re: Id::arbitrary(g),
+                code: Option::arbitrary(g),
+                msg: Option::<String>::arbitrary(g).map(Cow::Owned)
+            },
+            5 => Client::Test { re: Id::arbitrary(g), code: Option::arbitrary(g) },
+            _ => Client::SwitchingConnection { re: Id::arbitrary(g) }
+        }
+    }
+}
+
+impl<D: Arbitrary> Arbitrary for Message<D> {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Message {
+            id: Id::arbitrary(g),
+            data: Option::arbitrary(g),
+            seq: Option::arbitrary(g)
+        }
+    }
+}
+
+/// Generate an arbitrary byte array.
+fn array<const N: usize>(g: &mut Gen) -> [u8; N] {
+    let mut a = [0; N];
+    for b in &mut a {
+        *b = u8::arbitrary(g)
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Client, Connect, ConnectV2, Message, Server};
+    use quickcheck::quickcheck;
+
+    #[test]
+    fn server_message_roundtrip() {
+        fn prop(m: Message<Server<'static>>) -> bool {
+            let a = minicbor::to_vec(&m).unwrap();
+            let d: Message<Server<'_>> = minicbor::decode(&a).unwrap();
+            a == minicbor::to_vec(&d).unwrap()
+        }
+        quickcheck(prop as fn(_) -> bool)
+    }
+
+    #[test]
+    fn client_message_roundtrip() {
+        fn prop(m: Message<Client<'static>>) -> bool {
+            let a = minicbor::to_vec(&m).unwrap();
+            let d: Message<Client<'_>> = minicbor::decode(&a).unwrap();
+            a == minicbor::to_vec(&d).unwrap()
+        }
+        quickcheck(prop as fn(_) -> bool)
+    }
+
+    #[test]
+    fn connect_message_roundtrip() {
+        fn prop(m: Message<Connect<'static>>) -> bool {
+            let a = minicbor::to_vec(&m).unwrap();
+            let d: Message<Connect<'_>> = minicbor::decode(&a).unwrap();
+            a == minicbor::to_vec(&d).unwrap()
+        }
+        quickcheck(prop as fn(_) -> bool)
+    }
+
+    #[test]
+    fn connect_v2_message_roundtrip() {
+        fn prop(m: Message<ConnectV2<'static>>) -> bool {
+            let a = minicbor::to_vec(&m).unwrap();
+            let d: Message<ConnectV2<'_>> = minicbor::decode(&a).unwrap();
+            a == minicbor::to_vec(&d).unwrap()
+        }
+        quickcheck(prop as fn(_) -> bool)
+    }
+
+    #[test]
+    fn connect_decodes_as_connect_v2() {
+        fn prop(c: Connect<'static>) -> bool {
+            let a = minicbor::to_vec(&c).unwrap();
+            let d: ConnectV2<'_> = minicbor::decode(&a).unwrap();
+            a == minicbor::to_vec(&Connect { addr: d.addr, use_half_close: d.use_half_close, scheme: d.scheme }).unwrap()
+        }
+        quickcheck(prop as fn(_) -> bool)
+    }
+}
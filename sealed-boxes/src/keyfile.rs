@@ -0,0 +1,238 @@
+//! A versioned file format for secret keys.
+//!
+//! Layout (42 bytes):
+//!
+//! `magic ("CLVK") || version (1) || algorithm || key (32) || crc32 (big-endian)`
+//!
+//! The CRC-32 checksum covers all preceding bytes.
+
+use crate::{SecretKey, SecretKeyLegacy, K};
+use std::convert::TryInto;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"CLVK";
+const VERSION: u8 = 1;
+const LEN: usize = MAGIC.len() + 2 + K + 4;
+
+/// The algorithm a key is used with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Algorithm {
+    /// X25519 keys used with the current crypto_box implementation.
+    CryptoBox,
+    /// X25519 keys used with the legacy crypto_box 0.8 implementation.
+    CryptoBoxLegacy
+}
+
+impl Algorithm {
+    fn id(self) -> u8 {
+        match self {
+            Algorithm::CryptoBox       => 1,
+            Algorithm::CryptoBoxLegacy => 2
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Algorithm::CryptoBox),
+            2 => Some(Algorithm::CryptoBoxLegacy),
+            _ => None
+        }
+    }
+}
+
+/// The contents of a key file.
+#[derive(Clone)]
+pub struct KeyFile {
+    algorithm: Algorithm,
+    key: [u8; K]
+}
+
+impl KeyFile {
+    pub fn new(sk: &SecretKey) -> Self {
+        KeyFile { algorithm: Algorithm::CryptoBox, key: sk.to_bytes() }
+    }
+
+    pub fn legacy(sk: &SecretKeyLegacy) -> Self {
+        KeyFile { algorithm: Algorithm::CryptoBoxLegacy, key: sk.to_bytes() }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Get the secret key if used with the current crypto_box implementation.
+    pub fn secret_key(&self) -> Option<SecretKey> {
+        if self.algorithm == Algorithm::CryptoBox {
+            return Some(SecretKey::from(self.key))
+        }
+        None
+    }
+
+    /// Get the secret key if used with the legacy crypto_box implementation.
+    pub fn secret_key_legacy(&self) -> Option<SecretKeyLegacy> {
+        if self.algorithm == Algorithm::CryptoBoxLegacy {
+            return Some(SecretKeyLegacy::from(self.key))
+        }
+        None
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut v = Vec::with_capacity(LEN);
+        v.extend_from_slice(MAGIC);
+        v.push(VERSION);
+        v.push(self.algorithm.id());
+        v.extend_from_slice(&self.key);
+        let c = crc32(&v);
+        v.extend_from_slice(&c.to_be_bytes());
+        v
+    }
+
+    pub fn from_bytes(b: &[u8]) -> Result<Self, KeyFileError> {
+        if b.len() < MAGIC.len() || &b[.. MAGIC.len()] != MAGIC {
+            return Err(KeyFileError::Magic)
+        }
+        if b.len() != LEN {
+            return Err(KeyFileError::Length(b.len()))
+        }
+        let (data, crc) = b.split_at(LEN - 4);
+        if crc32(data).to_be_bytes() != crc {
+            return Err(KeyFileError::Checksum)
+        }
+        let version = data[MAGIC.len()];
+        if version != VERSION {
+            return Err(KeyFileError::Version(version))
+        }
+        let id = data[MAGIC.len() + 1];
+        let algorithm = Algorithm::from_id(id).ok_or(KeyFileError::Algorithm(id))?;
+        let key = data[MAGIC.len() + 2 ..].try_into().expect("data length = magic + 2 + K");
+        Ok(KeyFile { algorithm, key })
+    }
+
+    /// Write this key file to the given path.
+    ///
+    /// On Unix the file is created with permissions 0600.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut opts = fs::OpenOptions::new();
+        opts.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut opts, 0o600);
+        let mut file = opts.open(path)?;
+        file.write_all(&self.to_bytes())?;
+        file.sync_all()
+    }
+
+    /// Read a key file from the given path.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, KeyFileError> {
+        let b = fs::read(path)?;
+        KeyFile::from_bytes(&b)
+    }
+}
+
+impl fmt::Debug for KeyFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyFile").field("algorithm", &self.algorithm).finish()
+    }
+}
+
+/// Save a secret key to the given path.
+pub fn save_secret_key<P: AsRef<Path>>(path: P, sk: &SecretKey) -> io::Result<()> {
+    KeyFile::new(sk).save(path)
+}
+
+/// Load a secret key from the given path.
+pub fn load_secret_key<P: AsRef<Path>>(path: P) -> Result<SecretKey, KeyFileError> {
+    let f = KeyFile::load(path)?;
+    f.secret_key().ok_or(KeyFileError::Algorithm(f.algorithm.id()))
+}
+
+/// Errors when reading key files.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum KeyFileError {
+    /// An I/O error occurred.
+    Io(io::Error),
+    /// The data does not start with the expected magic bytes.
+    Magic,
+    /// The data has an unexpected length.
+    Length(usize),
+    /// The checksum does not match.
+    Checksum,
+    /// The format version is not supported.
+    Version(u8),
+    /// The algorithm is unknown or not applicable.
+    Algorithm(u8)
+}
+
+impl fmt::Display for KeyFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyFileError::Io(e)        => write!(f, "i/o error: {}", e),
+            KeyFileError::Magic        => f.write_str("not a key file"),
+            KeyFileError::Length(n)    => write!(f, "invalid key file length: {}", n),
+            KeyFileError::Checksum     => f.write_str("key file checksum mismatch"),
+            KeyFileError::Version(v)   => write!(f, "unsupported key file version: {}", v),
+            KeyFileError::Algorithm(a) => write!(f, "unsupported key algorithm: {}", a)
+        }
+    }
+}
+
+impl std::error::Error for KeyFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        if let KeyFileError::Io(e) = self {
+            return Some(e)
+        }
+        None
+    }
+}
+
+impl From<io::Error> for KeyFileError {
+    fn from(e: io::Error) -> Self {
+        KeyFileError::Io(e)
+    }
+}
+
+/// CRC-32 (IEEE 802.3) checksum.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in bytes {
+        crc ^= u32::from(*b);
+        for _ in 0 .. 8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask)
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gen_secret_key;
+    use super::*;
+
+    #[test]
+    fn checksum() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"))
+    }
+
+    #[test]
+    fn roundtrip() {
+        let sk = gen_secret_key();
+        let kf = KeyFile::from_bytes(&KeyFile::new(&sk).to_bytes()).unwrap();
+        assert_eq!(Algorithm::CryptoBox, kf.algorithm());
+        assert!(kf.secret_key_legacy().is_none());
+        assert_eq!(sk.to_bytes(), kf.secret_key().unwrap().to_bytes())
+    }
+
+    #[test]
+    fn corrupted() {
+        let mut b = KeyFile::new(&gen_secret_key()).to_bytes();
+        b[10] ^= 1;
+        assert!(matches!(KeyFile::from_bytes(&b), Err(KeyFileError::Checksum)));
+        assert!(matches!(KeyFile::from_bytes(&b[.. 20]), Err(KeyFileError::Length(20))));
+        assert!(matches!(KeyFile::from_bytes(b"hello"), Err(KeyFileError::Magic)))
+    }
+}
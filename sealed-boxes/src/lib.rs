@@ -5,14 +5,40 @@
 //! `ephemeral_pk || box(m, recipient_pk, ephemeral_sk, nonce=blake2b(ephemeral_pk || recipient_pk))`
 //!
 //! [1]: https://doc.libsodium.org/public-key_cryptography/sealed_boxes
+//!
+//! For interoperability with libsodium, [`encrypt_sodium`] and [`decrypt_sodium`]
+//! implement `crypto_box_seal` exactly, i.e. using XSalsa20-Poly1305 and the
+//! combined format `ephemeral_pk || tag || ciphertext`.
+//!
+//! The crate supports `no_std` environments with `alloc` if the default
+//! feature `std` is disabled. Functions which need randomness require the
+//! feature `getrandom` (enabled by `std`).
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
+#[cfg(feature = "std")]
+pub mod keyfile;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64;
 use crypto_box::{ChaChaBox, aead::AeadInPlace};
 use minicbor::{Decode, Encode};
-use rand_core::{OsRng, RngCore};
-use std::convert::TryInto;
+use core::convert::TryInto;
+use core::fmt;
+use rand_core::CryptoRngCore;
+
+#[cfg(feature = "getrandom")]
+use rand_core::OsRng;
 
 pub use crypto_box::{PublicKey, SecretKey, aead::Error};
 
+/// Number of bytes [`encrypt_sodium`] adds to a message.
+pub use crypto_box::SEALBYTES;
+
 // crypto_box 0.8
 pub use crypto_box_legacy::{PublicKey as PublicKeyLegacy, SecretKey as SecretKeyLegacy};
 use crypto_box_legacy::{ChaChaBox as ChaChaBoxLegacy, aead::AeadInPlace as AeadInPlaceLegacy};
@@ -41,36 +67,191 @@ pub struct Data<const N: usize> {
     pub tag: [u8; T]
 }
 
+impl<const N: usize> Data<N> {
+    /// The length of the byte encoding.
+    pub const LEN: usize = K + N + T;
+
+    /// Concatenate key, data and tag.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut v = Vec::with_capacity(Self::LEN);
+        v.extend_from_slice(&self.key);
+        v.extend_from_slice(&self.data);
+        v.extend_from_slice(&self.tag);
+        v
+    }
+
+    /// Split the given bytes into key, data and tag.
+    pub fn from_bytes(b: &[u8]) -> Result<Self, DecodeError> {
+        if b.len() != Self::LEN {
+            return Err(DecodeError::Length(b.len()))
+        }
+        let (key, rest) = b.split_at(K);
+        let (data, tag) = rest.split_at(N);
+        Ok(Data {
+            key: key.try_into().expect("key length = K"),
+            data: data.try_into().expect("data length = N"),
+            tag: tag.try_into().expect("tag length = T")
+        })
+    }
+
+    /// Encode as URL-safe base64 without padding.
+    pub fn to_base64(&self) -> String {
+        BASE64.encode(self.to_bytes())
+    }
+
+    /// Decode from URL-safe base64 without padding.
+    pub fn from_base64(s: &str) -> Result<Self, DecodeError> {
+        let b = BASE64.decode(s).map_err(DecodeError::Base64)?;
+        Data::from_bytes(&b)
+    }
+}
+
+/// Errors when decoding [`Data`] from bytes or base64.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DecodeError {
+    /// The input has an unexpected length.
+    Length(usize),
+    /// The input is not valid base64.
+    Base64(base64::DecodeError)
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Length(n) => write!(f, "invalid sealed box length: {}", n),
+            DecodeError::Base64(e) => write!(f, "invalid base64: {}", e)
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        if let DecodeError::Base64(e) = self {
+            return Some(e)
+        }
+        None
+    }
+}
+
 /// Generate a new random secret key.
+#[cfg(feature = "getrandom")]
 pub fn gen_secret_key() -> SecretKey {
-    SecretKey::from(fresh_array())
+    gen_secret_key_with_rng(&mut OsRng)
+}
+
+/// Generate a new secret key using the given random number generator.
+pub fn gen_secret_key_with_rng(rng: &mut impl CryptoRngCore) -> SecretKey {
+    SecretKey::from(fresh_array_with_rng(rng))
 }
 
 /// Generate a new random secret key.
+#[cfg(feature = "getrandom")]
 pub fn gen_secret_key_legacy() -> SecretKeyLegacy {
-    SecretKeyLegacy::from(fresh_array())
+    gen_secret_key_legacy_with_rng(&mut OsRng)
+}
+
+/// Generate a new secret key using the given random number generator.
+pub fn gen_secret_key_legacy_with_rng(rng: &mut impl CryptoRngCore) -> SecretKeyLegacy {
+    SecretKeyLegacy::from(fresh_array_with_rng(rng))
 }
 
 /// Generate a new random array.
+#[cfg(feature = "getrandom")]
 pub fn fresh_array<const N: usize>() -> [u8; N] {
+    fresh_array_with_rng(&mut OsRng)
+}
+
+/// Generate a new array using the given random number generator.
+pub fn fresh_array_with_rng<const N: usize>(rng: &mut impl CryptoRngCore) -> [u8; N] {
     let mut a = [0; N];
-    OsRng.fill_bytes(&mut a);
+    rng.fill_bytes(&mut a);
     a
 }
 
 /// Encrypt a message for the given public key.
-pub fn encrypt<const N: usize>(pk: &PublicKey, mut msg: [u8; N]) -> Result<Data<N>, Error> {
-    let es = gen_secret_key();
+#[cfg(feature = "getrandom")]
+pub fn encrypt<const N: usize>(pk: &PublicKey, msg: [u8; N]) -> Result<Data<N>, Error> {
+    encrypt_with_rng(&mut OsRng, pk, msg, &[])
+}
+
+/// Encrypt a message for the given public key and bind it to associated data.
+///
+/// The associated data is not part of the result, but the same data needs
+/// to be given to [`decrypt_with_ad`] for decryption to succeed.
+#[cfg(feature = "getrandom")]
+pub fn encrypt_with_ad<const N: usize>(pk: &PublicKey, msg: [u8; N], ad: &[u8]) -> Result<Data<N>, Error> {
+    encrypt_with_rng(&mut OsRng, pk, msg, ad)
+}
+
+/// Like [`encrypt_with_ad`] but the ephemeral key is generated with the
+/// given random number generator.
+pub fn encrypt_with_rng<const N: usize>(rng: &mut impl CryptoRngCore, pk: &PublicKey, msg: [u8; N], ad: &[u8]) -> Result<Data<N>, Error> {
+    seal(&gen_secret_key_with_rng(rng), pk, msg, ad)
+}
+
+/// Encrypt a batch of messages, each for its own public key.
+///
+/// If all public keys are distinct, a single ephemeral key is used for the
+/// whole batch (nonces differ per recipient), otherwise every message gets
+/// its own ephemeral key. With feature `rayon` messages are encrypted in
+/// parallel.
+#[cfg(feature = "getrandom")]
+pub fn encrypt_batch<const N: usize>(msgs: &[(PublicKey, [u8; N])]) -> Result<Vec<Data<N>>, Error> {
+    encrypt_batch_with_rng(&mut OsRng, msgs)
+}
+
+/// Like [`encrypt_batch`] but ephemeral keys are generated with the given
+/// random number generator.
+pub fn encrypt_batch_with_rng<const N: usize>(rng: &mut impl CryptoRngCore, msgs: &[(PublicKey, [u8; N])]) -> Result<Vec<Data<N>>, Error> {
+    let mut keys = msgs.iter().map(|(pk, _)| pk.as_bytes()).collect::<Vec<_>>();
+    keys.sort_unstable();
+    keys.dedup();
+    if keys.len() != msgs.len() {
+        return msgs.iter().map(|(pk, m)| encrypt_with_rng(rng, pk, *m, &[])).collect()
+    }
+    let es = gen_secret_key_with_rng(rng);
+    batch(msgs, |(pk, m)| seal(&es, pk, *m, &[])).into_iter().collect()
+}
+
+/// Decrypt a batch of messages using the given secret key.
+///
+/// With feature `rayon` messages are decrypted in parallel.
+pub fn decrypt_batch<const N: usize>(sk: &SecretKey, data: &[Data<N>]) -> Vec<Result<[u8; N], Error>> {
+    batch(data, |d| decrypt(sk, *d))
+}
+
+/// Encrypt a message using the given ephemeral secret key.
+fn seal<const N: usize>(es: &SecretKey, pk: &PublicKey, mut msg: [u8; N], ad: &[u8]) -> Result<Data<N>, Error> {
     let ep = es.public_key();
     let nc = nonce(ep.as_bytes(), pk.as_bytes()).into();
-    let cb = ChaChaBox::new(pk, &es);
-    let tg = AeadInPlace::encrypt_in_place_detached(&cb, &nc, &[], &mut msg[..])?;
+    let cb = ChaChaBox::new(pk, es);
+    let tg = AeadInPlace::encrypt_in_place_detached(&cb, &nc, ad, &mut msg[..])?;
     Ok(Data { key: *ep.as_bytes(), data: msg, tag: tg.into() })
 }
 
+#[cfg(feature = "rayon")]
+fn batch<T: Sync, R: Send, F: Fn(&T) -> R + Sync + Send>(xs: &[T], f: F) -> Vec<R> {
+    use rayon::prelude::*;
+    xs.par_iter().map(f).collect()
+}
+
+#[cfg(not(feature = "rayon"))]
+fn batch<T, R, F: Fn(&T) -> R>(xs: &[T], f: F) -> Vec<R> {
+    xs.iter().map(f).collect()
+}
+
 /// Encrypt a message for the given public key.
-pub fn encrypt_legacy<const N: usize>(pk: &PublicKeyLegacy, mut msg: [u8; N]) -> Result<Data<N>, Error> {
-    let es = gen_secret_key_legacy();
+#[cfg(feature = "getrandom")]
+pub fn encrypt_legacy<const N: usize>(pk: &PublicKeyLegacy, msg: [u8; N]) -> Result<Data<N>, Error> {
+    encrypt_legacy_with_rng(&mut OsRng, pk, msg)
+}
+
+/// Like [`encrypt_legacy`] but the ephemeral key is generated with the
+/// given random number generator.
+pub fn encrypt_legacy_with_rng<const N: usize>(rng: &mut impl CryptoRngCore, pk: &PublicKeyLegacy, mut msg: [u8; N]) -> Result<Data<N>, Error> {
+    let es = gen_secret_key_legacy_with_rng(rng);
     let ep = es.public_key();
     let nc = nonce(ep.as_bytes(), pk.as_bytes()).into();
     let cb = ChaChaBoxLegacy::new(pk, &es);
@@ -79,15 +260,55 @@ pub fn encrypt_legacy<const N: usize>(pk: &PublicKeyLegacy, mut msg: [u8; N]) ->
 }
 
 /// Decrypt a message using the given secret key.
-pub fn decrypt<const N: usize>(sk: &SecretKey, mut data: Data<N>) -> Result<[u8; N], Error> {
+pub fn decrypt<const N: usize>(sk: &SecretKey, data: Data<N>) -> Result<[u8; N], Error> {
+    decrypt_with_ad(sk, data, &[])
+}
+
+/// Decrypt a message bound to associated data using the given secret key.
+pub fn decrypt_with_ad<const N: usize>(sk: &SecretKey, mut data: Data<N>, ad: &[u8]) -> Result<[u8; N], Error> {
     let ep = PublicKey::from(data.key);
     let tg = data.tag.into();
     let nc = nonce(ep.as_bytes(), sk.public_key().as_bytes()).into();
     let cb = ChaChaBox::new(&ep, sk);
-    AeadInPlace::decrypt_in_place_detached(&cb, &nc, &[], &mut data.data, &tg)?;
+    AeadInPlace::decrypt_in_place_detached(&cb, &nc, ad, &mut data.data, &tg)?;
     Ok(data.data)
 }
 
+/// Decrypt a message using the given secret key.
+pub fn decrypt_legacy<const N: usize>(sk: &SecretKeyLegacy, mut data: Data<N>) -> Result<[u8; N], Error> {
+    let ep = PublicKeyLegacy::from(data.key);
+    let tg = data.tag.into();
+    let nc = nonce(ep.as_bytes(), sk.public_key().as_bytes()).into();
+    let cb = ChaChaBoxLegacy::new(&ep, sk);
+    AeadInPlaceLegacy::decrypt_in_place_detached(&cb, &nc, &[], &mut data.data, &tg).map_err(|_| Error)?;
+    Ok(data.data)
+}
+
+/// Decrypt a message produced by either [`encrypt`] or [`encrypt_legacy`].
+///
+/// The current implementation is tried first. If decryption fails, the
+/// same secret key is used with the legacy implementation.
+pub fn decrypt_any<const N: usize>(sk: &SecretKey, data: Data<N>) -> Result<[u8; N], Error> {
+    decrypt(sk, data).or_else(|_| decrypt_legacy(&SecretKeyLegacy::from(sk.to_bytes()), data))
+}
+
+/// Encrypt a message for the given public key like libsodium's `crypto_box_seal`.
+#[cfg(feature = "getrandom")]
+pub fn encrypt_sodium(pk: &PublicKey, msg: &[u8]) -> Result<Vec<u8>, Error> {
+    encrypt_sodium_with_rng(&mut OsRng, pk, msg)
+}
+
+/// Like [`encrypt_sodium`] but the ephemeral key is generated with the
+/// given random number generator.
+pub fn encrypt_sodium_with_rng(rng: &mut impl CryptoRngCore, pk: &PublicKey, msg: &[u8]) -> Result<Vec<u8>, Error> {
+    pk.seal(rng, msg)
+}
+
+/// Decrypt a message like libsodium's `crypto_box_seal_open`.
+pub fn decrypt_sodium(sk: &SecretKey, ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+    sk.unseal(ciphertext)
+}
+
 /// Calculate the nonce as `blake2b(a || b)`.
 fn nonce<const N: usize>(a: &[u8], b: &[u8]) -> [u8; N] {
     let mut s = blake2b_simd::Params::new().hash_length(N).to_state();
@@ -129,4 +350,82 @@ mod tests {
         }
         assert!(decrypt(&sk2, dat).is_err())
     }
+
+    #[test]
+    fn byte_encoding() {
+        let it = encrypt(&gen_secret_key().public_key(), fresh_array::<57>()).unwrap();
+        assert_eq!(Data::<57>::LEN, it.to_bytes().len());
+        assert_eq!(it, Data::from_bytes(&it.to_bytes()).unwrap());
+        assert_eq!(it, Data::from_base64(&it.to_base64()).unwrap());
+        assert!(Data::<56>::from_bytes(&it.to_bytes()).is_err());
+        assert!(Data::<57>::from_base64("not base64!").is_err())
+    }
+
+    #[test]
+    fn batch_roundtrip() {
+        let sks = (0 .. 8).map(|_| gen_secret_key()).collect::<Vec<_>>();
+        let msgs = sks.iter().map(|sk| (sk.public_key(), fresh_array::<57>())).collect::<Vec<_>>();
+        let enc = encrypt_batch(&msgs).unwrap();
+        assert!(enc.iter().all(|d| d.key == enc[0].key));
+        for ((sk, (_, m)), d) in sks.iter().zip(&msgs).zip(&enc) {
+            assert_eq!(*m, decrypt(sk, *d).unwrap())
+        }
+        // duplicate recipients get distinct ephemeral keys
+        let pk = sks[0].public_key();
+        let msgs = [(pk.clone(), fresh_array::<57>()), (pk, fresh_array::<57>())];
+        let enc = encrypt_batch(&msgs).unwrap();
+        assert_ne!(enc[0].key, enc[1].key);
+        for (r, (_, m)) in decrypt_batch(&sks[0], &enc).into_iter().zip(&msgs) {
+            assert_eq!(*m, r.unwrap())
+        }
+    }
+
+    #[test]
+    fn legacy_fallback() {
+        let sk = gen_secret_key();
+        let pk = PublicKeyLegacy::from(*sk.public_key().as_bytes());
+        let da = fresh_array::<57>();
+        let it = encrypt_legacy(&pk, da).unwrap();
+        assert_eq!(da, decrypt_any(&sk, it).unwrap());
+        let it = encrypt(&sk.public_key(), da).unwrap();
+        assert_eq!(da, decrypt_any(&sk, it).unwrap());
+        assert!(decrypt_any(&gen_secret_key(), it).is_err())
+    }
+
+    #[test]
+    fn deterministic() {
+        use rand_chacha::ChaCha20Rng;
+        use rand_core::SeedableRng;
+
+        let sk = gen_secret_key_with_rng(&mut ChaCha20Rng::seed_from_u64(1));
+        assert_eq!(sk.to_bytes(), gen_secret_key_with_rng(&mut ChaCha20Rng::seed_from_u64(1)).to_bytes());
+        let pk = sk.public_key();
+        let da = fresh_array::<57>();
+        let a = encrypt_with_rng(&mut ChaCha20Rng::seed_from_u64(2), &pk, da, &[]).unwrap();
+        let b = encrypt_with_rng(&mut ChaCha20Rng::seed_from_u64(2), &pk, da, &[]).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(da, decrypt(&sk, a).unwrap())
+    }
+
+    #[test]
+    fn sodium() {
+        let sk1 = gen_secret_key();
+        let sk2 = gen_secret_key();
+        let msg = fresh_array::<57>();
+        let enc = encrypt_sodium(&sk1.public_key(), &msg).unwrap();
+        assert_eq!(msg.len() + SEALBYTES, enc.len());
+        assert_eq!(&msg[..], &decrypt_sodium(&sk1, &enc).unwrap()[..]);
+        assert!(decrypt_sodium(&sk2, &enc).is_err())
+    }
+
+    #[test]
+    fn associated_data() {
+        let sk = gen_secret_key();
+        let pk = sk.public_key();
+        let da = fresh_array::<57>();
+        let it = encrypt_with_ad(&pk, da, b"context").unwrap();
+        assert!(decrypt(&sk, it).is_err());
+        assert!(decrypt_with_ad(&sk, it, b"other context").is_err());
+        assert_eq!(da, decrypt_with_ad(&sk, it, b"context").unwrap())
+    }
 }
@@ -0,0 +1,280 @@
+//! Audit events.
+//!
+//! Security relevant actions (streams opened or denied, policy changes, key
+//! rotations) are described as [`AuditEvent`]s and passed to an [`AuditSink`]
+//! which decides where they go. Sinks should not block for long as they are
+//! invoked from async contexts.
+
+use crate::time::UnixTimeMillis;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A single audit record.
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditEvent {
+    #[serde(serialize_with = "crate::serde::encode_display")]
+    pub time: UnixTimeMillis,
+    #[serde(flatten)]
+    pub kind: AuditKind
+}
+
+/// The kind of audited action.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum AuditKind {
+    /// A stream to a destination has been opened.
+    StreamOpened {
+        stream: String,
+        destination: String
+    },
+    /// A stream to a destination has been closed.
+    StreamClosed {
+        stream: String,
+        destination: String,
+        sent: u64,
+        received: u64,
+        millis: u64
+    },
+    /// A stream to a destination has been rejected.
+    StreamDenied {
+        stream: String,
+        destination: String,
+        reason: String
+    },
+    /// The effective address policy has changed.
+    PolicyChanged {
+        description: String
+    },
+    /// The agent key has been replaced.
+    KeyRotated {
+        public_key: String
+    }
+}
+
+impl AuditEvent {
+    /// Create an event with the current time.
+    pub fn new(kind: AuditKind) -> Self {
+        let time = UnixTimeMillis::now().unwrap_or_else(|_| UnixTimeMillis::from(Duration::ZERO));
+        AuditEvent { time, kind }
+    }
+
+    /// Encode this event as a single line of JSON (without line terminator).
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("audit event serialises to JSON")
+    }
+}
+
+/// A destination of audit events.
+pub trait AuditSink: Send + Sync + 'static {
+    /// Record an event.
+    ///
+    /// Failures are not reported to the caller but handled by the sink,
+    /// e.g. by logging them.
+    fn record(&self, event: &AuditEvent);
+}
+
+impl<T: AuditSink + ?Sized> AuditSink for Arc<T> {
+    fn record(&self, event: &AuditEvent) {
+        (**self).record(event)
+    }
+}
+
+/// Emits audit events as log records with target `audit`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Log;
+
+impl AuditSink for Log {
+    fn record(&self, event: &AuditEvent) {
+        log::info!(target: "audit", "{}", event.to_json())
+    }
+}
+
+/// Discards all events.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Discard;
+
+impl AuditSink for Discard {
+    fn record(&self, _: &AuditEvent) {}
+}
+
+/// Appends events as JSON lines to a file.
+#[derive(Debug)]
+pub struct FileSink {
+    file: Mutex<File>
+}
+
+impl FileSink {
+    /// Open (or create) the given file for appending.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileSink { file: Mutex::new(file) })
+    }
+}
+
+impl AuditSink for FileSink {
+    fn record(&self, event: &AuditEvent) {
+        let mut line = event.to_json();
+        line.push('\n');
+        let mut file = self.file.lock().expect("audit file lock");
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            log::warn!("failed to write audit event: {}", e)
+        }
+    }
+}
+
+/// Sends events to the local syslog daemon.
+///
+/// Messages use facility `auth` and severity `info`.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct Syslog(crate::syslog::Syslog);
+
+#[cfg(unix)]
+impl Syslog {
+    /// Connect to the syslog socket at [`crate::syslog::SOCKET`].
+    pub fn connect(ident: &str) -> io::Result<Self> {
+        Self::connect_to(ident, crate::syslog::SOCKET)
+    }
+
+    /// Connect to a syslog socket at the given path.
+    pub fn connect_to<P: AsRef<Path>>(ident: &str, path: P) -> io::Result<Self> {
+        crate::syslog::Syslog::connect_to(ident, crate::syslog::Facility::Auth, path).map(Syslog)
+    }
+}
+
+#[cfg(unix)]
+impl AuditSink for Syslog {
+    fn record(&self, event: &AuditEvent) {
+        if let Err(e) = self.0.send(crate::syslog::Severity::Info, &event.to_json()) {
+            log::warn!("failed to send audit event to syslog: {}", e)
+        }
+    }
+}
+
+/// Posts events as JSON to an HTTP endpoint.
+///
+/// Requests are made from a background thread. If it can not keep up,
+/// events are dropped once `capacity` are queued.
+///
+/// Enabled with feature `webhook`.
+#[cfg(feature = "webhook")]
+#[derive(Debug)]
+pub struct Webhook {
+    queue: std::sync::mpsc::SyncSender<String>
+}
+
+#[cfg(feature = "webhook")]
+impl Webhook {
+    pub fn new(url: &str, capacity: usize, timeout: Duration) -> io::Result<Self> {
+        let (tx, rx) = std::sync::mpsc::sync_channel::<String>(capacity);
+        let client = ureq::AgentBuilder::new().timeout(timeout).build();
+        let url = url.to_string();
+        std::thread::Builder::new()
+            .name("audit-webhook".into())
+            .spawn(move || {
+                for body in rx {
+                    let result = client.post(&url)
+                        .set("Content-Type", "application/json")
+                        .send_string(&body);
+                    if let Err(e) = result {
+                        log::warn!(%url, "failed to post audit event: {}", e)
+                    }
+                }
+            })?;
+        Ok(Webhook { queue: tx })
+    }
+}
+
+#[cfg(feature = "webhook")]
+impl AuditSink for Webhook {
+    fn record(&self, event: &AuditEvent) {
+        if self.queue.try_send(event.to_json()).is_err() {
+            log::warn!("audit webhook queue is full, dropping event")
+        }
+    }
+}
+
+/// Passes events to several sinks.
+#[derive(Default)]
+pub struct Fanout(Vec<Box<dyn AuditSink>>);
+
+impl Fanout {
+    pub fn new() -> Self {
+        Fanout::default()
+    }
+
+    pub fn add<S: AuditSink>(&mut self, sink: S) -> &mut Self {
+        self.0.push(Box::new(sink));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl AuditSink for Fanout {
+    fn record(&self, event: &AuditEvent) {
+        for s in &self.0 {
+            s.record(event)
+        }
+    }
+}
+
+/// Keeps events in memory.
+///
+/// Clones share the same events.
+#[derive(Clone, Debug, Default)]
+pub struct Memory(Arc<Mutex<Vec<AuditEvent>>>);
+
+impl Memory {
+    pub fn new() -> Self {
+        Memory::default()
+    }
+
+    /// Get all recorded events.
+    pub fn events(&self) -> Vec<AuditEvent> {
+        self.0.lock().expect("audit memory lock").clone()
+    }
+}
+
+impl AuditSink for Memory {
+    fn record(&self, event: &AuditEvent) {
+        self.0.lock().expect("audit memory lock").push(event.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json() {
+        let e = AuditEvent {
+            time: UnixTimeMillis::from(Duration::from_millis(1_700_000_000_123)),
+            kind: AuditKind::StreamDenied {
+                stream: "7".into(),
+                destination: "db.internal:5432".into(),
+                reason: "address not allowed".into()
+            }
+        };
+        let v: serde_json::Value = serde_json::from_str(&e.to_json()).unwrap();
+        assert_eq!("2023-11-14T22:13:20.123Z", v["time"]);
+        assert_eq!("stream-denied", v["event"]);
+        assert_eq!("db.internal:5432", v["destination"]);
+    }
+
+    #[test]
+    fn fanout() {
+        let m = Memory::new();
+        let mut f = Fanout::new();
+        f.add(m.clone()).add(Discard);
+        f.record(&AuditEvent::new(AuditKind::PolicyChanged { description: "reload".into() }));
+        assert_eq!(1, m.events().len())
+    }
+}
@@ -1,4 +1,12 @@
-use base64::Engine;
+use base64::{alphabet, Engine};
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+
+const LENIENT: GeneralPurposeConfig = GeneralPurposeConfig::new()
+    .with_decode_padding_mode(DecodePaddingMode::Indifferent);
+
+const URL_SAFE_LENIENT: GeneralPurpose = GeneralPurpose::new(&alphabet::URL_SAFE, LENIENT);
+
+const STANDARD_LENIENT: GeneralPurpose = GeneralPurpose::new(&alphabet::STANDARD, LENIENT);
 
 /// Convert to URL-safe base64 string without padding.
 pub fn encode<T: AsRef<[u8]>>(bytes: T) -> String {
@@ -10,3 +18,25 @@ pub fn decode(s: &str) -> Option<Vec<u8>> {
     base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(s).ok()
 }
 
+/// Convert from URL-safe or standard base64 string, with or without padding.
+///
+/// Surrounding whitespace is ignored. Meant for user-supplied values.
+pub fn decode_lenient(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim();
+    URL_SAFE_LENIENT.decode(s).or_else(|_| STANDARD_LENIENT.decode(s)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lenient() {
+        let b = [0xfb, 0xff, 0xbf, 0x01];
+        for s in ["-_-_AQ", "-_-_AQ==", "+/+/AQ", "+/+/AQ==", " -_-_AQ\n"] {
+            assert_eq!(Some(b.to_vec()), decode_lenient(s), "{}", s)
+        }
+        assert_eq!(None, decode("+/+/AQ=="));
+        assert_eq!(None, decode_lenient("-_+/AQ"))
+    }
+}
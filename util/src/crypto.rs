@@ -1,10 +1,18 @@
+use argon2::Argon2;
 use chacha20poly1305::XChaCha20Poly1305;
 use chacha20poly1305::aead::{AeadInPlace, Error, KeyInit};
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use hkdf::Hkdf;
 use minicbor::{Decode, Encode};
 use minicbor::decode::{self, Decoder};
 use minicbor::encode::{self, Encoder, Write};
 use rand_core::RngCore;
+use sealed_boxes::{PublicKey, SecretKey};
+use sha2::Sha256;
 use std::convert::TryFrom;
+use std::fmt;
+use std::io;
 
 #[derive(Clone)]
 pub struct Key(chacha20poly1305::Key);
@@ -27,6 +35,16 @@ impl Key {
         Key::from(k)
     }
 
+    /// Derive a subkey for the given context (see [`derive_key`]).
+    pub fn derive(&self, context: &[u8]) -> Key {
+        derive_key(context, &self.0)
+    }
+
+    /// Derive a key from a passphrase with Argon2id.
+    pub fn from_passphrase(passphrase: &[u8], p: &KdfParams) -> Result<Self, KdfError> {
+        p.derive(passphrase).map(Key::from)
+    }
+
     pub fn encrypt(&self, n: &Nonce, ad: &[u8], val: &mut Vec<u8>) -> Result<(), Error> {
         let x = XChaCha20Poly1305::new(&self.0);
         x.encrypt_in_place(&n.0, ad, val)
@@ -38,6 +56,261 @@ impl Key {
     }
 }
 
+/// Parameters of the Argon2id passphrase-based key derivation.
+///
+/// The parameters (including the salt) are required to derive the same key
+/// again and should be stored alongside the data protected by the key.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct KdfParams {
+    /// Memory size in KiB.
+    #[n(0)] pub mem_cost: u32,
+    /// Number of iterations.
+    #[n(1)] pub time_cost: u32,
+    /// Degree of parallelism.
+    #[n(2)] pub parallelism: u32,
+    #[n(3)]
+    #[cbor(with = "minicbor::bytes")]
+    pub salt: [u8; 16]
+}
+
+impl KdfParams {
+    /// Default parameters (19 MiB, 2 iterations, 1 lane) with a fresh salt.
+    pub fn fresh() -> Self {
+        let mut salt = [0; 16];
+        rand_core::OsRng.fill_bytes(&mut salt);
+        KdfParams { mem_cost: 19 * 1024, time_cost: 2, parallelism: 1, salt }
+    }
+
+    fn derive(&self, passphrase: &[u8]) -> Result<[u8; 32], KdfError> {
+        let p = argon2::Params::new(self.mem_cost, self.time_cost, self.parallelism, Some(32))
+            .map_err(KdfError)?;
+        let a = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, p);
+        let mut k = [0; 32];
+        a.hash_password_into(passphrase, &self.salt, &mut k).map_err(KdfError)?;
+        Ok(k)
+    }
+}
+
+/// Derive a secret key for use with sealed boxes from a passphrase.
+pub fn derive_secret_key(passphrase: &[u8], p: &KdfParams) -> Result<SecretKey, KdfError> {
+    p.derive(passphrase).map(SecretKey::from)
+}
+
+/// Derive a symmetric key shared with the owner of the given public key.
+///
+/// The X25519 shared secret of `sk` and `pk` is passed through HKDF-SHA256
+/// with the context and both public keys as info. Both sides derive the same
+/// key if they use the same context. Public keys of small order are rejected.
+pub fn shared_key(sk: &SecretKey, pk: &PublicKey, context: &[u8]) -> Result<Key, InvalidPublicKey> {
+    let s = x25519_dalek::StaticSecret::from(sk.to_bytes());
+    let p = x25519_dalek::PublicKey::from(pk.to_bytes());
+    let ss = s.diffie_hellman(&p);
+    if !ss.was_contributory() {
+        return Err(InvalidPublicKey(()))
+    }
+    let mut keys = [sk.public_key().to_bytes(), pk.to_bytes()];
+    keys.sort();
+    let mut info = Vec::with_capacity(context.len() + 64);
+    info.extend_from_slice(context);
+    info.extend_from_slice(&keys[0]);
+    info.extend_from_slice(&keys[1]);
+    Ok(hkdf(ss.as_bytes(), &info))
+}
+
+/// Derive a key from input key material with HKDF-SHA256.
+///
+/// The context provides domain separation, i.e. different contexts yield
+/// independent keys from the same input key material.
+pub fn derive_key(context: &[u8], ikm: &[u8]) -> Key {
+    hkdf(ikm, context)
+}
+
+fn hkdf(ikm: &[u8], info: &[u8]) -> Key {
+    let mut k = [0; 32];
+    Hkdf::<Sha256>::new(None, ikm)
+        .expand(info, &mut k)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    Key::from(k)
+}
+
+/// Error when a public key does not contribute to a shared secret.
+#[derive(Debug, Clone)]
+pub struct InvalidPublicKey(());
+
+impl fmt::Display for InvalidPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid public key")
+    }
+}
+
+impl std::error::Error for InvalidPublicKey {}
+
+/// Error deriving a key from a passphrase.
+#[derive(Debug, Clone)]
+pub struct KdfError(argon2::Error);
+
+impl fmt::Display for KdfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "key derivation failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for KdfError {}
+
+/// Length of the nonce prefix of encrypted streams.
+const STREAM_HEADER_LEN: usize = 19;
+
+/// The header of an encrypted stream.
+///
+/// It is required for decryption and must be transferred alongside
+/// the encrypted chunks, e.g. in front of the first one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamHeader([u8; STREAM_HEADER_LEN]);
+
+impl StreamHeader {
+    pub fn fresh() -> Self {
+        let mut h = [0; STREAM_HEADER_LEN];
+        rand_core::OsRng.fill_bytes(&mut h);
+        StreamHeader(h)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; STREAM_HEADER_LEN] {
+        &self.0
+    }
+}
+
+impl From<[u8; STREAM_HEADER_LEN]> for StreamHeader {
+    fn from(h: [u8; STREAM_HEADER_LEN]) -> Self {
+        StreamHeader(h)
+    }
+}
+
+/// Chunk-wise encryption of a stream of data.
+///
+/// Uses the STREAM construction with XChaCha20-Poly1305. Each chunk is
+/// authenticated together with its position and whether it is the last one,
+/// so reordered, dropped or truncated chunks fail to decrypt.
+pub struct StreamEncryptor(EncryptorBE32<XChaCha20Poly1305>);
+
+impl StreamEncryptor {
+    /// Create a new encryptor with a fresh stream header.
+    pub fn new(k: &Key) -> (Self, StreamHeader) {
+        let h = StreamHeader::fresh();
+        (StreamEncryptor::with_header(k, &h), h)
+    }
+
+    pub fn with_header(k: &Key, h: &StreamHeader) -> Self {
+        StreamEncryptor(EncryptorBE32::<XChaCha20Poly1305>::new(&k.0, &h.0.into()))
+    }
+
+    /// Encrypt the next chunk in place.
+    pub fn encrypt_next(&mut self, ad: &[u8], val: &mut Vec<u8>) -> Result<(), Error> {
+        self.0.encrypt_next_in_place(ad, val)
+    }
+
+    /// Encrypt the last chunk in place.
+    pub fn encrypt_last(self, ad: &[u8], val: &mut Vec<u8>) -> Result<(), Error> {
+        self.0.encrypt_last_in_place(ad, val)
+    }
+}
+
+/// Chunk-wise decryption of a stream of data encrypted with [`StreamEncryptor`].
+pub struct StreamDecryptor(DecryptorBE32<XChaCha20Poly1305>);
+
+impl StreamDecryptor {
+    pub fn new(k: &Key, h: &StreamHeader) -> Self {
+        StreamDecryptor(DecryptorBE32::<XChaCha20Poly1305>::new(&k.0, &h.0.into()))
+    }
+
+    /// Decrypt the next chunk in place.
+    pub fn decrypt_next(&mut self, ad: &[u8], val: &mut Vec<u8>) -> Result<(), Error> {
+        self.0.decrypt_next_in_place(ad, val)
+    }
+
+    /// Decrypt the last chunk in place.
+    pub fn decrypt_last(self, ad: &[u8], val: &mut Vec<u8>) -> Result<(), Error> {
+        self.0.decrypt_last_in_place(ad, val)
+    }
+}
+
+/// Plaintext chunk size of [`encrypt_file`].
+const FILE_CHUNK_LEN: usize = 64 * 1024;
+
+/// Length of the authentication tag appended to each encrypted chunk.
+const TAG_LEN: usize = 16;
+
+/// Encrypt everything read from `r` and write it to `w`.
+///
+/// The output consists of the stream header followed by the encrypted
+/// chunks of at most 64 KiB each (see [`StreamEncryptor`]). Memory usage
+/// is independent of the input size.
+pub async fn encrypt_file<R, W>(k: &Key, mut r: R, mut w: W) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin
+{
+    let (mut e, h) = StreamEncryptor::new(k);
+    w.write_all(h.as_bytes()).await?;
+    let mut cur = read_chunk(&mut r, FILE_CHUNK_LEN).await?;
+    loop {
+        let next = if cur.len() < FILE_CHUNK_LEN {
+            Vec::new()
+        } else {
+            read_chunk(&mut r, FILE_CHUNK_LEN).await?
+        };
+        if next.is_empty() {
+            e.encrypt_last(&[], &mut cur).map_err(invalid_data)?;
+            w.write_all(&cur).await?;
+            return w.flush().await
+        }
+        e.encrypt_next(&[], &mut cur).map_err(invalid_data)?;
+        w.write_all(&cur).await?;
+        cur = next
+    }
+}
+
+/// Decrypt everything read from `r`, as produced by [`encrypt_file`], and
+/// write it to `w`.
+///
+/// Decrypted chunks are written as soon as they are authenticated, so on
+/// error `w` may have received a prefix of the plaintext.
+pub async fn decrypt_file<R, W>(k: &Key, mut r: R, mut w: W) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin
+{
+    let mut h = [0; STREAM_HEADER_LEN];
+    r.read_exact(&mut h).await?;
+    let mut d = StreamDecryptor::new(k, &StreamHeader::from(h));
+    let mut cur = read_chunk(&mut r, FILE_CHUNK_LEN + TAG_LEN).await?;
+    loop {
+        let next = if cur.len() < FILE_CHUNK_LEN + TAG_LEN {
+            Vec::new()
+        } else {
+            read_chunk(&mut r, FILE_CHUNK_LEN + TAG_LEN).await?
+        };
+        if next.is_empty() {
+            d.decrypt_last(&[], &mut cur).map_err(invalid_data)?;
+            w.write_all(&cur).await?;
+            return w.flush().await
+        }
+        d.decrypt_next(&[], &mut cur).map_err(invalid_data)?;
+        w.write_all(&cur).await?;
+        cur = next
+    }
+}
+
+/// Read up to `n` bytes, less only if EOF is reached.
+async fn read_chunk<R: AsyncRead + Unpin>(r: &mut R, n: usize) -> io::Result<Vec<u8>> {
+    let mut v = Vec::with_capacity(n + TAG_LEN);
+    r.take(n as u64).read_to_end(&mut v).await?;
+    Ok(v)
+}
+
+fn invalid_data(e: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
 impl From<[u8; 32]> for Key {
     fn from(k: [u8; 32]) -> Self {
         Key(k.into())
@@ -83,4 +356,93 @@ mod tests {
         assert_eq!(&b"hello world"[..], &v)
     }
 
+    #[test]
+    fn stream_success() {
+        let k = Key::fresh();
+        let (mut e, h) = StreamEncryptor::new(&k);
+        let mut a = b"hello".to_vec();
+        let mut b = b"world".to_vec();
+        e.encrypt_next(&[], &mut a).unwrap();
+        e.encrypt_last(&[], &mut b).unwrap();
+        let mut d = StreamDecryptor::new(&k, &h);
+        d.decrypt_next(&[], &mut a).unwrap();
+        d.decrypt_last(&[], &mut b).unwrap();
+        assert_eq!(&b"hello"[..], &a);
+        assert_eq!(&b"world"[..], &b)
+    }
+
+    #[test]
+    fn stream_reordered_or_truncated() {
+        let k = Key::fresh();
+        let (mut e, h) = StreamEncryptor::new(&k);
+        let mut a = b"hello".to_vec();
+        let mut b = b"world".to_vec();
+        e.encrypt_next(&[], &mut a).unwrap();
+        e.encrypt_last(&[], &mut b).unwrap();
+        // reordered
+        assert!(StreamDecryptor::new(&k, &h).decrypt_next(&[], &mut b.clone()).is_err());
+        // truncated
+        assert!(StreamDecryptor::new(&k, &h).decrypt_last(&[], &mut a.clone()).is_err())
+    }
+
+    #[test]
+    fn file_roundtrip() {
+        use futures::executor::block_on;
+        use futures::io::Cursor;
+
+        let k = Key::fresh();
+        for n in [0, 1, FILE_CHUNK_LEN, 2 * FILE_CHUNK_LEN, 150_000] {
+            let data = (0 .. n).map(|i| i as u8).collect::<Vec<_>>();
+            let mut enc = Vec::new();
+            block_on(encrypt_file(&k, Cursor::new(&data), Cursor::new(&mut enc))).unwrap();
+            let mut dec = Vec::new();
+            block_on(decrypt_file(&k, Cursor::new(&enc), Cursor::new(&mut dec))).unwrap();
+            assert_eq!(data, dec);
+            // truncated
+            let mut dec = Vec::new();
+            let trunc = &enc[.. enc.len() - 1];
+            assert!(block_on(decrypt_file(&k, Cursor::new(trunc), Cursor::new(&mut dec))).is_err())
+        }
+    }
+
+    #[test]
+    fn derived() {
+        let k = Key::fresh();
+        let n = Nonce::fresh();
+        let mut v = b"hello world".to_vec();
+        k.derive(b"a").encrypt(&n, &[], &mut v).unwrap();
+        assert!(k.derive(b"b").decrypt(&n, &[], &mut v.clone()).is_err());
+        assert!(k.decrypt(&n, &[], &mut v.clone()).is_err());
+        k.derive(b"a").decrypt(&n, &[], &mut v).unwrap();
+        assert_eq!(&b"hello world"[..], &v)
+    }
+
+    #[test]
+    fn shared() {
+        let a = sealed_boxes::gen_secret_key();
+        let b = sealed_boxes::gen_secret_key();
+        let ka = shared_key(&a, &b.public_key(), b"test").unwrap();
+        let kb = shared_key(&b, &a.public_key(), b"test").unwrap();
+        let n = Nonce::fresh();
+        let mut v = b"hello world".to_vec();
+        ka.encrypt(&n, &[], &mut v).unwrap();
+        kb.decrypt(&n, &[], &mut v.clone()).unwrap();
+        let kc = shared_key(&b, &a.public_key(), b"other").unwrap();
+        assert!(kc.decrypt(&n, &[], &mut v).is_err());
+        assert!(shared_key(&a, &PublicKey::from([0; 32]), b"test").is_err())
+    }
+
+    #[test]
+    fn passphrase() {
+        let p = KdfParams { mem_cost: 64, time_cost: 1, ..KdfParams::fresh() };
+        let p: KdfParams = minicbor::decode(&minicbor::to_vec(&p).unwrap()).unwrap();
+        let k1 = derive_secret_key(b"secret", &p).unwrap();
+        let k2 = derive_secret_key(b"secret", &p).unwrap();
+        let k3 = derive_secret_key(b"Secret", &p).unwrap();
+        assert_eq!(k1.to_bytes(), k2.to_bytes());
+        assert_ne!(k1.to_bytes(), k3.to_bytes());
+        let q = KdfParams { mem_cost: 64, time_cost: 1, ..KdfParams::fresh() };
+        assert_ne!(k1.to_bytes(), derive_secret_key(b"secret", &q).unwrap().to_bytes())
+    }
+
 }
@@ -0,0 +1,169 @@
+//! DNS resolution.
+
+use crate::metrics;
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Resolution of host names to socket addresses.
+pub trait Resolver: Send + Sync + 'static {
+    /// Resolve the given host name.
+    ///
+    /// An empty result is reported as error of kind [`io::ErrorKind::NotFound`].
+    fn resolve<'a>(&'a self, host: &'a str, port: u16) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>>;
+}
+
+/// The resolver of the operating system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct System;
+
+impl Resolver for System {
+    fn resolve<'a>(&'a self, host: &'a str, port: u16) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((host, port)).await?.collect::<Vec<_>>();
+            non_empty(host, addrs)
+        })
+    }
+}
+
+/// A fixed mapping of host names to IP addresses.
+#[derive(Debug, Clone, Default)]
+pub struct Static {
+    hosts: HashMap<String, Vec<IpAddr>>
+}
+
+impl Static {
+    pub fn new() -> Self {
+        Static::default()
+    }
+
+    /// Add the IP addresses of a host name.
+    pub fn insert<I>(&mut self, host: &str, addrs: I)
+    where
+        I: IntoIterator<Item = IpAddr>
+    {
+        self.hosts.entry(host.to_ascii_lowercase()).or_default().extend(addrs)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+}
+
+impl Resolver for Static {
+    fn resolve<'a>(&'a self, host: &'a str, port: u16) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+        let addrs = self.hosts.get(&host.to_ascii_lowercase())
+            .map(|ips| ips.iter().map(|ip| SocketAddr::new(*ip, port)).collect())
+            .unwrap_or_default();
+        Box::pin(futures::future::ready(non_empty(host, addrs)))
+    }
+}
+
+/// Use the first resolver and if it fails, the second one.
+#[derive(Debug, Clone)]
+pub struct Fallback<A, B>(pub A, pub B);
+
+impl<A: Resolver, B: Resolver> Resolver for Fallback<A, B> {
+    fn resolve<'a>(&'a self, host: &'a str, port: u16) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+        Box::pin(async move {
+            match self.0.resolve(host, port).await {
+                Ok(addrs) => Ok(addrs),
+                Err(_)    => self.1.resolve(host, port).await
+            }
+        })
+    }
+}
+
+/// Caches successful results of another resolver for some time.
+#[derive(Debug)]
+pub struct Cached<R> {
+    inner: R,
+    ttl: Duration,
+    cache: Mutex<HashMap<(String, u16), (Instant, Vec<SocketAddr>)>>
+}
+
+impl<R> Cached<R> {
+    pub fn new(inner: R, ttl: Duration) -> Self {
+        Cached { inner, ttl, cache: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl<R: Resolver> Resolver for Cached<R> {
+    fn resolve<'a>(&'a self, host: &'a str, port: u16) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+        Box::pin(async move {
+            let key = (host.to_ascii_lowercase(), port);
+            {
+                let mut cache = self.cache.lock().expect("dns cache lock");
+                let now = Instant::now();
+                cache.retain(|_, (t, _)| now.duration_since(*t) < self.ttl);
+                if let Some((_, addrs)) = cache.get(&key) {
+                    metrics::counter("dns_cache_hits", &[], 1);
+                    return Ok(addrs.clone())
+                }
+            }
+            metrics::counter("dns_cache_misses", &[], 1);
+            let addrs = self.inner.resolve(host, port).await?;
+            self.cache.lock().expect("dns cache lock").insert(key, (Instant::now(), addrs.clone()));
+            Ok(addrs)
+        })
+    }
+}
+
+/// A resolver based on `hickory-resolver`.
+///
+/// Enabled with feature `hickory`.
+#[cfg(feature = "hickory")]
+pub struct Hickory(hickory_resolver::TokioAsyncResolver);
+
+#[cfg(feature = "hickory")]
+impl Hickory {
+    /// Create a resolver using the system configuration (e.g. `/etc/resolv.conf`).
+    pub fn from_system_conf() -> io::Result<Self> {
+        hickory_resolver::TokioAsyncResolver::tokio_from_system_conf()
+            .map(Hickory)
+            .map_err(io::Error::other)
+    }
+}
+
+#[cfg(feature = "hickory")]
+impl Resolver for Hickory {
+    fn resolve<'a>(&'a self, host: &'a str, port: u16) -> BoxFuture<'a, io::Result<Vec<SocketAddr>>> {
+        Box::pin(async move {
+            let ips = self.0.lookup_ip(host).await.map_err(io::Error::other)?;
+            non_empty(host, ips.iter().map(|ip| SocketAddr::new(ip, port)).collect())
+        })
+    }
+}
+
+fn non_empty(host: &str, addrs: Vec<SocketAddr>) -> io::Result<Vec<SocketAddr>> {
+    if addrs.is_empty() {
+        let msg = format!("no addresses found for {}", host);
+        return Err(io::Error::new(io::ErrorKind::NotFound, msg))
+    }
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use std::net::Ipv4Addr;
+    use super::*;
+
+    #[test]
+    fn static_and_fallback() {
+        let mut a = Static::new();
+        a.insert("DB.internal", [IpAddr::from(Ipv4Addr::new(10, 0, 0, 1))]);
+        let mut b = Static::new();
+        b.insert("cache.internal", [IpAddr::from(Ipv4Addr::new(10, 0, 0, 2))]);
+        let r = Cached::new(Fallback(a, b), Duration::from_secs(60));
+        let x = block_on(r.resolve("db.internal", 5432)).unwrap();
+        assert_eq!(vec![SocketAddr::from(([10, 0, 0, 1], 5432))], x);
+        let y = block_on(r.resolve("cache.internal", 6379)).unwrap();
+        assert_eq!(vec![SocketAddr::from(([10, 0, 0, 2], 6379))], y);
+        let e = block_on(r.resolve("unknown.internal", 1)).unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, e.kind())
+    }
+}
@@ -2,6 +2,11 @@ use futures::io::{AsyncRead, AsyncWrite};
 use minicbor::{Encode, Decode};
 use minicbor_io::{AsyncReader, AsyncWriter, Error};
 use std::fmt::Debug;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::time::timeout;
 
 pub async fn send<T, W>(w: &mut AsyncWriter<W>, v: T) -> Result<usize, Error>
 where
@@ -22,3 +27,37 @@ where
     Ok(v)
 }
 
+
+/// Like [`send`] but fails with [`io::ErrorKind::TimedOut`] if the value
+/// could not be written within the given duration.
+pub async fn send_timeout<T, W>(w: &mut AsyncWriter<W>, v: T, d: Duration) -> Result<usize, Error>
+where
+    T: Encode<()> + Debug,
+    W: AsyncWrite + Unpin
+{
+    timeout(d, send(w, v)).await.unwrap_or_else(|_| Err(timed_out("send")))
+}
+
+/// Like [`recv`] but fails with [`io::ErrorKind::TimedOut`] if no value
+/// could be read within the given duration.
+pub async fn recv_timeout<'a, T, R>(r: &'a mut AsyncReader<R>, d: Duration) -> Result<Option<T>, Error>
+where
+    T: Decode<'a, ()> + Debug,
+    R: AsyncRead + Unpin
+{
+    timeout(d, recv(r)).await.unwrap_or_else(|_| Err(timed_out("recv")))
+}
+
+fn timed_out(op: &str) -> Error {
+    Error::from(io::Error::new(io::ErrorKind::TimedOut, format!("{} timed out", op)))
+}
+
+/// Open a TCP connection, optionally from the given local IP address.
+pub async fn tcp_connect(addr: SocketAddr, local: Option<IpAddr>) -> io::Result<TcpStream> {
+    let Some(ip) = local else {
+        return TcpStream::connect(addr).await
+    };
+    let sock = if addr.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+    sock.bind(SocketAddr::new(ip, 0))?;
+    sock.connect(addr).await
+}
@@ -1,7 +1,17 @@
+pub mod audit;
 pub mod base64;
 pub mod crypto;
+pub mod dns;
 pub mod io;
+pub mod location;
+pub mod metrics;
+pub mod retry;
+pub mod secret;
 pub mod serde;
+pub mod service;
+pub mod sign;
+#[cfg(unix)]
+pub mod syslog;
 pub mod time;
 
 use ::serde::de::{self, Deserialize, Deserializer};
@@ -13,6 +23,9 @@ use std::ops::Deref;
 use std::str::FromStr;
 use tokio_rustls::rustls::pki_types::ServerName;
 
+pub use location::{InvalidLocation, Location};
+pub use secret::Secret;
+
 /// A non-empty vector.
 #[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
 #[serde(transparent)]
@@ -79,45 +92,6 @@ impl fmt::Display for Empty {
 
 impl std::error::Error for Empty {}
 
-#[derive(Clone, Copy, Debug)]
-pub enum Location {
-    Eu,
-    Us
-}
-
-impl fmt::Display for Location {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Location::Eu => f.write_str("eu"),
-            Location::Us => f.write_str("us")
-        }
-    }
-}
-
-/// Error caused by parsing invalid or unknown locations.
-#[derive(Clone, Debug)]
-pub struct InvalidLocation(String);
-
-impl fmt::Display for InvalidLocation {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "invalid location: {}", self.0)
-    }
-}
-
-impl std::error::Error for InvalidLocation {}
-
-impl<'a> FromStr for Location {
-    type Err = InvalidLocation;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "eu" => Ok(Location::Eu),
-            "us" => Ok(Location::Us),
-            _    => Err(InvalidLocation(s.into()))
-        }
-    }
-}
-
 #[derive(Debug, Clone)]
 pub struct HostName(ServerName<'static>);
 
@@ -165,8 +139,12 @@ impl std::error::Error for InvalidHostName {}
 impl<'a> FromStr for HostName {
     type Err = InvalidHostName;
 
+    /// Parse a host name.
+    ///
+    /// A trailing dot is removed and the name is converted to lowercase.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match ServerName::try_from(s.to_string()) {
+        let name = s.strip_suffix('.').unwrap_or(s);
+        match ServerName::try_from(domain_to_ascii(name)?.to_ascii_lowercase()) {
             Ok(n@ServerName::DnsName(_)) => Ok(HostName(n)),
             Ok(_)  => Err(InvalidHostName(format!("not a DNS name: `{}`", s))),
             Err(e) => Err(InvalidHostName(format!("`{}`: {:?}", s, e)))
@@ -174,6 +152,14 @@ impl<'a> FromStr for HostName {
     }
 }
 
+/// Convert a (possibly internationalized) domain name to its ASCII form.
+///
+/// Unicode labels are converted to punycode (e.g. `bücher.example` becomes
+/// `xn--bcher-kva.example`).
+pub fn domain_to_ascii(s: &str) -> Result<String, InvalidHostName> {
+    idna::domain_to_ascii(s).map_err(|e| InvalidHostName(format!("`{}`: {}", s, e)))
+}
+
 impl TryFrom<&str> for HostName {
     type Error = InvalidHostName;
 
@@ -194,3 +180,22 @@ impl Serialize for HostName {
         self.as_str().serialize(s)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::HostName;
+
+    #[test]
+    fn idn_hostname() {
+        let h: HostName = "bücher.example".parse().unwrap();
+        assert_eq!("xn--bcher-kva.example", h.as_str());
+        assert_eq!(h, "xn--bcher-kva.example".parse().unwrap())
+    }
+
+    #[test]
+    fn normalized_hostname() {
+        let h: HostName = "DB.Internal.".parse().unwrap();
+        assert_eq!("db.internal", h.as_str());
+        assert_eq!(h, "db.internal".parse().unwrap())
+    }
+}
@@ -0,0 +1,169 @@
+//! Gateway locations (regions) and their host names.
+
+use crate::{HostName, InvalidHostName};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::{Serialize, Serializer};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+
+/// Environment variable to add locations or override their host templates.
+///
+/// Syntax: comma-separated `<location>=<host template>` entries, e.g.
+/// `ap=gateway.ap.cluvio.com,eu=gw.example.com`.
+pub const LOCATIONS_ENV: &str = "CLUVIO_LOCATIONS";
+
+/// The host template of built-in locations.
+///
+/// `{location}` is replaced with the location identifier.
+pub const DEFAULT_HOST_TEMPLATE: &str = "gateway.{location}.cluvio.com";
+
+/// A gateway location identifier, e.g. `eu` or `us`.
+///
+/// Identifiers are lowercase ASCII letters, digits and `-`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Location(Cow<'static, str>);
+
+impl Location {
+    pub const EU: Location = Location(Cow::Borrowed("eu"));
+    pub const US: Location = Location(Cow::Borrowed("us"));
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for Location {
+    type Err = InvalidLocation;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        if s.is_empty() || !s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(InvalidLocation(s))
+        }
+        Ok(Location(Cow::Owned(s)))
+    }
+}
+
+impl<'de> Deserialize<'de> for Location {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = <Cow<'de, str>>::deserialize(d)?;
+        Location::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Location {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        self.as_str().serialize(s)
+    }
+}
+
+/// Error caused by parsing invalid or unknown locations.
+#[derive(Clone, Debug)]
+pub struct InvalidLocation(String);
+
+impl fmt::Display for InvalidLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid location: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidLocation {}
+
+/// The set of known locations with their gateway host templates.
+#[derive(Clone, Debug)]
+pub struct Registry {
+    entries: BTreeMap<Location, String>
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut entries = BTreeMap::new();
+        entries.insert(Location::EU, DEFAULT_HOST_TEMPLATE.to_string());
+        entries.insert(Location::US, DEFAULT_HOST_TEMPLATE.to_string());
+        Registry { entries }
+    }
+}
+
+impl Registry {
+    /// Create a registry with the built-in locations.
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// Create a registry with the built-in locations and the entries of
+    /// the [`LOCATIONS_ENV`] environment variable (if set).
+    pub fn from_env() -> Result<Self, InvalidLocation> {
+        let mut r = Registry::new();
+        if let Ok(v) = env::var(LOCATIONS_ENV) {
+            r.extend_from_str(&v)?
+        }
+        Ok(r)
+    }
+
+    /// Add or override entries given as comma-separated `<location>=<host template>`.
+    pub fn extend_from_str(&mut self, s: &str) -> Result<(), InvalidLocation> {
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (loc, tpl) = entry.split_once('=').ok_or_else(|| InvalidLocation(entry.to_string()))?;
+            self.insert(loc.parse()?, tpl.trim())
+        }
+        Ok(())
+    }
+
+    /// Add a location or override its host template.
+    pub fn insert<T: Into<String>>(&mut self, loc: Location, template: T) {
+        self.entries.insert(loc, template.into());
+    }
+
+    /// Lookup a known location by its identifier.
+    pub fn get(&self, s: &str) -> Result<Location, InvalidLocation> {
+        let loc = Location::from_str(s)?;
+        if self.entries.contains_key(&loc) {
+            return Ok(loc)
+        }
+        Err(InvalidLocation(format!("unknown location `{}`", s)))
+    }
+
+    /// Iterate over all known locations.
+    pub fn locations(&self) -> impl Iterator<Item = &Location> {
+        self.entries.keys()
+    }
+
+    /// Get the gateway host name of the given location.
+    pub fn gateway_host(&self, loc: &Location) -> Result<HostName, InvalidHostName> {
+        let tpl = self.entries.get(loc).map(String::as_str).unwrap_or(DEFAULT_HOST_TEMPLATE);
+        HostName::from_str(&tpl.replace("{location}", loc.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin() {
+        let r = Registry::new();
+        assert_eq!(Location::EU, r.get("EU").unwrap());
+        assert_eq!("gateway.us.cluvio.com", r.gateway_host(&Location::US).unwrap().as_str());
+        assert!(r.get("ap").is_err())
+    }
+
+    #[test]
+    fn custom() {
+        let mut r = Registry::new();
+        r.extend_from_str("ap = gateway.{location}.cluvio.com, eu=gw.example.com").unwrap();
+        let ap = r.get("ap").unwrap();
+        assert_eq!("gateway.ap.cluvio.com", r.gateway_host(&ap).unwrap().as_str());
+        assert_eq!("gw.example.com", r.gateway_host(&Location::EU).unwrap().as_str());
+        assert!(r.extend_from_str("no-template").is_err());
+        assert!(r.extend_from_str("a b=x.com").is_err())
+    }
+}
@@ -0,0 +1,172 @@
+//! A lightweight metrics facade.
+//!
+//! Instrumented code records values with [`counter`], [`gauge`] and
+//! [`histogram`]. Where the values go is decided by the [`Exporter`]
+//! installed with [`set_exporter`]. Without an exporter, recording is a
+//! no-op.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Metric labels as key-value pairs.
+pub type Labels<'a> = &'a [(&'static str, &'a str)];
+
+/// A metrics backend.
+pub trait Exporter: Send + Sync + 'static {
+    /// Increment a counter by the given amount.
+    fn counter(&self, name: &'static str, labels: Labels<'_>, delta: u64);
+
+    /// Set a gauge to the given value.
+    fn gauge(&self, name: &'static str, labels: Labels<'_>, value: f64);
+
+    /// Record a value in a histogram.
+    fn histogram(&self, name: &'static str, labels: Labels<'_>, value: f64);
+}
+
+static EXPORTER: OnceLock<Box<dyn Exporter>> = OnceLock::new();
+
+/// Install the global exporter.
+///
+/// This can only be done once; subsequent calls return an error.
+pub fn set_exporter<E: Exporter>(e: E) -> Result<(), AlreadySet> {
+    EXPORTER.set(Box::new(e)).map_err(|_| AlreadySet(()))
+}
+
+/// Increment a counter by the given amount.
+pub fn counter(name: &'static str, labels: Labels<'_>, delta: u64) {
+    if let Some(e) = EXPORTER.get() {
+        e.counter(name, labels, delta)
+    }
+}
+
+/// Set a gauge to the given value.
+pub fn gauge(name: &'static str, labels: Labels<'_>, value: f64) {
+    if let Some(e) = EXPORTER.get() {
+        e.gauge(name, labels, value)
+    }
+}
+
+/// Record a value in a histogram.
+pub fn histogram(name: &'static str, labels: Labels<'_>, value: f64) {
+    if let Some(e) = EXPORTER.get() {
+        e.histogram(name, labels, value)
+    }
+}
+
+/// Error returned by [`set_exporter`] if an exporter is already installed.
+#[derive(Clone, Debug)]
+pub struct AlreadySet(());
+
+impl fmt::Display for AlreadySet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("metrics exporter already set")
+    }
+}
+
+impl std::error::Error for AlreadySet {}
+
+/// Metric name and labels.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Key {
+    pub name: &'static str,
+    pub labels: Vec<(&'static str, String)>
+}
+
+impl Key {
+    fn new(name: &'static str, labels: Labels<'_>) -> Self {
+        Key { name, labels: labels.iter().map(|(k, v)| (*k, v.to_string())).collect() }
+    }
+}
+
+/// Summary of histogram values.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Summary {
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64
+}
+
+impl Summary {
+    fn add(&mut self, x: f64) {
+        if self.count == 0 {
+            self.min = x;
+            self.max = x
+        } else {
+            self.min = self.min.min(x);
+            self.max = self.max.max(x)
+        }
+        self.count += 1;
+        self.sum += x
+    }
+}
+
+/// The values of all metrics at some point in time.
+#[derive(Clone, Debug, Default)]
+pub struct Snapshot {
+    pub counters: BTreeMap<Key, u64>,
+    pub gauges: BTreeMap<Key, f64>,
+    pub histograms: BTreeMap<Key, Summary>
+}
+
+/// An exporter which keeps metrics in memory.
+///
+/// Clones share the same metrics, so a clone can be installed as global
+/// exporter while the original is used to take snapshots.
+#[derive(Clone, Debug, Default)]
+pub struct Registry(Arc<Mutex<Snapshot>>);
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// Get the current values of all metrics.
+    pub fn snapshot(&self) -> Snapshot {
+        self.0.lock().expect("metrics registry lock").clone()
+    }
+}
+
+impl Exporter for Registry {
+    fn counter(&self, name: &'static str, labels: Labels<'_>, delta: u64) {
+        let mut s = self.0.lock().expect("metrics registry lock");
+        let c = s.counters.entry(Key::new(name, labels)).or_default();
+        *c = c.saturating_add(delta)
+    }
+
+    fn gauge(&self, name: &'static str, labels: Labels<'_>, value: f64) {
+        let mut s = self.0.lock().expect("metrics registry lock");
+        s.gauges.insert(Key::new(name, labels), value);
+    }
+
+    fn histogram(&self, name: &'static str, labels: Labels<'_>, value: f64) {
+        let mut s = self.0.lock().expect("metrics registry lock");
+        s.histograms.entry(Key::new(name, labels)).or_default().add(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry() {
+        let r = Registry::new();
+        set_exporter(r.clone()).unwrap();
+        assert!(set_exporter(Registry::new()).is_err());
+        counter("streams", &[("dest", "db")], 1);
+        counter("streams", &[("dest", "db")], 2);
+        counter("streams", &[], 1);
+        gauge("active", &[], 3.0);
+        gauge("active", &[], 2.0);
+        histogram("latency", &[], 1.0);
+        histogram("latency", &[], 3.0);
+        let s = r.snapshot();
+        assert_eq!(Some(&3), s.counters.get(&Key::new("streams", &[("dest", "db")])));
+        assert_eq!(Some(&1), s.counters.get(&Key::new("streams", &[])));
+        assert_eq!(Some(&2.0), s.gauges.get(&Key::new("active", &[])));
+        let h = s.histograms[&Key::new("latency", &[])];
+        assert_eq!((2, 4.0, 1.0, 3.0), (h.count, h.sum, h.min, h.max))
+    }
+}
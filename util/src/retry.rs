@@ -0,0 +1,134 @@
+//! Retrying fallible operations with exponential backoff.
+
+use rand_core::RngCore;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// A retry policy.
+///
+/// The first attempt happens immediately. Attempt `n > 0` is delayed by
+/// `initial_delay * factor^(n - 1)`, bounded by `max_delay`.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    initial_delay: Duration,
+    max_delay: Duration,
+    factor: u32,
+    jitter: bool,
+    max_attempts: Option<u32>
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(64),
+            factor: 2,
+            jitter: false,
+            max_attempts: None
+        }
+    }
+}
+
+impl Policy {
+    pub fn new() -> Self {
+        Policy::default()
+    }
+
+    pub fn with_initial_delay(mut self, d: Duration) -> Self {
+        self.initial_delay = d;
+        self
+    }
+
+    pub fn with_max_delay(mut self, d: Duration) -> Self {
+        self.max_delay = d;
+        self
+    }
+
+    pub fn with_factor(mut self, f: u32) -> Self {
+        self.factor = f;
+        self
+    }
+
+    /// Randomise delays between 50% and 100% of their nominal value.
+    pub fn with_jitter(mut self, j: bool) -> Self {
+        self.jitter = j;
+        self
+    }
+
+    /// Limit the total number of attempts (`None` means unlimited).
+    pub fn with_max_attempts(mut self, n: Option<u32>) -> Self {
+        self.max_attempts = n;
+        self
+    }
+
+    /// Get the delay before the given attempt (starting at 0).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        if attempt == 0 {
+            return Duration::ZERO
+        }
+        let d = self.factor
+            .checked_pow(attempt - 1)
+            .and_then(|f| self.initial_delay.checked_mul(f))
+            .map(|d| d.min(self.max_delay))
+            .unwrap_or(self.max_delay);
+        if self.jitter {
+            let half = d / 2;
+            let nanos = u64::try_from(half.as_nanos()).unwrap_or(u64::MAX);
+            if nanos > 0 {
+                return half + Duration::from_nanos(rand_core::OsRng.next_u64() % nanos)
+            }
+        }
+        d
+    }
+
+    /// Run the given operation until it succeeds or the max. number of
+    /// attempts is reached, in which case the last error is returned.
+    ///
+    /// The function receives the current attempt number.
+    pub async fn retry<T, E, F, R>(&self, mut f: F) -> Result<T, E>
+    where
+        F: FnMut(u32) -> R,
+        R: Future<Output = Result<T, E>>
+    {
+        let mut attempt = 0;
+        loop {
+            let d = self.delay(attempt);
+            if !d.is_zero() {
+                sleep(d).await
+            }
+            match f(attempt).await {
+                Ok(x) => return Ok(x),
+                Err(e) => {
+                    attempt = attempt.saturating_add(1);
+                    if self.max_attempts.map(|n| attempt >= n).unwrap_or(false) {
+                        return Err(e)
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays() {
+        let p = Policy::new().with_initial_delay(Duration::from_secs(2));
+        let d = (0 .. 8).map(|i| p.delay(i).as_secs()).collect::<Vec<_>>();
+        assert_eq!(vec![0, 2, 4, 8, 16, 32, 64, 64], d);
+        assert_eq!(Duration::from_secs(64), p.delay(u32::MAX))
+    }
+
+    #[test]
+    fn jitter() {
+        let p = Policy::new().with_jitter(true);
+        for i in 1 .. 10 {
+            let d = p.delay(i);
+            let n = Policy::new().delay(i);
+            assert!(n / 2 <= d && d <= n)
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Secret values.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use zeroize::Zeroize;
+
+const REDACTED: &str = "********";
+
+/// A secret value.
+///
+/// `Debug`, `Display` and `Serialize` output is redacted and the value is
+/// zeroized when dropped. Access requires an explicit [`Secret::expose`].
+pub struct Secret<T> {
+    value: T,
+    wipe: fn(&mut T)
+}
+
+impl<T: Zeroize> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Secret { value, wipe: T::zeroize }
+    }
+}
+
+impl<T> Secret<T> {
+    /// Wrap a value which zeroizes itself when dropped.
+    ///
+    /// This is meant for foreign types (e.g. cryptographic keys) which wipe
+    /// their memory in their own `Drop` impl but do not implement `Zeroize`.
+    pub fn self_zeroizing(value: T) -> Self {
+        Secret { value, wipe: |_| () }
+    }
+
+    /// Access the secret value.
+    pub fn expose(&self) -> &T {
+        &self.value
+    }
+
+    /// Mutably access the secret value.
+    pub fn expose_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T> Drop for Secret<T> {
+    fn drop(&mut self) {
+        (self.wipe)(&mut self.value)
+    }
+}
+
+impl<T: Clone> Clone for Secret<T> {
+    fn clone(&self) -> Self {
+        Secret { value: self.value.clone(), wipe: self.wipe }
+    }
+}
+
+impl<T: Zeroize> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Secret::new(value)
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(REDACTED)
+    }
+}
+
+impl<'de, T: Deserialize<'de> + Zeroize> Deserialize<'de> for Secret<T> {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        T::deserialize(d).map(Secret::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacted() {
+        let s = Secret::new(String::from("hunter2"));
+        assert_eq!("********", format!("{:?}", s));
+        assert_eq!("********", s.to_string());
+        assert_eq!("\"********\"", serde_json::to_string(&s).unwrap());
+        assert_eq!("hunter2", s.expose());
+        let t: Secret<String> = serde_json::from_str("\"hunter2\"").unwrap();
+        assert_eq!("hunter2", t.expose())
+    }
+}
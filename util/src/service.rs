@@ -0,0 +1,316 @@
+//! Integration with service managers.
+//!
+//! A program running as managed service reports its life cycle through the
+//! [`Service`] trait. [`detect`] returns the implementation matching the
+//! environment the program was started in; on Windows, [`Scm`] is created
+//! from the status handle obtained when registering with the service control
+//! manager. [`Systemd`] is available on all Unix platforms.
+
+use std::io;
+use std::time::Duration;
+
+/// A service manager.
+pub trait Service: Send + Sync {
+    /// Name of the service manager, e.g. "systemd".
+    fn manager(&self) -> &'static str;
+
+    /// Startup has completed.
+    fn ready(&self) -> io::Result<()>;
+
+    /// Shutdown has begun.
+    fn stopping(&self) -> io::Result<()>;
+
+    /// Reloading the configuration has begun.
+    ///
+    /// Completion is signalled with [`Service::ready`].
+    fn reloading(&self) -> io::Result<()>;
+
+    /// Free-form status description.
+    fn status(&self, msg: &str) -> io::Result<()>;
+
+    /// Signal liveness to the manager's watchdog.
+    fn watchdog(&self) -> io::Result<()>;
+
+    /// How often [`Service::watchdog`] is expected to be called (if at all).
+    fn watchdog_interval(&self) -> Option<Duration>;
+}
+
+/// Detect the service manager this process runs under.
+///
+/// If no service manager is detected, [`Unmanaged`] is returned.
+pub fn detect() -> Box<dyn Service> {
+    #[cfg(unix)]
+    if let Some(s) = Systemd::from_env() {
+        return Box::new(s)
+    }
+    #[cfg(target_os = "macos")]
+    if std::env::var_os("XPC_SERVICE_NAME").is_some_and(|n| n != "0") {
+        return Box::new(Launchd)
+    }
+    Box::new(Unmanaged)
+}
+
+/// No service manager.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Unmanaged;
+
+impl Service for Unmanaged {
+    fn manager(&self) -> &'static str {
+        "none"
+    }
+
+    fn ready(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn stopping(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn reloading(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn status(&self, _: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn watchdog(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn watchdog_interval(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// The macOS launchd.
+///
+/// launchd does not expect any notifications, so all methods are no-ops.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Launchd;
+
+impl Service for Launchd {
+    fn manager(&self) -> &'static str {
+        "launchd"
+    }
+
+    fn ready(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn stopping(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn reloading(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn status(&self, _: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn watchdog(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn watchdog_interval(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// systemd's notification protocol (see `sd_notify(3)`).
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct Systemd {
+    socket: std::os::unix::net::UnixDatagram,
+    watchdog: Option<Duration>
+}
+
+#[cfg(unix)]
+impl Systemd {
+    /// Connect to the socket given by `$NOTIFY_SOCKET` (if set).
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os("NOTIFY_SOCKET")?;
+        match Self::connect(&path) {
+            Ok(mut s) => {
+                s.watchdog = watchdog_from_env();
+                Some(s)
+            }
+            Err(e) => {
+                log::warn!(?path, "failed to connect to notify socket: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Connect to the given notification socket.
+    ///
+    /// On Linux, a leading `@` denotes an abstract socket address.
+    pub fn connect(path: &std::ffi::OsStr) -> io::Result<Self> {
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::net::UnixDatagram;
+
+        let socket = UnixDatagram::unbound()?;
+        match path.as_bytes() {
+            #[cfg(target_os = "linux")]
+            [b'@', name @ ..] => {
+                use std::os::linux::net::SocketAddrExt;
+                let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                socket.connect_addr(&addr)?
+            }
+            _ => socket.connect(path)?
+        }
+        Ok(Systemd { socket, watchdog: None })
+    }
+
+    fn notify(&self, state: &str) -> io::Result<()> {
+        self.socket.send(state.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Get the watchdog interval from `$WATCHDOG_USEC` if `$WATCHDOG_PID` is unset or ours.
+#[cfg(unix)]
+fn watchdog_from_env() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None
+        }
+    }
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec))
+}
+
+#[cfg(unix)]
+impl Service for Systemd {
+    fn manager(&self) -> &'static str {
+        "systemd"
+    }
+
+    fn ready(&self) -> io::Result<()> {
+        self.notify("READY=1")
+    }
+
+    fn stopping(&self) -> io::Result<()> {
+        self.notify("STOPPING=1")
+    }
+
+    fn reloading(&self) -> io::Result<()> {
+        self.notify("RELOADING=1")
+    }
+
+    fn status(&self, msg: &str) -> io::Result<()> {
+        self.notify(&format!("STATUS={}", msg.replace('\n', " ")))
+    }
+
+    fn watchdog(&self) -> io::Result<()> {
+        self.notify("WATCHDOG=1")
+    }
+
+    fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog
+    }
+}
+
+/// The Windows service control manager.
+#[cfg(windows)]
+#[derive(Debug, Clone, Copy)]
+pub struct Scm(windows_service::service_control_handler::ServiceStatusHandle);
+
+#[cfg(windows)]
+impl Scm {
+    pub fn new(handle: windows_service::service_control_handler::ServiceStatusHandle) -> Self {
+        Scm(handle)
+    }
+
+    /// The service has stopped, with a service-specific exit code (0 = success).
+    pub fn stopped(&self, code: u32) -> io::Result<()> {
+        use windows_service::service::{ServiceExitCode, ServiceState};
+        let code = if code == 0 { ServiceExitCode::NO_ERROR } else { ServiceExitCode::ServiceSpecific(code) };
+        self.set_with(ServiceState::Stopped, code)
+    }
+
+    fn set(&self, state: windows_service::service::ServiceState) -> io::Result<()> {
+        self.set_with(state, windows_service::service::ServiceExitCode::NO_ERROR)
+    }
+
+    fn set_with(&self, state: windows_service::service::ServiceState, exit_code: windows_service::service::ServiceExitCode) -> io::Result<()> {
+        use windows_service::service::{ServiceControlAccept, ServiceState, ServiceStatus, ServiceType};
+        let accept =
+            if state == ServiceState::Running {
+                ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+            } else {
+                ServiceControlAccept::empty()
+            };
+        let status = ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted: accept,
+            exit_code,
+            checkpoint: 0,
+            wait_hint: Duration::from_secs(10),
+            process_id: None
+        };
+        self.0.set_service_status(status).map_err(io::Error::other)
+    }
+}
+
+#[cfg(windows)]
+impl Service for Scm {
+    fn manager(&self) -> &'static str {
+        "scm"
+    }
+
+    fn ready(&self) -> io::Result<()> {
+        self.set(windows_service::service::ServiceState::Running)
+    }
+
+    fn stopping(&self) -> io::Result<()> {
+        self.set(windows_service::service::ServiceState::StopPending)
+    }
+
+    fn reloading(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn status(&self, _: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn watchdog(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn watchdog_interval(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::net::UnixDatagram;
+    use super::*;
+
+    #[test]
+    fn systemd_notify() {
+        let dir  = std::env::temp_dir().join(format!("util-service-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify");
+        let _    = std::fs::remove_file(&path);
+        let sock = UnixDatagram::bind(&path).unwrap();
+        let s = Systemd::connect(path.as_os_str()).unwrap();
+        s.ready().unwrap();
+        s.reloading().unwrap();
+        s.status("connected\nto gateway").unwrap();
+        let mut buf = [0; 64];
+        let n = sock.recv(&mut buf).unwrap();
+        assert_eq!(b"READY=1", &buf[.. n]);
+        let n = sock.recv(&mut buf).unwrap();
+        assert_eq!(b"RELOADING=1", &buf[.. n]);
+        let n = sock.recv(&mut buf).unwrap();
+        assert_eq!(b"STATUS=connected to gateway", &buf[.. n]);
+        std::fs::remove_dir_all(&dir).unwrap()
+    }
+}
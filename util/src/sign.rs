@@ -0,0 +1,139 @@
+//! Ed25519 signatures.
+
+use ed25519_dalek::Signer;
+use minicbor::{Decode, Encode};
+use minicbor::decode::{self, Decoder};
+use minicbor::encode::{self, Encoder, Write};
+use rand_core::RngCore;
+use std::convert::{Infallible, TryFrom};
+use std::fmt;
+use std::marker::PhantomData;
+
+pub use ed25519_dalek::{Signature, SignatureError, SigningKey, VerifyingKey};
+
+/// Generate a new random signing key.
+pub fn gen_signing_key() -> SigningKey {
+    let mut k = [0; 32];
+    rand_core::OsRng.fill_bytes(&mut k);
+    SigningKey::from_bytes(&k)
+}
+
+/// Sign the given message.
+pub fn sign(k: &SigningKey, msg: &[u8]) -> Signature {
+    k.sign(msg)
+}
+
+/// Verify the signature of the given message.
+pub fn verify(k: &VerifyingKey, msg: &[u8], sig: &Signature) -> Result<(), SignatureError> {
+    k.verify_strict(msg, sig)
+}
+
+/// A value of type `T` together with a signature of its CBOR encoding.
+///
+/// The value can only be accessed after successful signature verification.
+#[derive(Clone)]
+pub struct Signed<T> {
+    bytes: Vec<u8>,
+    sig: Signature,
+    _type: PhantomData<fn() -> T>
+}
+
+impl<T> Signed<T> {
+    /// Sign the CBOR encoding of the given value.
+    pub fn new(k: &SigningKey, val: &T) -> Result<Self, encode::Error<Infallible>>
+    where
+        T: Encode<()>
+    {
+        let bytes = minicbor::to_vec(val)?;
+        let sig = sign(k, &bytes);
+        Ok(Signed { bytes, sig, _type: PhantomData })
+    }
+
+    /// Verify the signature and decode the value.
+    pub fn verify<'a>(&'a self, k: &VerifyingKey) -> Result<T, Error>
+    where
+        T: Decode<'a, ()>
+    {
+        verify(k, &self.bytes, &self.sig).map_err(Error::Signature)?;
+        minicbor::decode(&self.bytes).map_err(Error::Decode)
+    }
+
+    pub fn signature(&self) -> &Signature {
+        &self.sig
+    }
+}
+
+impl<T> fmt::Debug for Signed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Signed").field("sig", &self.sig).finish()
+    }
+}
+
+impl<C, T> Encode<C> for Signed<T> {
+    fn encode<W: Write>(&self, e: &mut Encoder<W>, _: &mut C) -> Result<(), encode::Error<W::Error>> {
+        e.array(2)?
+            .bytes(&self.bytes)?
+            .bytes(&self.sig.to_bytes())?
+            .ok()
+    }
+}
+
+impl<'b, C, T> Decode<'b, C> for Signed<T> {
+    fn decode(d: &mut Decoder<'b>, _: &mut C) -> Result<Self, decode::Error> {
+        let p = d.position();
+        if Some(2) != d.array()? {
+            return Err(decode::Error::message("sign::Signed not an array of 2 elements").at(p))
+        }
+        let bytes = d.bytes()?.to_vec();
+        let p = d.position();
+        let sig = <[u8; 64]>::try_from(d.bytes()?).map_err(|_| {
+            decode::Error::message("sign::Signature not 64 bytes").at(p)
+        })?;
+        Ok(Signed { bytes, sig: Signature::from_bytes(&sig), _type: PhantomData })
+    }
+}
+
+/// Error verifying a [`Signed`] value.
+#[derive(Debug)]
+pub enum Error {
+    /// The signature is not valid.
+    Signature(SignatureError),
+    /// The value could not be decoded.
+    Decode(decode::Error)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Signature(e) => write!(f, "invalid signature: {}", e),
+            Error::Decode(e)    => write!(f, "failed to decode signed value: {}", e)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn success() {
+        let k = gen_signing_key();
+        let s = Signed::new(&k, &String::from("hello world")).unwrap();
+        let v = minicbor::to_vec(&s).unwrap();
+        let s: Signed<String> = minicbor::decode(&v).unwrap();
+        assert_eq!("hello world", s.verify(&k.verifying_key()).unwrap())
+    }
+
+    #[test]
+    fn failure() {
+        let k1 = gen_signing_key();
+        let k2 = gen_signing_key();
+        let s = Signed::new(&k1, &String::from("hello world")).unwrap();
+        assert!(s.verify(&k2.verifying_key()).is_err());
+        let mut t = s.clone();
+        t.bytes[1] ^= 1;
+        assert!(t.verify(&k1.verifying_key()).is_err())
+    }
+}
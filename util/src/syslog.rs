@@ -0,0 +1,154 @@
+//! Sending messages to the local syslog daemon.
+//!
+//! Every message is a single datagram of the form `<PRI>IDENT[PID]: MSG`
+//! which syslogd, rsyslog and systemd-journald all understand.
+
+use log::Level;
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The default syslog socket.
+pub const SOCKET: &str = "/dev/log";
+
+/// The syslog facilities used by the agent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Facility {
+    Daemon = 3,
+    Auth   = 4
+}
+
+/// The syslog severities used by the agent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error   = 3,
+    Warning = 4,
+    Info    = 6,
+    Debug   = 7
+}
+
+impl From<Level> for Severity {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::ERROR => Severity::Error,
+            Level::WARN  => Severity::Warning,
+            Level::INFO  => Severity::Info,
+            _            => Severity::Debug
+        }
+    }
+}
+
+/// A connection to a syslog socket.
+///
+/// Clones share the same socket.
+#[derive(Clone, Debug)]
+pub struct Syslog {
+    ident: Arc<str>,
+    facility: Facility,
+    socket: Arc<UnixDatagram>
+}
+
+impl Syslog {
+    /// Connect to the syslog socket at [`SOCKET`].
+    pub fn connect(ident: &str, facility: Facility) -> io::Result<Self> {
+        Self::connect_to(ident, facility, SOCKET)
+    }
+
+    /// Connect to a syslog socket at the given path.
+    pub fn connect_to<P: AsRef<Path>>(ident: &str, facility: Facility, path: P) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Syslog { ident: ident.into(), facility, socket: Arc::new(socket) })
+    }
+
+    /// Send a single message with the given severity.
+    pub fn send(&self, severity: Severity, msg: &str) -> io::Result<()> {
+        let mut m = self.message(severity);
+        m.buf.extend_from_slice(msg.as_bytes());
+        m.send()
+    }
+
+    /// Start a message with the given severity.
+    ///
+    /// The message is sent when dropped. A trailing newline is removed.
+    pub fn message(&self, severity: Severity) -> Message<'_> {
+        let priority = self.facility as u8 * 8 + severity as u8;
+        let head = format!("<{}>{}[{}]: ", priority, self.ident, std::process::id());
+        Message { syslog: self, buf: head.into_bytes(), sent: false }
+    }
+}
+
+/// Buffers a message and sends it as one datagram when dropped.
+#[derive(Debug)]
+pub struct Message<'a> {
+    syslog: &'a Syslog,
+    buf: Vec<u8>,
+    sent: bool
+}
+
+impl Message<'_> {
+    fn send(&mut self) -> io::Result<()> {
+        self.sent = true;
+        if self.buf.ends_with(b"\n") {
+            self.buf.pop();
+        }
+        self.syslog.socket.send(&self.buf).map(|_| ())
+    }
+}
+
+impl Write for Message<'_> {
+    fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(b);
+        Ok(b.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Message<'_> {
+    fn drop(&mut self) {
+        if !self.sent {
+            // Failures can not be logged as this may be the log writer.
+            let _ = self.send();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_and_severity() {
+        let dir  = std::env::temp_dir().join(format!("util-syslog-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("log");
+        let _    = std::fs::remove_file(&path);
+        let sock = UnixDatagram::bind(&path).unwrap();
+        let pid  = std::process::id();
+
+        let daemon = Syslog::connect_to("agent", Facility::Daemon, &path).unwrap();
+        for level in [Level::ERROR, Level::WARN, Level::INFO, Level::DEBUG, Level::TRACE] {
+            writeln!(daemon.message(level.into()), "{}", level).unwrap()
+        }
+        let auth = Syslog::connect_to("agent", Facility::Auth, &path).unwrap();
+        auth.send(Severity::Info, "audit").unwrap();
+
+        let mut buf = [0; 64];
+        for expected in [
+            format!("<27>agent[{pid}]: ERROR"),
+            format!("<28>agent[{pid}]: WARN"),
+            format!("<30>agent[{pid}]: INFO"),
+            format!("<31>agent[{pid}]: DEBUG"),
+            format!("<31>agent[{pid}]: TRACE"),
+            format!("<38>agent[{pid}]: audit")
+        ] {
+            let n = sock.recv(&mut buf).unwrap();
+            assert_eq!(expected.as_bytes(), &buf[.. n])
+        }
+        std::fs::remove_dir_all(&dir).unwrap()
+    }
+}
@@ -1,11 +1,22 @@
 use minicbor::{Encode, Decode};
+use std::fmt;
+use std::str::FromStr;
 use std::time::{Duration, SystemTime, SystemTimeError, UNIX_EPOCH};
 
 /// A UNIX timestamp, i.e. seconds since 1970-01-01 00:00:00 UTC.
+///
+/// Displayed and parsed in RFC 3339 format, e.g. `2024-01-31T12:00:00Z`.
 #[derive(Copy, Clone, Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord)]
 #[cbor(transparent)]
 pub struct UnixTime(#[n(0)] u64);
 
+/// A UNIX timestamp with millisecond precision.
+///
+/// Displayed and parsed in RFC 3339 format, e.g. `2024-01-31T12:00:00.123Z`.
+#[derive(Copy, Clone, Debug, Decode, Encode, PartialEq, Eq, PartialOrd, Ord)]
+#[cbor(transparent)]
+pub struct UnixTimeMillis(#[n(0)] u64);
+
 impl UnixTime {
     pub fn now() -> Result<Self, SystemTimeError> {
         let d = SystemTime::now().duration_since(UNIX_EPOCH)?;
@@ -15,6 +26,55 @@ impl UnixTime {
     pub fn seconds(self) -> u64 {
         self.0
     }
+
+    pub fn checked_add(self, d: Duration) -> Option<Self> {
+        self.0.checked_add(d.as_secs()).map(UnixTime)
+    }
+
+    pub fn checked_sub(self, d: Duration) -> Option<Self> {
+        self.0.checked_sub(d.as_secs()).map(UnixTime)
+    }
+
+    /// The duration since an earlier point in time (`None` if `t` is later).
+    pub fn duration_since(self, t: UnixTime) -> Option<Duration> {
+        self.0.checked_sub(t.0).map(Duration::from_secs)
+    }
+
+    pub fn to_system_time(self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.0)
+    }
+}
+
+impl UnixTimeMillis {
+    pub fn now() -> Result<Self, SystemTimeError> {
+        let d = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        Ok(UnixTimeMillis::from(d))
+    }
+
+    pub fn millis(self) -> u64 {
+        self.0
+    }
+
+    pub fn seconds(self) -> u64 {
+        self.0 / 1000
+    }
+
+    pub fn checked_add(self, d: Duration) -> Option<Self> {
+        u64::try_from(d.as_millis()).ok().and_then(|m| self.0.checked_add(m)).map(UnixTimeMillis)
+    }
+
+    pub fn checked_sub(self, d: Duration) -> Option<Self> {
+        u64::try_from(d.as_millis()).ok().and_then(|m| self.0.checked_sub(m)).map(UnixTimeMillis)
+    }
+
+    /// The duration since an earlier point in time (`None` if `t` is later).
+    pub fn duration_since(self, t: UnixTimeMillis) -> Option<Duration> {
+        self.0.checked_sub(t.0).map(Duration::from_millis)
+    }
+
+    pub fn to_system_time(self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.0)
+    }
 }
 
 impl From<u64> for UnixTime {
@@ -29,3 +89,96 @@ impl From<Duration> for UnixTime {
     }
 }
 
+impl From<Duration> for UnixTimeMillis {
+    fn from(d: Duration) -> Self {
+        UnixTimeMillis(u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+    }
+}
+
+impl From<UnixTime> for UnixTimeMillis {
+    fn from(t: UnixTime) -> Self {
+        UnixTimeMillis(t.0.saturating_mul(1000))
+    }
+}
+
+impl From<UnixTimeMillis> for UnixTime {
+    fn from(t: UnixTimeMillis) -> Self {
+        UnixTime(t.seconds())
+    }
+}
+
+impl fmt::Display for UnixTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        humantime::format_rfc3339_seconds(self.to_system_time()).fmt(f)
+    }
+}
+
+impl fmt::Display for UnixTimeMillis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        humantime::format_rfc3339_millis(self.to_system_time()).fmt(f)
+    }
+}
+
+impl FromStr for UnixTime {
+    type Err = InvalidTime;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        since_epoch(s).map(UnixTime::from)
+    }
+}
+
+impl FromStr for UnixTimeMillis {
+    type Err = InvalidTime;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        since_epoch(s).map(UnixTimeMillis::from)
+    }
+}
+
+/// Parse an RFC 3339 timestamp as duration since the UNIX epoch.
+fn since_epoch(s: &str) -> Result<Duration, InvalidTime> {
+    humantime::parse_rfc3339_weak(s)
+        .map_err(|e| InvalidTime(format!("`{}`: {}", s, e)))?
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| InvalidTime(format!("`{}`: before 1970-01-01", s)))
+}
+
+/// Error caused by parsing invalid timestamps.
+#[derive(Clone, Debug)]
+pub struct InvalidTime(String);
+
+impl fmt::Display for InvalidTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid time: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidTime {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc3339() {
+        let t = UnixTime::from(1_700_000_000);
+        assert_eq!("2023-11-14T22:13:20Z", t.to_string());
+        assert_eq!(t, t.to_string().parse().unwrap());
+        let m = UnixTimeMillis::from(Duration::from_millis(1_700_000_000_123));
+        assert_eq!("2023-11-14T22:13:20.123Z", m.to_string());
+        assert_eq!(m, m.to_string().parse().unwrap());
+        assert_eq!(t, UnixTime::from(m));
+        assert!("yesterday".parse::<UnixTime>().is_err())
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = UnixTimeMillis::from(Duration::from_millis(1500));
+        let b = a.checked_add(Duration::from_millis(250)).unwrap();
+        assert_eq!(Some(Duration::from_millis(250)), b.duration_since(a));
+        assert_eq!(None, a.duration_since(b));
+        assert_eq!(None, a.checked_sub(Duration::from_secs(2)));
+        let t = UnixTime::from(10);
+        assert_eq!(Some(UnixTime::from(15)), t.checked_add(Duration::from_secs(5)))
+    }
+}